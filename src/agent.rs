@@ -0,0 +1,298 @@
+//! Optional resident agent keeping a WSL session open, so that the wrapper's helper commands
+//! (daemon checks and probes, path conversions) skip the cost of starting `wsl.exe`.
+//!
+//! The agent listens on a loopback port recorded in a state file, together with a token that
+//! clients send first. A connection carries one request and one response, each a JSON line.
+//! The docker command itself still gets its own `wsl.exe`, which owns the console.
+
+use crate::{config::Config, state, wsl::shell_quote};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// How long a freshly spawned agent may take to start listening.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    /// Runs a command in the distro with stdin and stderr closed.
+    Run {
+        args: Vec<String>,
+    },
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    success: bool,
+    stdout: String,
+}
+
+/// Where a running agent listens, as recorded in its state file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Endpoint {
+    port: u16,
+    token: String,
+}
+
+fn endpoint_file(distro_name: &str) -> PathBuf {
+    state::state_dir().join(format!("{distro_name}.agent"))
+}
+
+fn read_endpoint(distro_name: &str) -> Option<Endpoint> {
+    let content = fs::read_to_string(endpoint_file(distro_name)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Runs `args` in the distro through the agent, starting it on first use when it is enabled.
+/// Returns `None` when no agent is available, for the caller to spawn `wsl.exe` itself.
+pub fn run(config: &Config, args: &[&str]) -> Option<(bool, String)> {
+    // Starting is attempted once per process, so a broken agent costs a single timeout.
+    static START_FAILED: OnceLock<()> = OnceLock::new();
+
+    let args = args.iter().map(|arg| arg.to_string()).collect();
+    let request = Request::Run { args };
+    if let Some(endpoint) = read_endpoint(&config.distro_name) {
+        if let Ok(response) = send(&endpoint, &request) {
+            return Some((response.success, response.stdout));
+        }
+    }
+
+    if !config.agent.enabled || START_FAILED.get().is_some() {
+        return None;
+    }
+    match spawn(config).and_then(|endpoint| send(&endpoint, &request)) {
+        Ok(response) => Some((response.success, response.stdout)),
+        Err(err) => {
+            debug!("agent is unavailable: {err:#}");
+            let _ = START_FAILED.set(());
+            None
+        }
+    }
+}
+
+/// Starts the agent in the background, replacing any running one.
+pub fn start(config: &Config) -> Result<()> {
+    stop(config);
+    spawn(config).map(drop)
+}
+
+/// Spawns the agent in the background and waits until it listens.
+fn spawn(config: &Config) -> Result<Endpoint> {
+    let file = endpoint_file(&config.distro_name);
+    let _ = fs::remove_file(&file);
+
+    // The agent loads the same config, for the settings of the commands it runs.
+    let mut cmd = Command::new(env::current_exe()?);
+    cmd.arg("docker-wrapper");
+    if let Some(path) = &config.path {
+        cmd.arg("--config").arg(path);
+    }
+    if let Some(profile) = &config.profile {
+        cmd.args(["--profile", profile]);
+    }
+    cmd.args(["agent", "serve", "--distro", &config.distro_name])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        use windows_sys::Win32::System::Threading::{CREATE_NO_WINDOW, DETACHED_PROCESS};
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NO_WINDOW);
+    }
+    cmd.spawn().context("failed to start the agent")?;
+
+    let deadline = Instant::now() + START_TIMEOUT;
+    while Instant::now() < deadline {
+        if let Some(endpoint) = read_endpoint(&config.distro_name) {
+            debug!("agent listens on port {}", endpoint.port);
+            return Ok(endpoint);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    bail!(
+        "the agent did not start within {}s",
+        START_TIMEOUT.as_secs()
+    )
+}
+
+/// Asks a running agent to exit. Returns whether one was running.
+pub fn stop(config: &Config) -> bool {
+    let Some(endpoint) = read_endpoint(&config.distro_name) else {
+        return false;
+    };
+    let stopped = send(&endpoint, &Request::Stop).is_ok();
+    let _ = fs::remove_file(endpoint_file(&config.distro_name));
+
+    stopped
+}
+
+fn send(endpoint: &Endpoint, request: &Request) -> Result<Response> {
+    let address = (Ipv4Addr::LOCALHOST, endpoint.port).into();
+    let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(1))?;
+    writeln!(stream, "{}", endpoint.token)?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Body of the agent process: serves requests until told to stop, idle for
+/// `agent.idle_timeout` seconds, or until its WSL session ends (`wsl --shutdown`).
+pub fn serve(config: &Config, distro_name: &str) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    listener.set_nonblocking(true)?;
+    let mut shell = Shell::spawn(distro_name)?;
+
+    let endpoint = Endpoint {
+        port: listener.local_addr()?.port(),
+        token: random_token()?,
+    };
+    fs::create_dir_all(state::state_dir())?;
+    let file = endpoint_file(distro_name);
+    fs::write(&file, serde_json::to_string(&endpoint)?)?;
+
+    let idle_timeout = Duration::from_secs(config.agent.idle_timeout);
+    let mut last_request = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                last_request = Instant::now();
+                match handle(stream, &endpoint.token, &mut shell) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => debug!("agent request failed: {err:#}"),
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                if last_request.elapsed() >= idle_timeout || !shell.is_alive() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // A newer agent may have replaced the file meanwhile.
+    if read_endpoint(distro_name).is_some_and(|current| current.token == endpoint.token) {
+        let _ = fs::remove_file(&file);
+    }
+
+    Ok(())
+}
+
+/// Serves one connection. Returns `false` when the agent should stop.
+fn handle(stream: TcpStream, token: &str, shell: &mut Shell) -> Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != token {
+        bail!("rejected a client with a wrong token");
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let request = serde_json::from_str(&line)?;
+    let (success, stdout) = match &request {
+        Request::Run { args } => shell.run(args)?,
+        Request::Stop => (true, String::new()),
+    };
+
+    let mut stream = stream;
+    let response = Response { success, stdout };
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+
+    Ok(!matches!(request, Request::Stop))
+}
+
+/// `sh` running in the distro, fed one command line at a time.
+struct Shell {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    count: u64,
+}
+
+impl Shell {
+    fn spawn(distro_name: &str) -> Result<Self> {
+        let mut child = Command::new("wsl")
+            .args(["-d", distro_name, "-u", "root", "-e", "sh"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().context("no stdin")?;
+        let stdout = BufReader::new(child.stdout.take().context("no stdout")?);
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            count: 0,
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Runs `args` and collects its stdout up to a marker line carrying the exit status.
+    /// The marker follows a newline of its own, which is removed again from the output.
+    fn run(&mut self, args: &[String]) -> Result<(bool, String)> {
+        self.count += 1;
+        let marker = format!("__docker_wrapper_agent_{}__", self.count);
+        let command: Vec<_> = args.iter().map(|arg| shell_quote(arg)).collect();
+        writeln!(
+            self.stdin,
+            "( {} ) </dev/null 2>/dev/null; printf '\\n{marker} %d\\n' $?",
+            command.join(" ")
+        )?;
+        self.stdin.flush()?;
+
+        let mut stdout = vec![];
+        loop {
+            let mut line = vec![];
+            if self.stdout.read_until(b'\n', &mut line)? == 0 {
+                return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            if let Some(status) = text.strip_prefix(&marker) {
+                stdout.pop();
+                let status: i32 = status.trim().parse()?;
+                return Ok((status == 0, String::from_utf8_lossy(&stdout).to_string()));
+            }
+            stdout.extend(line);
+        }
+    }
+}
+
+impl Drop for Shell {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// 128 bits from the random number generator of the OS, in hex.
+fn random_token() -> Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).context("failed to generate the agent token")?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
@@ -0,0 +1,559 @@
+use crate::{
+    bake::{convert_set_override, fix_bake_files},
+    compose::{fix_compose_files, TempFile},
+    config::Config,
+    flags::{
+        first_positional, resolve_command, scan, CommandSpec, Positionals, Token, ValueKind,
+        ValueRef, COMPOSE, COMPOSE_CP, COMPOSE_RUN,
+    },
+    wsl::{convert_path, prefetch_paths},
+};
+use anyhow::Result;
+use std::{env, path::Path};
+
+/// Variables of the Windows environment that docker, buildx and compose read, handed to the
+/// docker command in the distro. `DOCKER_HOST` and `DOCKER_CONTEXT` are left out, since the
+/// wrapper decides which daemon it talks to.
+const FORWARDED_ENV: &[&str] = &[
+    "BUILDKIT_PROGRESS",
+    "BUILDX_BUILDER",
+    "BUILDX_NO_DEFAULT_ATTESTATIONS",
+    "COMPOSE_ANSI",
+    "COMPOSE_BAKE",
+    "COMPOSE_IGNORE_ORPHANS",
+    "COMPOSE_MENU",
+    "COMPOSE_PARALLEL_LIMIT",
+    "COMPOSE_PROFILES",
+    "COMPOSE_PROJECT_NAME",
+    "COMPOSE_REMOVE_ORPHANS",
+    "COMPOSE_STATUS_STDOUT",
+    "DOCKER_BUILDKIT",
+    "DOCKER_CLI_HINTS",
+    "DOCKER_CONTENT_TRUST",
+    "DOCKER_DEFAULT_PLATFORM",
+    "DOCKER_HIDE_LEGACY_COMMANDS",
+];
+
+/// Variables holding a path, forwarded with it converted.
+const FORWARDED_PATH_ENV: &[&str] = &["DOCKER_CERT_PATH", "DOCKER_CONFIG"];
+
+/// The variables of [`FORWARDED_ENV`] and [`FORWARDED_PATH_ENV`] set on the Windows side,
+/// along with the lists of paths compose reads.
+pub fn forwarded_env(config: &Config) -> Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, String)> = FORWARDED_ENV
+        .iter()
+        .filter_map(|name| Some((name.to_string(), env::var(name).ok()?)))
+        .collect();
+    for name in FORWARDED_PATH_ENV {
+        if let Ok(path) = env::var(name) {
+            vars.push((name.to_string(), convert_host_path(config, &path)?));
+        }
+    }
+
+    // `COMPOSE_FILE` is separated by `;` on Windows and by `:` on Linux, unless
+    // `COMPOSE_PATH_SEPARATOR` sets the separator for both.
+    let separator = env::var("COMPOSE_PATH_SEPARATOR").ok();
+    if let Ok(files) = env::var("COMPOSE_FILE") {
+        let (split, join) = match &separator {
+            Some(separator) => (&**separator, &**separator),
+            None => (";", ":"),
+        };
+        vars.push((
+            "COMPOSE_FILE".into(),
+            convert_path_list(config, &files, split, join)?,
+        ));
+    }
+    if let Some(separator) = separator {
+        vars.push(("COMPOSE_PATH_SEPARATOR".into(), separator));
+    }
+    if let Ok(files) = env::var("COMPOSE_ENV_FILES") {
+        vars.push((
+            "COMPOSE_ENV_FILES".into(),
+            convert_path_list(config, &files, ",", ",")?,
+        ));
+    }
+
+    Ok(vars)
+}
+
+fn convert_path_list(config: &Config, paths: &str, split: &str, join: &str) -> Result<String> {
+    let paths = paths
+        .split(split)
+        .filter(|path| !path.is_empty())
+        .map(|path| convert_host_path(config, path))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(paths.join(join))
+}
+
+/// Rewrites `args` in place. The returned temporary files back some of the rewritten
+/// arguments and must be kept alive until docker has exited.
+pub fn modify_args(config: &Config, args: &mut Vec<String>) -> Result<Vec<TempFile>> {
+    prefetch_paths(config, &path_candidates(args))?;
+
+    let tokens = scan(args, crate::flags::GLOBAL_FLAGS, false);
+    rewrite_tokens(config, args, &tokens, true)?;
+    let Some(command_index) = first_positional(&tokens) else {
+        return Ok(vec![]);
+    };
+
+    let mut command_args = args.split_off(command_index);
+    let temp_files = modify_command_args(config, &mut command_args)?;
+    args.append(&mut command_args);
+
+    Ok(temp_files)
+}
+
+/// Parts of `args` that look like Windows paths and are likely to be converted: whole
+/// arguments, `--flag=` values, the host side of `-v` values and the comma-separated fields
+/// of values such as `--mount`, with or without their `key=`.
+/// They are converted together up front rather than one WSL round trip at a time.
+fn path_candidates(args: &[String]) -> Vec<&str> {
+    let mut candidates = vec![];
+    for arg in args {
+        let value = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => value,
+            _ => arg,
+        };
+        candidates.push(value);
+        candidates.push(split_volume_spec(value).0);
+        for field in value.split(',').map(|field| field.trim_matches('"')) {
+            candidates.push(field);
+            if let Some((_, path)) = field.split_once('=') {
+                candidates.push(path);
+            }
+        }
+    }
+    candidates.retain(|candidate| is_windows_path(candidate));
+
+    candidates
+}
+
+/// Rewrites the arguments of a subcommand, `args[0]` being its name.
+fn modify_command_args(config: &Config, args: &mut Vec<String>) -> Result<Vec<TempFile>> {
+    if args[0] == "compose" {
+        let temp_files = fix_compose_files(config, args)?;
+        rewrite_command(config, &mut args[1..], &COMPOSE)?;
+        let subcommand = compose_global_end(args);
+        let spec = [&COMPOSE_RUN, &COMPOSE_CP]
+            .into_iter()
+            .find(|spec| args.get(subcommand).is_some_and(|name| name == spec.name));
+        match spec {
+            Some(spec) => rewrite_command(config, &mut args[subcommand + 1..], spec)?,
+            // Subcommands that are not modeled keep the backslash fallback.
+            None => {
+                for arg in args.iter_mut().skip(subcommand + 1) {
+                    fix_arg_containing_backslash(config, arg)?;
+                }
+            }
+        }
+
+        return Ok(temp_files);
+    }
+
+    match resolve_command(args) {
+        Some((spec, start)) if spec.name == "bake" => {
+            let mut bake_args = args.split_off(start);
+            let temp_files = fix_bake_files(config, &mut bake_args)?;
+            rewrite_command(config, &mut bake_args, spec)?;
+            args.append(&mut bake_args);
+
+            return Ok(temp_files);
+        }
+        Some((spec, start)) => rewrite_command(config, &mut args[start..], spec)?,
+        None => {
+            for arg in args.iter_mut() {
+                fix_arg_containing_backslash(config, arg)?;
+            }
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Rewrites the flags and positionals of a command according to its spec.
+fn rewrite_command(config: &Config, args: &mut [String], spec: &CommandSpec) -> Result<()> {
+    let tokens = scan(args, spec.flags, spec.interspersed);
+    rewrite_tokens(config, args, &tokens, spec.fallback)?;
+
+    let positionals = tokens.iter().filter_map(|token| match token {
+        Token::Positional(index) => Some(*index),
+        Token::Flag { .. } => None,
+    });
+    for (nth, index) in positionals.enumerate() {
+        // Past the image or container of `run`/`exec` is the command run in the container.
+        if !spec.interspersed && nth > 0 {
+            break;
+        }
+
+        match spec.positionals {
+            Positionals::LocalPath if nth == 0 => fix_local_path(config, &mut args[index])?,
+            Positionals::CpOperands => {
+                let operand = &args[index];
+                if operand != "-" && !is_container_path(operand) {
+                    args[index] = convert_host_path(config, operand)?;
+                }
+            }
+            _ if spec.fallback => fix_arg_containing_backslash(config, &mut args[index])?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites the values of the flags among `tokens`. Values of unmodeled flags are only
+/// touched by the backslash fallback, and only when `fallback` is set.
+fn rewrite_tokens(
+    config: &Config,
+    args: &mut [String],
+    tokens: &[Token],
+    fallback: bool,
+) -> Result<()> {
+    for token in tokens {
+        let Token::Flag {
+            spec,
+            value: Some(value),
+            ..
+        } = *token
+        else {
+            continue;
+        };
+
+        match spec {
+            Some(spec) => rewrite_value(config, args, value, spec.value)?,
+            None if fallback => {
+                let mut raw = args[value.index][value.offset..].to_string();
+                fix_arg_containing_backslash(config, &mut raw)?;
+                args[value.index].replace_range(value.offset.., &raw);
+            }
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn rewrite_value(
+    config: &Config,
+    args: &mut [String],
+    value: ValueRef,
+    kind: ValueKind,
+) -> Result<()> {
+    let raw = &args[value.index][value.offset..];
+    let converted = match kind {
+        ValueKind::HostPath if raw != "-" => convert_host_path(config, raw)?,
+        ValueKind::Mount | ValueKind::Secret => {
+            convert_path_fields(config, raw, &["source", "src"])?
+        }
+        // `--output C:\out` is short for `type=local,dest=C:\out`, while a bare
+        // `--cache-from` is an image.
+        ValueKind::Exporter if !raw.contains('=') => match is_windows_path(raw) {
+            true => convert_host_path(config, raw)?,
+            false => return Ok(()),
+        },
+        ValueKind::Exporter => convert_path_fields(config, raw, &["dest", "src"])?,
+        ValueKind::Ssh => convert_ssh_spec(config, raw)?,
+        ValueKind::SecurityOpt => convert_security_opt(config, raw)?,
+        ValueKind::BakeSet => convert_set_override(config, raw)?,
+        ValueKind::Volume => convert_volume_spec(config, raw)?,
+        _ => return Ok(()),
+    };
+    args[value.index].replace_range(value.offset.., &converted);
+
+    Ok(())
+}
+
+/// Returns the index of the compose subcommand in a `docker compose` invocation, i.e. the end
+/// of the compose global flags. Subcommands reuse short names such as `-f` for unrelated
+/// things (`logs -f`, `rm -f`), so only the global flags may be treated as paths.
+pub fn compose_global_end(args: &[String]) -> usize {
+    let tokens = scan(&args[1..], COMPOSE.flags, false);
+    first_positional(&tokens).map_or(args.len(), |index| index + 1)
+}
+
+/// Converts a positional referring to a local file or directory, such as the build context.
+/// URLs and `-` (stdin) are left for docker; only things that exist as paths are converted.
+fn fix_local_path(config: &Config, arg: &mut String) -> Result<()> {
+    if is_windows_absolute_path(arg) || Path::new(arg).exists() {
+        *arg = convert_host_path(config, arg)?;
+    }
+
+    Ok(())
+}
+
+/// Converts the fields named by `keys` of a comma-separated value, such as the `source=` of
+/// `--mount type=bind,source=C:\x,target=/x`, the form VS Code Dev Containers generates for
+/// the workspace, or the `src=` of `--secret id=npmrc,src=C:\Users\me\.npmrc`. Only values
+/// that look like Windows paths are converted, leaving volume names alone.
+fn convert_path_fields(config: &Config, spec: &str, keys: &[&str]) -> Result<String> {
+    let mut fields = split_mount_fields(spec);
+    for field in &mut fields {
+        // Docker reads the value as CSV, so a field containing a comma comes quoted.
+        let quoted = field.len() >= 2 && field.starts_with('"') && field.ends_with('"');
+        let inner = if quoted {
+            &field[1..field.len() - 1]
+        } else {
+            &field[..]
+        };
+        let Some((key, path)) = inner.split_once('=').filter(|(key, _)| keys.contains(key)) else {
+            continue;
+        };
+        if !is_windows_path(path) {
+            continue;
+        }
+
+        let converted = format!("{key}={}", convert_path(config, path)?);
+        *field = if quoted {
+            format!("\"{converted}\"")
+        } else {
+            converted
+        };
+    }
+
+    Ok(fields.join(","))
+}
+
+/// Converts the sockets or keys of an `--ssh` value such as
+/// `default=C:\Users\me\.ssh\id_ed25519`, which lists them after the id separated by
+/// commas. Without them, the agent relayed from Windows is used.
+fn convert_ssh_spec(config: &Config, spec: &str) -> Result<String> {
+    let Some((id, paths)) = spec.split_once('=') else {
+        return Ok(spec.to_string());
+    };
+    let paths = paths
+        .split(',')
+        .map(|path| match is_windows_path(path) {
+            true => convert_path(config, path),
+            false => Ok(path.to_string()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(format!("{id}={}", paths.join(",")))
+}
+
+/// Converts the profile of a `--security-opt seccomp=C:\profiles\custom.json`, which the CLI
+/// reads, leaving `seccomp=unconfined` and the other options alone.
+fn convert_security_opt(config: &Config, opt: &str) -> Result<String> {
+    match opt.split_once('=') {
+        Some(("seccomp", profile)) if profile != "unconfined" => {
+            Ok(format!("seccomp={}", convert_host_path(config, profile)?))
+        }
+        _ => Ok(opt.to_string()),
+    }
+}
+
+/// Splits a comma-separated value such as `--mount` at the commas outside double quotes.
+fn split_mount_fields(spec: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut in_quotes = false;
+    for c in spec.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                fields.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        fields.last_mut().expect("starts with a field").push(c);
+    }
+
+    fields
+}
+
+/// Converts the host side of a `-v` value such as `C:\work\app:/app:ro`, leaving named
+/// volumes and Linux paths untouched.
+fn convert_volume_spec(config: &Config, spec: &str) -> Result<String> {
+    let (host, rest) = split_volume_spec(spec);
+    let host = match volume_host(host) {
+        VolumeHost::WindowsPath => convert_path(config, host)?,
+        // Docker resolves relative paths against the current directory, which it runs in.
+        VolumeHost::RelativePath => host.replace('\\', "/"),
+        VolumeHost::LinuxPath | VolumeHost::Volume => return Ok(spec.to_string()),
+    };
+
+    Ok(format!("{host}{rest}"))
+}
+
+/// What the host side of a `-v` value names.
+#[derive(Debug, PartialEq, Eq)]
+enum VolumeHost {
+    WindowsPath,
+    RelativePath,
+    LinuxPath,
+    Volume,
+}
+
+/// Volume names start with a letter or digit and contain no separators, so anything else is
+/// a path.
+fn volume_host(host: &str) -> VolumeHost {
+    if is_windows_absolute_path(host) {
+        VolumeHost::WindowsPath
+    } else if host.starts_with('/') {
+        VolumeHost::LinuxPath
+    } else if host.starts_with('.') || host.contains(['/', '\\']) {
+        VolumeHost::RelativePath
+    } else {
+        VolumeHost::Volume
+    }
+}
+
+/// Splits a `-v` value into the host part and the rest starting at the separating colon.
+/// The colon after a drive letter belongs to the host part, unless no other colon follows:
+/// `c:/data` mounts the volume `c`, since a bind mount needs a path in the container.
+pub fn split_volume_spec(spec: &str) -> (&str, &str) {
+    let skip = if has_drive_letter(spec) && spec[2..].contains(':') {
+        2
+    } else {
+        0
+    };
+    match spec[skip..].find(':') {
+        Some(pos) => spec.split_at(skip + pos),
+        None => (spec, ""),
+    }
+}
+
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Drive-letter (`C:\x`) and UNC (`\\server\share`) paths.
+pub fn is_windows_absolute_path(path: &str) -> bool {
+    has_drive_letter(path) || path.starts_with("\\\\")
+}
+
+fn is_windows_path(path: &str) -> bool {
+    has_drive_letter(path) || path.contains('\\')
+}
+
+/// Whether a `docker cp` operand names a path in a container (`mycontainer:/data`). A single
+/// letter before the colon is a drive letter, and paths starting with `.` or containing a
+/// separator before the colon are local, following docker's own rules.
+fn is_container_path(operand: &str) -> bool {
+    let Some((container, _)) = operand.split_once(':') else {
+        return false;
+    };
+
+    container.len() > 1 && !container.starts_with('.') && !container.contains(['/', '\\'])
+}
+
+/// Converts a path given on the Windows side into the path the distro sees. Docker runs in
+/// the distro's view of the current directory, so relative paths only need their separators
+/// fixed, and Linux absolute paths are returned unchanged.
+pub fn convert_host_path(config: &Config, path: &str) -> Result<String> {
+    if is_windows_absolute_path(path) {
+        return convert_path(config, path);
+    }
+
+    Ok(path.replace('\\', "/"))
+}
+
+fn fix_arg_containing_backslash(config: &Config, arg: &mut String) -> Result<()> {
+    if arg.contains('\\') {
+        if let Ok(path) = convert_path(config, arg) {
+            *arg = path;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `command` as rewritten, without the path prefetching that would ask the distro.
+    fn rewritten(command: &str) -> String {
+        let mut args: Vec<String> = command.split(' ').map(str::to_string).collect();
+        modify_command_args(&Config::default(), &mut args).unwrap();
+        args.join(" ")
+    }
+
+    #[test]
+    fn command_arguments_are_rewritten() {
+        let cases = [
+            (r"run -v C:\x:/y:ro image", "run -v /mnt/c/x:/y:ro image"),
+            (r"run -v .\rel:/y image", "run -v ./rel:/y image"),
+            ("run -v data:/y image", "run -v data:/y image"),
+            (r"run -vC:\x:/y image", "run -v/mnt/c/x:/y image"),
+            (
+                r"container run --volume=C:\x:/y image",
+                "container run --volume=/mnt/c/x:/y image",
+            ),
+            (
+                r"run --mount type=bind,source=C:\x,target=/y image",
+                "run --mount type=bind,source=/mnt/c/x,target=/y image",
+            ),
+            (
+                r"run --mount type=bind,src=C:\x,target=/y image",
+                "run --mount type=bind,src=/mnt/c/x,target=/y image",
+            ),
+            (
+                "run --mount type=volume,source=data,target=/y image",
+                "run --mount type=volume,source=data,target=/y image",
+            ),
+            (
+                r"run --env-file C:\x\.env image",
+                "run --env-file /mnt/c/x/.env image",
+            ),
+            (r"run -e DIR=C:\x image", r"run -e DIR=C:\x image"),
+            (r"run image ls C:\x", r"run image ls C:\x"),
+            (r"build -t app C:\ctx", "build -t app /mnt/c/ctx"),
+            (
+                r"build -f C:\ctx\Dockerfile .",
+                "build -f /mnt/c/ctx/Dockerfile .",
+            ),
+            (
+                r"build -fC:\ctx\Dockerfile .",
+                "build -f/mnt/c/ctx/Dockerfile .",
+            ),
+            (
+                r"build --file=.\Dockerfile .",
+                "build --file=./Dockerfile .",
+            ),
+            (r"cp C:\a ctr:/b", "cp /mnt/c/a ctr:/b"),
+            (r"cp ctr:/b .\a", "cp ctr:/b ./a"),
+            (r"save -o C:\x\app.tar app", "save -o /mnt/c/x/app.tar app"),
+            (
+                r"image load -i C:\x\app.tar",
+                "image load -i /mnt/c/x/app.tar",
+            ),
+            (r"compose cp C:\x app:/y", "compose cp /mnt/c/x app:/y"),
+            (r"compose cp app:/y .\x", "compose cp app:/y ./x"),
+            (
+                r"compose exec app cat C:\x",
+                "compose exec app cat /mnt/c/x",
+            ),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(rewritten(command), expected, "{command}");
+        }
+    }
+
+    #[test]
+    fn volume_specs_split_after_the_drive_letter() {
+        assert_eq!(
+            split_volume_spec(r"C:\work\app:/app:ro"),
+            (r"C:\work\app", ":/app:ro")
+        );
+        assert_eq!(split_volume_spec("mydata:/data"), ("mydata", ":/data"));
+        assert_eq!(split_volume_spec("c:/data"), ("c", ":/data"));
+        assert_eq!(split_volume_spec("/data"), ("/data", ""));
+    }
+
+    #[test]
+    fn only_paths_count_as_bind_mounts() {
+        assert_eq!(volume_host(r"C:\work"), VolumeHost::WindowsPath);
+        assert_eq!(volume_host("D:/work"), VolumeHost::WindowsPath);
+        assert_eq!(volume_host(r"\\server\share"), VolumeHost::WindowsPath);
+        assert_eq!(volume_host(r".\data"), VolumeHost::RelativePath);
+        assert_eq!(volume_host("./data"), VolumeHost::RelativePath);
+        assert_eq!(volume_host(r"data\db"), VolumeHost::RelativePath);
+        assert_eq!(volume_host("/srv/data"), VolumeHost::LinuxPath);
+        assert_eq!(volume_host("mydata"), VolumeHost::Volume);
+        assert_eq!(volume_host("pg.data-1_x"), VolumeHost::Volume);
+        assert_eq!(volume_host("c"), VolumeHost::Volume);
+    }
+}
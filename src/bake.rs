@@ -0,0 +1,246 @@
+use crate::{
+    args::{convert_host_path, is_windows_absolute_path},
+    compose::{temp_file_path, TempFile},
+    config::Config,
+    flags::{scan, Token, ValueKind, BAKE_FLAGS},
+    wsl::convert_path,
+};
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Files `docker buildx bake` merges from the current directory when no `-f` is given, in
+/// the order it reads them.
+const DEFAULT_BAKE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+    "docker-bake.json",
+    "docker-bake.hcl",
+    "docker-bake.override.json",
+    "docker-bake.override.hcl",
+];
+
+/// Replaces the HCL and JSON files of a `docker buildx bake` invocation, `args` being what
+/// follows `bake`, with copies whose string literals holding Windows absolute paths, such as a
+/// `context` or `dockerfile`, hold distro paths instead. Other files are left for the flag
+/// rewriting to convert.
+///
+/// Bake resolves relative paths against the current directory, which docker keeps in the
+/// distro, so the copies can live in a temporary directory.
+pub fn fix_bake_files(config: &Config, args: &mut Vec<String>) -> Result<Vec<TempFile>> {
+    let tokens = scan(args, BAKE_FLAGS, true);
+    let file_args: Vec<_> = tokens
+        .iter()
+        .filter_map(|token| match *token {
+            Token::Flag {
+                spec: Some(spec),
+                value: Some(value),
+                ..
+            } if spec.value == ValueKind::BakeFile => Some(value),
+            _ => None,
+        })
+        .collect();
+
+    let mut temp_files = vec![];
+    if file_args.is_empty() {
+        let files: Vec<PathBuf> = DEFAULT_BAKE_FILES
+            .iter()
+            .map(PathBuf::from)
+            .filter(|file| file.is_file())
+            .collect();
+        let mut file_flags = vec![];
+        for file in &files {
+            let (path, temp_file) = translate_bake_file(config, file)?;
+            file_flags.push("-f".to_string());
+            file_flags.push(path);
+            temp_files.extend(temp_file);
+        }
+
+        // Nothing to rewrite: leave the discovery to bake itself.
+        if !temp_files.is_empty() {
+            args.splice(0..0, file_flags);
+        }
+        return Ok(temp_files);
+    }
+
+    for file_arg in file_args {
+        let value = &args[file_arg.index][file_arg.offset..];
+        if value == "-" {
+            continue;
+        }
+
+        let (path, temp_file) = translate_bake_file(config, Path::new(value))?;
+        args[file_arg.index].replace_range(file_arg.offset.., &path);
+        temp_files.extend(temp_file);
+    }
+
+    Ok(temp_files)
+}
+
+/// Returns the distro path bake should read `file` from, writing a rewritten copy first when
+/// it is an HCL or JSON file naming Windows paths.
+fn translate_bake_file(config: &Config, file: &Path) -> Result<(String, Option<TempFile>)> {
+    let extension = file.extension().and_then(|extension| extension.to_str());
+    let rewritten = match extension {
+        Some(extension @ ("hcl" | "json")) => {
+            let content = fs::read_to_string(file)
+                .with_context(|| format!("failed to read bake file '{}'", file.display()))?;
+            rewrite_path_literals(config, &content)?.map(|content| (extension, content))
+        }
+        _ => None,
+    };
+    let Some((extension, content)) = rewritten else {
+        let path = convert_host_path(config, &file.display().to_string())?;
+        return Ok((path, None));
+    };
+
+    let temp_path = temp_file_path("bake", extension)?;
+    fs::write(&temp_path, content)
+        .with_context(|| format!("failed to write '{}'", temp_path.display()))?;
+    let temp_file = TempFile(temp_path);
+    let path = convert_path(config, &temp_file.0.display().to_string())?;
+
+    Ok((path, Some(temp_file)))
+}
+
+/// Converts the string literals of an HCL or JSON file that are Windows absolute paths, in
+/// both of which a backslash is written `\\`. Literals interpolating variables are left
+/// alone. Returns `None` if there are none.
+fn rewrite_path_literals(config: &Config, content: &str) -> Result<Option<String>> {
+    let mut rewritten = String::with_capacity(content.len());
+    let mut changed = false;
+    let mut rest = content;
+    while let Some(start) = rest.find('"') {
+        rewritten.push_str(&rest[..=start]);
+        rest = &rest[start + 1..];
+
+        let mut escaped = false;
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match (escaped, c) {
+                (true, _) => escaped = false,
+                (false, '\\') => escaped = true,
+                (false, '"') => return Some(i),
+                _ => {}
+            }
+            None
+        });
+        let Some(end) = end else {
+            break;
+        };
+
+        let literal = &rest[..end];
+        let path = literal.replace(r"\\", r"\");
+        if is_windows_absolute_path(&path) && !literal.contains("${") {
+            rewritten.push_str(&convert_path(config, &path)?);
+            changed = true;
+        } else {
+            rewritten.push_str(literal);
+        }
+        rewritten.push('"');
+        rest = &rest[end + 1..];
+    }
+    rewritten.push_str(rest);
+
+    Ok(changed.then_some(rewritten))
+}
+
+/// Converts the path of a `--set` override such as `app.context=C:\work\app`, for the
+/// attributes that name local files.
+pub fn convert_set_override(config: &Config, set: &str) -> Result<String> {
+    let Some((key, value)) = set.split_once('=') else {
+        return Ok(set.to_string());
+    };
+    let attribute = key.rsplit('.').next().unwrap_or(key);
+    if !matches!(attribute, "context" | "dockerfile") || !is_windows_absolute_path(value) {
+        return Ok(set.to_string());
+    }
+
+    Ok(format!("{key}={}", convert_path(config, value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(content: &str) -> Option<String> {
+        rewrite_path_literals(&Config::default(), content).unwrap()
+    }
+
+    #[test]
+    fn hcl_path_literals_are_converted() {
+        let hcl = r#"
+target "app" {
+  context = "C:\\work\\app"
+  dockerfile = "D:\\docker\\app.Dockerfile"
+  tags = ["app:latest"]
+}
+"#;
+        assert_eq!(
+            rewritten(hcl).as_deref(),
+            Some(
+                r#"
+target "app" {
+  context = "/mnt/c/work/app"
+  dockerfile = "/mnt/d/docker/app.Dockerfile"
+  tags = ["app:latest"]
+}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn escaped_quotes_do_not_end_a_literal() {
+        let hcl = r#"args = { MESSAGE = "say \"C:\\x\"" }
+context = "C:\\x""#;
+        assert_eq!(
+            rewritten(hcl).as_deref(),
+            Some(
+                r#"args = { MESSAGE = "say \"C:\\x\"" }
+context = "/mnt/c/x""#
+            )
+        );
+    }
+
+    #[test]
+    fn interpolated_and_relative_literals_are_left_alone() {
+        let hcl = r#"
+target "app" {
+  context = "${ROOT}\\app"
+  dockerfile = "C:\\${DIR}\\Dockerfile"
+  contexts = { base = "./base" }
+}
+"#;
+        assert_eq!(rewritten(hcl), None);
+    }
+
+    #[test]
+    fn json_path_literals_are_converted() {
+        let json =
+            r#"{"target": {"app": {"context": "C:\\work\\app", "dockerfile": "Dockerfile"}}}"#;
+        assert_eq!(
+            rewritten(json).as_deref(),
+            Some(
+                r#"{"target": {"app": {"context": "/mnt/c/work/app", "dockerfile": "Dockerfile"}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn set_overrides_convert_only_file_attributes() {
+        let config = Config::default();
+        let converted = |set| convert_set_override(&config, set).unwrap();
+        assert_eq!(converted(r"app.context=C:\x"), "app.context=/mnt/c/x");
+        assert_eq!(
+            converted(r"*.dockerfile=C:\x\Dockerfile"),
+            "*.dockerfile=/mnt/c/x/Dockerfile"
+        );
+        assert_eq!(converted(r"app.args.DIR=C:\x"), r"app.args.DIR=C:\x");
+        assert_eq!(converted("app.context=./app"), "app.context=./app");
+        assert_eq!(converted("app.no-cache"), "app.no-cache");
+    }
+}
@@ -0,0 +1,493 @@
+use crate::{
+    config::Verbosity,
+    context::DEFAULT_CONTEXT_NAME,
+    expose::DEFAULT_PIPE_NAME,
+    flavor::Flavor,
+    preset::{Preset, PRESET_TLS_PORT},
+    vm::MemoryReclaim,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// First argument that switches from docker passthrough to the wrapper's own commands.
+pub const NAMESPACE: &str = "docker-wrapper";
+
+/// Wrapper commands that predate the `docker-wrapper` namespace and are still accepted bare.
+const LEGACY_SUBCOMMANDS: &[&str] = &["stop-daemon", "reset-registration"];
+
+/// Commands of the docker wrapper itself, reached through `docker docker-wrapper ...`.
+///
+/// Anything that is not a wrapper subcommand is forwarded to docker, so
+/// `docker docker-wrapper --config other.toml ps -a` runs `docker ps -a` with another config.
+#[derive(Debug, Parser)]
+#[command(
+    name = "docker-wrapper",
+    bin_name = "docker docker-wrapper",
+    version,
+    about
+)]
+pub struct WrapperCli {
+    /// Use this config file instead of `~/.config/docker-wrapper/config.toml`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Use the distro of this profile from the config instead of the default one.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Print only errors from the wrapper itself; docker's own output is unaffected.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print every command the wrapper runs on the way.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Print the command docker would be run with after path conversion, without running it.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Rebuild the distro if it turns out to be broken, rather than failing with a hint.
+    #[arg(long, global = true)]
+    pub auto_repair: bool,
+
+    #[command(subcommand)]
+    pub command: WrapperCommand,
+}
+
+impl WrapperCli {
+    /// Verbosity requested on the command line, overriding the config.
+    pub fn verbosity(&self) -> Option<Verbosity> {
+        match (self.quiet, self.verbose) {
+            (true, _) => Some(Verbosity::Quiet),
+            (_, true) => Some(Verbosity::Verbose),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WrapperCommand {
+    /// Shut down WSL, stopping the docker daemon with it.
+    StopDaemon,
+    /// Unregister the docker host distro and provision it again from scratch.
+    ResetRegistration,
+    /// Provision the docker host distro now rather than on the first docker command.
+    Setup {
+        /// Base distro to build the docker host from, stored in the config.
+        #[arg(long)]
+        flavor: Option<Flavor>,
+        /// Import this rootfs tarball instead of downloading one, for machines offline.
+        #[arg(long, value_name = "PATH")]
+        rootfs_file: Option<PathBuf>,
+        /// Install docker from the .deb packages in this directory instead of from the
+        /// internet.
+        #[arg(long, value_name = "DIR")]
+        engine_debs: Option<PathBuf>,
+        /// Run dockerd as an unprivileged user, stored in the config.
+        #[arg(long)]
+        rootless: bool,
+        /// Go ahead even if the drive seems too full for the distro.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Rebuild the distro on a fresh base of the configured release, keeping images,
+    /// containers and volumes.
+    Reprovision {
+        /// Go ahead even if the drive seems too full for the backup and the new distro.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Move the distro with everything in it into DIR, which becomes `storage_dir`.
+    Move {
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+        /// Go ahead even if the drive seems too full for the distro.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Save the distro with its images, containers and volumes into a tarball, along with a
+    /// `<FILE>.json` describing it.
+    Backup {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Import a tarball saved by `backup` as the distro.
+    Restore {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        /// Overwrite the distro if it is registered already.
+        #[arg(long)]
+        replace: bool,
+        /// Go ahead even if the drive seems too full for the distro.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Copy the distro with everything in it into a new profile, leaving the original as it
+    /// is, to try changes on.
+    Clone {
+        /// Name of the profile to create.
+        profile: String,
+        /// Go ahead even if the drive seems too full for the copy.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Register the distro under another name, stored as `distro_name` in the config. The old
+    /// registration is only removed once the renamed distro runs docker.
+    Rename {
+        new_name: String,
+        /// Go ahead even if the drive seems too full for the copy.
+        #[arg(long)]
+        skip_disk_check: bool,
+    },
+    /// Remove the distro with all its images, containers and volumes, along with everything
+    /// else the wrapper set up for it: downloads, state, scheduled tasks, contexts and
+    /// certificates.
+    Uninstall {
+        /// Confirm that the docker data is to be deleted.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Carry over a distro set up by an earlier version under another name, by default
+    /// renaming it to the configured `distro_name`.
+    Migrate {
+        /// Keep the distro under its name, storing that as `distro_name`.
+        #[arg(long)]
+        adopt: bool,
+        /// Leave the distro alone and set a new one up.
+        #[arg(long, conflicts_with = "adopt")]
+        ignore: bool,
+    },
+    /// Use a distro that has docker installed already as the docker host, instead of one set
+    /// up by the wrapper. `uninstall` leaves it registered.
+    Adopt { distro: String },
+    /// Upgrade the docker engine in the distro to the latest release and restart it.
+    UpgradeEngine {
+        /// Only list the docker packages that have updates.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Keep the footprint of the distro down with the features the installed WSL supports.
+    /// Without options, enables all of them.
+    Optimize {
+        /// Make the virtual disk sparse, so that space freed inside is given back to Windows.
+        #[arg(long)]
+        sparse: bool,
+        /// How the VM gives memory it no longer uses back to Windows.
+        #[arg(long, value_name = "MODE")]
+        memory_reclaim: Option<MemoryReclaim>,
+    },
+    /// Shrink the virtual disk of the distro to what it holds, giving the space freed by
+    /// deleted images back to Windows. Shuts WSL down and takes an elevated prompt.
+    Compact,
+    /// Show what takes up space: docker's images, containers and volumes, the virtual disk
+    /// they are stored in and the cached rootfs.
+    DiskUsage,
+    /// Check WSL, the distro and the docker daemon, with hints for anything broken.
+    Doctor,
+    /// Show whether the distro and the docker daemon are running.
+    Status {
+        /// Print the status as JSON, for scripts.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the log of the docker daemon in the distro.
+    Logs {
+        /// Keep printing new lines as they are logged.
+        #[arg(short, long)]
+        follow: bool,
+        /// Number of lines to print from the end of the log.
+        #[arg(short = 'n', long, default_value_t = 100)]
+        tail: usize,
+    },
+    /// Change the wrapper's settings.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Read or change `/etc/docker/daemon.json` in the distro, restarting the daemon on
+    /// changes. Keys are dotted paths such as `log-opts.max-size`.
+    #[command(subcommand)]
+    DaemonConfig(DaemonConfigCommand),
+    /// Adjust the networking of the distro to the networks of Windows.
+    #[command(subcommand)]
+    Network(NetworkCommand),
+    /// Manage how the ports containers publish are reached from Windows and the LAN.
+    #[command(subcommand)]
+    Ports(PortsCommand),
+    /// Save named volumes to tarballs on the Windows side and load them back.
+    #[command(subcommand)]
+    Volume(VolumeCommand),
+    /// Serve the Docker API to native Windows tools until interrupted.
+    Expose {
+        /// Named pipe to serve, `\\.\pipe\<NAME>`.
+        #[arg(long, value_name = "NAME", default_value = DEFAULT_PIPE_NAME)]
+        pipe: String,
+        /// Serve on this localhost TCP port instead of a named pipe.
+        #[arg(long, value_name = "PORT", conflicts_with = "pipe")]
+        tcp: Option<u16>,
+        /// With --tcp, have the daemon serve the port itself with TLS and client certificates.
+        /// The setting persists, so the wrapper exits once the daemon has been reconfigured.
+        #[arg(long, requires = "tcp")]
+        tls: bool,
+        /// Remove the TLS listener set up with --tls.
+        #[arg(long, conflicts_with_all = ["tcp", "tls"])]
+        no_tls: bool,
+    },
+    /// Serve the Docker API for VS Code Dev Containers until interrupted, after printing the
+    /// settings that point VS Code at the wrapper and the served pipe.
+    Devcontainers {
+        /// Named pipe to serve, `\\.\pipe\<NAME>`.
+        #[arg(long, value_name = "NAME", default_value = DEFAULT_PIPE_NAME)]
+        pipe: String,
+    },
+    /// Set the daemon up for a particular use, printing the environment tools need if any.
+    Enable {
+        preset: Preset,
+        /// Localhost port the daemon serves TLS on, for testcontainers.
+        #[arg(long, value_name = "PORT", default_value_t = PRESET_TLS_PORT)]
+        port: u16,
+        /// Also store the environment in the Windows user environment with `setx`, for
+        /// testcontainers.
+        #[arg(long)]
+        persist: bool,
+    },
+    /// Manage docker contexts pointing the Windows docker CLI at the exposed daemon.
+    #[command(subcommand)]
+    Context(ContextCommand),
+    /// Control the resident agent that speeds up helper commands.
+    #[command(subcommand)]
+    Agent(AgentCommand),
+    /// Schedule or run the updates set up by `[maintenance]` in the config.
+    #[command(subcommand)]
+    Maintenance(MaintenanceCommand),
+    /// Stop the distro once idle for `daemon.idle_shutdown` minutes.
+    #[command(subcommand)]
+    Idle(IdleCommand),
+    /// Start the distro and the daemon when logging in to Windows, so that the first docker
+    /// command does not wait for them.
+    #[command(subcommand)]
+    Autostart(AutostartCommand),
+    /// Relay an ssh agent connection to the Windows agent; run from the distro by builds
+    /// given `--ssh`.
+    #[command(hide = true)]
+    SshAgentRelay,
+    /// Run docker with the given arguments.
+    #[command(external_subcommand)]
+    Docker(Vec<String>),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ContextCommand {
+    /// Create or update a context for the endpoint served by `expose`, to be selected with
+    /// `docker context use`.
+    Create {
+        /// Name of the context.
+        #[arg(default_value = DEFAULT_CONTEXT_NAME)]
+        name: String,
+        /// Named pipe the context connects to, `\\.\pipe\<NAME>`.
+        #[arg(long, value_name = "NAME", default_value = DEFAULT_PIPE_NAME)]
+        pipe: String,
+        /// Connect to this localhost TCP port instead of a named pipe.
+        #[arg(long, value_name = "PORT", conflicts_with = "pipe")]
+        tcp: Option<u16>,
+        /// With --tcp, use the client certificates generated by `expose --tls`.
+        #[arg(long, requires = "tcp")]
+        tls: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AgentCommand {
+    /// Start the agent now rather than on first use.
+    Start,
+    /// Stop the running agent.
+    Stop,
+    /// Run as the agent; started in the background by the wrapper.
+    #[command(hide = true)]
+    Serve {
+        #[arg(long)]
+        distro: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MaintenanceCommand {
+    /// Register the daily task with the Windows Task Scheduler.
+    Install,
+    /// Remove the daily task.
+    Uninstall,
+    /// Apply the updates now, as the task does.
+    Run,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DaemonConfigCommand {
+    /// Print the value of a key, or the whole file without one.
+    Get { key: Option<String> },
+    /// Set a key, keeping the rest of the file. The value is read as JSON, such as `true`,
+    /// `3` or `["a","b"]`, and taken as a string otherwise.
+    Set { key: String, value: String },
+    /// Remove a key.
+    Unset { key: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NetworkCommand {
+    /// Make the distro and docker's networks use the MTU of the Windows adapters, which a
+    /// VPN lowers, so that large packets are not silently dropped.
+    FixMtu {
+        /// Use this MTU instead of the one detected.
+        #[arg(long)]
+        mtu: Option<u16>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PortsCommand {
+    /// List the ports the running containers publish, whether they answer on Windows'
+    /// localhost and the LAN, and the URL to use.
+    List {
+        /// Print the ports as JSON, for scripts.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Forward the ports the running containers publish from the LAN, and stop forwarding
+    /// those no longer published, as `network.lan_ports` does around docker commands.
+    Sync,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VolumeCommand {
+    /// Save the contents of a named volume into a gzipped tarball.
+    Backup {
+        volume: String,
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Load a tarball saved by `backup` into a named volume, created if it does not exist.
+    Restore {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        volume: String,
+        /// Replace the contents of the volume if it exists already.
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum IdleCommand {
+    /// Register a task checking every few minutes with the Windows Task Scheduler.
+    Install,
+    /// Remove the task.
+    Uninstall,
+    /// Stop the distro now if it is idle, as the task does.
+    Check,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AutostartCommand {
+    /// Register the task with the Windows Task Scheduler. Depending on the policies of the
+    /// machine, this takes an elevated prompt.
+    Enable,
+    /// Remove the task.
+    Disable,
+    /// Start the distro and the daemon now, as the task does.
+    Run,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Store a setting in the config file and apply it to the installed distro.
+    Set { key: ConfigKey, value: String },
+    /// Change the resources of the WSL VM in `%USERPROFILE%\.wslconfig`.
+    #[command(subcommand)]
+    Vm(VmCommand),
+    /// Change the distro's `/etc/wsl.conf`, such as its mount options and interop.
+    #[command(subcommand)]
+    WslConf(WslConfCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WslConfCommand {
+    /// Set keys such as `automount.options=metadata` or `interop.appendWindowsPath=false`.
+    Set {
+        #[arg(required = true, value_name = "SECTION.KEY=VALUE")]
+        settings: Vec<String>,
+        /// Restart the distro so that the settings apply, stopping its containers.
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VmCommand {
+    /// Set keys of the `[wsl2]` section, such as `memory=8GB processors=4 swap=2GB`.
+    Set {
+        #[arg(required = true, value_name = "KEY=VALUE")]
+        settings: Vec<String>,
+        /// Shut WSL down so that the settings apply, stopping every distro.
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+/// Settings that can be changed with `config set`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConfigKey {
+    /// Key sequence detaching from a container (`ctrl-p,ctrl-q`).
+    DetachKeys,
+    /// How much the wrapper prints: `quiet`, `normal` or `verbose`.
+    Verbosity,
+    /// Directory new distros are imported into, such as `D:\wsl`.
+    StorageDir,
+    /// Registry mirrors the daemon pulls through, comma-separated; empty for none.
+    RegistryMirror,
+    /// Registries the daemon reaches without verified TLS, comma-separated; empty for none.
+    InsecureRegistry,
+    /// DNS servers pinned for the distro and the daemon, comma-separated; empty for the
+    /// ones of Windows.
+    Dns,
+}
+
+impl ConfigKey {
+    /// Where the setting lives in the config file.
+    pub fn path(self) -> &'static [&'static str] {
+        match self {
+            Self::DetachKeys => &["client", "detach_keys"],
+            Self::Verbosity => &["output", "verbosity"],
+            Self::StorageDir => &["storage_dir"],
+            Self::RegistryMirror => &["daemon", "registry_mirrors"],
+            Self::InsecureRegistry => &["daemon", "insecure_registries"],
+            Self::Dns => &["network", "dns"],
+        }
+    }
+
+    /// Whether the setting holds a list, given as comma-separated values.
+    pub fn is_list(self) -> bool {
+        matches!(
+            self,
+            Self::RegistryMirror | Self::InsecureRegistry | Self::Dns
+        )
+    }
+}
+
+pub enum Invocation {
+    /// Plain docker invocation to be forwarded as-is (after path rewriting).
+    Docker(Vec<String>),
+    Wrapper(WrapperCli),
+}
+
+impl Invocation {
+    /// Decides whether `args` (without the program name) are meant for docker or for the
+    /// wrapper. Parse errors and `--help` of the wrapper CLI exit the process like clap does.
+    pub fn parse(args: Vec<String>) -> Self {
+        match args.first().map(|arg| &**arg) {
+            Some(NAMESPACE) => Self::Wrapper(WrapperCli::parse_from(&args)),
+            Some(first) if LEGACY_SUBCOMMANDS.contains(&first) => {
+                let wrapper_args = [NAMESPACE.to_string()].into_iter().chain(args);
+                Self::Wrapper(WrapperCli::parse_from(wrapper_args))
+            }
+            _ => Self::Docker(args),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::{
+    args::{compose_global_end, convert_host_path, is_windows_absolute_path, split_volume_spec},
+    config::Config,
+    flags::{scan, Token, ValueKind, ValueRef, COMPOSE_GLOBAL_FLAGS},
+    wsl::convert_path,
+};
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Files `docker compose` picks up from the current directory when no `-f` is given.
+const DEFAULT_COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Files `docker compose` merges on top of the default file when no `-f` is given.
+const DEFAULT_OVERRIDE_FILES: &[&str] = &[
+    "compose.override.yaml",
+    "compose.override.yml",
+    "docker-compose.override.yaml",
+    "docker-compose.override.yml",
+];
+
+/// File that is removed when dropped, used for rewritten compose and bake files that must
+/// outlive argument rewriting until docker has finished.
+pub struct TempFile(pub PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Replaces the compose files of a `docker compose` invocation with copies whose bind mounts
+/// use distro paths. Files that need no rewriting are only converted in place. Without `-f`,
+/// the files of `COMPOSE_FILE` or the default ones are passed as `-f` when one is rewritten.
+///
+/// The rewritten copies live in a temporary directory, so `--project-directory` is pinned to
+/// the directory of the first original file to keep relative paths and the project name.
+pub fn fix_compose_files(config: &Config, args: &mut Vec<String>) -> Result<Vec<TempFile>> {
+    let global_end = compose_global_end(args);
+    let tokens = scan(&args[1..global_end], COMPOSE_GLOBAL_FLAGS, false);
+    let mut file_args = vec![];
+    let mut has_project_directory = false;
+    for token in tokens {
+        let Token::Flag {
+            spec: Some(spec),
+            value: Some(value),
+            ..
+        } = token
+        else {
+            continue;
+        };
+
+        // The tokens index into `args[1..]`.
+        let value = ValueRef {
+            index: value.index + 1,
+            ..value
+        };
+        match spec.value {
+            ValueKind::ComposeFile => file_args.push(value),
+            _ if spec.names.contains(&"--project-directory") => has_project_directory = true,
+            _ => {}
+        }
+    }
+
+    let mut temp_files = vec![];
+    let first_file = if file_args.is_empty() {
+        let files = compose_file_env().unwrap_or_else(discover_compose_files);
+        let mut file_flags = vec![];
+        for file in &files {
+            let (path, temp_file) = translate_compose_file(config, file)?;
+            file_flags.push("-f".to_string());
+            file_flags.push(path);
+            temp_files.extend(temp_file);
+        }
+
+        // Nothing to rewrite: leave the discovery to compose itself.
+        if temp_files.is_empty() {
+            return Ok(temp_files);
+        }
+
+        args.splice(1..1, file_flags);
+        files[0].display().to_string()
+    } else {
+        let first = args[file_args[0].index][file_args[0].offset..].to_string();
+        for file_arg in &file_args {
+            let value = &args[file_arg.index][file_arg.offset..];
+            if value == "-" {
+                continue;
+            }
+
+            let (path, temp_file) = translate_compose_file(config, Path::new(value))?;
+            args[file_arg.index].replace_range(file_arg.offset.., &path);
+            temp_files.extend(temp_file);
+        }
+
+        first
+    };
+
+    if !temp_files.is_empty() && !has_project_directory {
+        let project_dir = Path::new(&first_file)
+            .parent()
+            .map(|dir| dir.display().to_string())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+        let project_dir = convert_host_path(config, &project_dir)?;
+        args.splice(1..1, ["--project-directory".to_string(), project_dir]);
+    }
+
+    Ok(temp_files)
+}
+
+/// The files listed by `COMPOSE_FILE`, separated by `;` on Windows unless
+/// `COMPOSE_PATH_SEPARATOR` says otherwise.
+fn compose_file_env() -> Option<Vec<PathBuf>> {
+    let files = env::var("COMPOSE_FILE").ok()?;
+    let separator = env::var("COMPOSE_PATH_SEPARATOR").unwrap_or_else(|_| ";".to_string());
+    let files: Vec<_> = files
+        .split(&*separator)
+        .filter(|file| !file.is_empty())
+        .map(PathBuf::from)
+        .collect();
+
+    (!files.is_empty()).then_some(files)
+}
+
+fn discover_compose_files() -> Vec<PathBuf> {
+    let find = |candidates: &[&str]| {
+        candidates
+            .iter()
+            .map(PathBuf::from)
+            .find(|candidate| candidate.is_file())
+    };
+
+    find(DEFAULT_COMPOSE_FILES)
+        .into_iter()
+        .chain(find(DEFAULT_OVERRIDE_FILES))
+        .collect()
+}
+
+/// Returns the distro path docker should read `file` from, writing a rewritten copy first
+/// when its volumes refer to Windows paths.
+fn translate_compose_file(config: &Config, file: &Path) -> Result<(String, Option<TempFile>)> {
+    let content = fs::read_to_string(file)
+        .with_context(|| format!("failed to read compose file '{}'", file.display()))?;
+    let mut compose: Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("failed to parse compose file '{}'", file.display()))?;
+
+    if !rewrite_volumes(config, &mut compose)? {
+        let path = convert_host_path(config, &file.display().to_string())?;
+        return Ok((path, None));
+    }
+
+    let temp_path = temp_file_path("compose", "yml")?;
+    fs::write(&temp_path, serde_yaml::to_string(&compose)?)
+        .with_context(|| format!("failed to write '{}'", temp_path.display()))?;
+    let temp_file = TempFile(temp_path);
+    let path = convert_host_path(config, &temp_file.0.display().to_string())?;
+
+    Ok((path, Some(temp_file)))
+}
+
+/// Fresh path in the wrapper's temporary directory for a rewritten file of `kind`.
+pub fn temp_file_path(kind: &str, extension: &str) -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = env::temp_dir().join("docker-wrapper");
+    fs::create_dir_all(&dir)?;
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    Ok(dir.join(format!("{kind}-{}-{n}.{extension}", process::id())))
+}
+
+/// Rewrites the host side of every service volume, in both the short (`C:\x:/x`) and the long
+/// (`source: C:\x`) syntax. Returns whether anything was changed.
+fn rewrite_volumes(config: &Config, compose: &mut Value) -> Result<bool> {
+    let Some(services) = compose.get_mut("services").and_then(Value::as_mapping_mut) else {
+        return Ok(false);
+    };
+
+    let mut changed = false;
+    for service in services.values_mut() {
+        let Some(volumes) = service.get_mut("volumes").and_then(Value::as_sequence_mut) else {
+            continue;
+        };
+
+        for volume in volumes {
+            let host = match volume {
+                Value::String(spec) => {
+                    let (host, rest) = split_volume_spec(spec);
+                    convert_compose_host_path(config, host)?.map(|host| format!("{host}{rest}"))
+                }
+                Value::Mapping(volume) => match volume.get("source") {
+                    Some(Value::String(source)) => convert_compose_host_path(config, source)?,
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            let Some(host) = host else {
+                continue;
+            };
+            match volume {
+                Value::Mapping(volume) => {
+                    volume.insert("source".into(), host.into());
+                }
+                _ => *volume = host.into(),
+            }
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Converts a host path found in a compose file. Relative paths are resolved by compose
+/// against the project directory, so they only need their separators fixed.
+fn convert_compose_host_path(config: &Config, path: &str) -> Result<Option<String>> {
+    if is_windows_absolute_path(path) {
+        return convert_path(config, path).map(Some);
+    }
+
+    if path.starts_with('.') && path.contains('\\') {
+        return Ok(Some(path.replace('\\', "/")));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(compose: &str) -> Option<String> {
+        let mut compose: Value = serde_yaml::from_str(compose).unwrap();
+        rewrite_volumes(&Config::default(), &mut compose)
+            .unwrap()
+            .then(|| serde_yaml::to_string(&compose["services"]["app"]["volumes"]).unwrap())
+    }
+
+    #[test]
+    fn volumes_are_rewritten_in_both_syntaxes() {
+        let compose = r#"
+services:
+  app:
+    volumes:
+      - 'C:\work\app:/app:ro'
+      - '.\data:/data'
+      - ./ok:/ok
+      - cache:/cache
+      - type: bind
+        source: 'C:\work\logs'
+        target: /logs
+      - type: volume
+        source: db
+        target: /db
+"#;
+        assert_eq!(
+            rewritten(compose).as_deref(),
+            Some(
+                "- /mnt/c/work/app:/app:ro
+- ./data:/data
+- ./ok:/ok
+- cache:/cache
+- type: bind
+  source: /mnt/c/work/logs
+  target: /logs
+- type: volume
+  source: db
+  target: /db
+"
+            )
+        );
+    }
+
+    #[test]
+    fn named_volumes_and_linux_paths_are_left_alone() {
+        let compose = "
+services:
+  app:
+    volumes:
+      - cache:/cache
+      - ./ok:/ok
+      - /srv/data:/data
+";
+        assert_eq!(rewritten(compose), None);
+    }
+
+    #[test]
+    fn project_directory_follows_a_rewritten_file() {
+        let dir = env::temp_dir().join(format!("docker-wrapper-compose-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("compose.yml");
+        fs::write(
+            &file,
+            "services:\n  app:\n    volumes:\n      - 'C:\\x:/x'\n",
+        )
+        .unwrap();
+        let dir = dir.display().to_string();
+        let file = file.display().to_string();
+
+        let mut args: Vec<String> = ["compose", "-f", &file, "up"].map(String::from).into();
+        let temp_files = fix_compose_files(&Config::default(), &mut args).unwrap();
+        let copy = temp_files[0].0.display().to_string();
+        assert_eq!(
+            args,
+            ["compose", "--project-directory", &dir, "-f", &copy, "up"]
+        );
+        assert!(fs::read_to_string(&copy).unwrap().contains("/mnt/c/x:/x"));
+
+        let mut args: Vec<String> = ["compose", "--project-directory", ".", "-f", &file, "up"]
+            .map(String::from)
+            .into();
+        let temp_files = fix_compose_files(&Config::default(), &mut args).unwrap();
+        let copy = temp_files[0].0.display().to_string();
+        assert_eq!(
+            args,
+            ["compose", "--project-directory", ".", "-f", &copy, "up"]
+        );
+
+        drop(temp_files);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,973 @@
+use crate::{
+    flavor::{Arch, Flavor},
+    network::parse_cidr,
+};
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use toml_edit::{DocumentMut, Item, TableLike};
+
+const DEFAULT_UBUNTU_RELEASE: &str = "jammy";
+const DEFAULT_DISTRO_NAME: &str = "custom-docker-host";
+const DEFAULT_DETACH_KEYS: &str = "ctrl-^";
+const DEFAULT_DISTRO_USER: &str = "dockeruser";
+/// Smallest MTU IPv6 works with, which docker's networks may carry.
+pub const MIN_MTU: u16 = 1280;
+
+/// Wrapper settings loaded from `~/.config/docker-wrapper/config.toml`.
+///
+/// Every key is optional; anything missing falls back to the built-in default, and the
+/// `DOCKER_WRAPPER_*` environment variables take precedence over the file.
+///
+/// `[profiles.<name>]` tables describe further distros, each overriding any of the keys
+/// above; the one used is picked with `--profile`, `DOCKER_WRAPPER_PROFILE` or a top-level
+/// `profile = "<name>"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Profile the settings were taken from, `None` for the top-level ones.
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Config file given instead of the default one.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// Skips checking for free disk space before provisioning, set by `--skip-disk-check`
+    /// or `DOCKER_WRAPPER_SKIP_DISK_CHECK`.
+    #[serde(skip)]
+    pub skip_disk_check: bool,
+    /// Name the docker host distro is registered under in WSL.
+    pub distro_name: String,
+    /// Non-root user of the distro that docker commands run as, in the docker group. It is
+    /// also the default user of `wsl -d <distro>`.
+    pub distro_user: String,
+    /// Base distro of the docker host.
+    pub flavor: Flavor,
+    /// Architecture of the rootfs, by default the one of Windows.
+    pub arch: Option<Arch>,
+    /// Ubuntu release of the docker host, a codename such as `noble`, or `latest` for the
+    /// newest one with a WSL image at setup time.
+    pub ubuntu_release: String,
+    /// Where the rootfs tarball of the docker host is downloaded from, by default the
+    /// official one of the flavor.
+    pub rootfs_url: Option<String>,
+    /// Expected SHA-256 of the rootfs tarball, by default the one published next to it.
+    pub rootfs_sha256: Option<String>,
+    /// Local rootfs tarball imported instead of downloading one. It is only verified when
+    /// `rootfs_sha256` is set.
+    pub rootfs_file: Option<PathBuf>,
+    /// Keeps the downloaded rootfs tarball after it is imported, so that setting up again
+    /// does not download it again. A `rootfs_file` is never deleted.
+    pub keep_rootfs: bool,
+    /// Version of the docker engine and CLI installed at setup, such as `27.3` for the newest
+    /// patch release of it, by default the latest.
+    pub docker_version: Option<String>,
+    /// Directory of docker-ce, CLI and containerd `.deb` packages installed at setup instead
+    /// of fetching docker from the internet. Only for the flavors using dpkg.
+    pub engine_debs: Option<PathBuf>,
+    /// Directory that holds one subdirectory per managed distro.
+    pub storage_dir: PathBuf,
+    pub client: ClientConfig,
+    pub daemon: DaemonConfig,
+    pub agent: AgentConfig,
+    pub output: OutputConfig,
+    pub network: NetworkConfig,
+    pub maintenance: MaintenanceConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Key sequence detaching from a container, written to the distro's
+    /// `~/.docker/config.json`.
+    pub detach_keys: String,
+    /// Copies the registry logins of the Windows docker CLI into the distro's client config
+    /// before commands that may talk to a registry.
+    pub windows_credentials: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Enables BuildKit in the generated `/etc/docker/daemon.json`.
+    pub buildkit: bool,
+    /// Seconds to wait for a starting daemon to accept connections before giving up.
+    pub start_timeout: u64,
+    /// Seconds for which a daemon seen alive is trusted to still run, skipping the check
+    /// and start otherwise done before every command. `0` checks every time.
+    pub alive_cache: u64,
+    /// Rebuilds a distro that no longer runs commands or whose daemon cannot start, keeping
+    /// the docker data when it is readable, instead of failing every command.
+    pub auto_repair: bool,
+    /// Runs dockerd as an unprivileged user of the distro instead of root. Applied at setup;
+    /// switching an installed distro takes a reprovision.
+    pub rootless: bool,
+    /// Boots the distro with systemd, which then manages dockerd, when the rootfs has it.
+    /// Applied at setup; distros without systemd keep using the init scripts.
+    pub systemd: bool,
+    /// Minutes without docker commands or running containers after which `idle check`
+    /// stops the distro to give its memory back to Windows. `0` keeps it running.
+    pub idle_shutdown: u64,
+    /// Pull-through caches the daemon tries before Docker Hub, such as
+    /// `https://mirror.corp.example`, written to `registry-mirrors` of `daemon.json`.
+    pub registry_mirrors: Vec<String>,
+    /// Registries reached over plain HTTP or with certificates that do not verify, as
+    /// `host:port` or a CIDR, written to `insecure-registries` of `daemon.json`.
+    pub insecure_registries: Vec<String>,
+    /// Log driver of containers, written to `log-driver` of `daemon.json` at setup.
+    pub log_driver: String,
+    /// Size a container log grows to before it is rotated, such as `10m`, for the
+    /// `json-file` and `local` drivers. Empty to let logs grow without bound.
+    pub log_max_size: String,
+    /// Rotated log files kept per container along with `log_max_size`.
+    pub log_max_file: u32,
+    /// Ranges the daemon carves bridge networks out of, instead of the `172.17.0.0/16` and
+    /// neighbours that corporate networks and VPNs often route. Written to
+    /// `default-address-pools` of `daemon.json` at setup.
+    pub address_pools: Vec<AddressPool>,
+}
+
+/// `[[daemon.address_pools]]` entry: networks of `size` bits of prefix are taken from `base`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressPool {
+    /// IPv4 range in CIDR notation, such as `10.200.0.0/16`.
+    pub base: String,
+    pub size: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    /// Starts a resident agent on first use, keeping a WSL session open to run helper
+    /// commands through. An agent started with `agent start` is used either way.
+    pub enabled: bool,
+    /// Seconds without requests after which the agent exits.
+    pub idle_timeout: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Translates distro paths in the output of inspect commands back for Windows tools.
+    pub translate_paths: PathStyle,
+    /// How much the wrapper itself prints to stderr.
+    pub verbosity: Verbosity,
+}
+
+/// Updates applied by `maintenance run`, which `maintenance install` schedules daily.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    /// Allows scheduling the maintenance task; nothing is updated behind the user's back
+    /// without it.
+    pub enabled: bool,
+    /// Local time of day the task runs at, as `HH:MM`.
+    pub time: String,
+    /// Upgrades the packages of the distro, security fixes among them.
+    pub distro_updates: bool,
+    /// Upgrades the docker engine, unless `docker_version` pins it.
+    pub engine_updates: bool,
+}
+
+/// Proxy and CA for networks that intercept traffic. Without proxies set here, the
+/// `HTTP(S)_PROXY` environment variables are used, and then the Windows proxy settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    /// Comma-separated hosts reached without the proxy.
+    pub no_proxy: Option<String>,
+    /// PEM file of the CA that signs intercepted HTTPS traffic, trusted by the wrapper's
+    /// downloads instead of the system store and installed into the distro.
+    pub ca_file: Option<PathBuf>,
+    /// MTU of the distro's `eth0` and of docker's bridge networks, for VPNs whose adapter
+    /// takes smaller packets than WSL assumes. Set by `network fix-mtu`.
+    pub mtu: Option<u16>,
+    /// Nameservers pinned instead of the ones WSL passes on from Windows, which VPNs often
+    /// leave unusable.
+    pub dns: Vec<String>,
+    /// Where `dns` applies: `distro`, `daemon` or `both`.
+    pub dns_target: DnsTarget,
+    /// Forwards the ports containers publish on every address from the LAN addresses of
+    /// Windows with `netsh interface portproxy`, which takes an elevated terminal. Mirrored
+    /// networking needs no forwarding.
+    pub lan_ports: bool,
+    /// Also lets the forwarded ports through Windows Firewall, from `lan_firewall_scope`
+    /// only. The rules are named `docker-wrapper <distro> tcp/<port>`.
+    pub lan_firewall: bool,
+    /// Remote addresses the firewall rules admit, in the `remoteip` format of netsh:
+    /// `localsubnet`, `any`, or addresses and ranges separated by commas.
+    pub lan_firewall_scope: String,
+    /// Points `host.docker.internal` at the Windows host for containers started with
+    /// `docker run` and `docker create`. Compose services need
+    /// `extra_hosts: ["host.docker.internal:host-gateway"]` of their own. Off by default, as
+    /// the address is looked up in the distro for every such command.
+    pub host_docker_internal: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsTarget {
+    /// A static `/etc/resolv.conf` in the distro, which containers inherit, with the one
+    /// WSL generates turned off.
+    Distro,
+    /// `dns` of `daemon.json`, for containers and builds only.
+    Daemon,
+    #[default]
+    Both,
+}
+
+impl DnsTarget {
+    pub fn distro(self) -> bool {
+        matches!(self, Self::Distro | Self::Both)
+    }
+
+    pub fn daemon(self) -> bool {
+        matches!(self, Self::Daemon | Self::Both)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    /// Progress of long-running work such as provisioning the distro.
+    #[default]
+    Normal,
+    /// Also every command run on the way, for debugging.
+    Verbose,
+}
+
+impl FromStr for Verbosity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "quiet" => Ok(Self::Quiet),
+            "normal" => Ok(Self::Normal),
+            "verbose" => Ok(Self::Verbose),
+            _ => bail!("invalid verbosity '{s}' (expected quiet, normal or verbose)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStyle {
+    /// Output is passed through untouched.
+    #[default]
+    Off,
+    /// `/mnt/c/x` becomes `C:\x`; other paths are left alone.
+    Windows,
+    /// Like `Windows`, and paths inside the distro become `\\wsl$\<distro>\...`.
+    Unc,
+}
+
+impl FromStr for PathStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "windows" => Ok(Self::Windows),
+            "unc" => Ok(Self::Unc),
+            _ => bail!("invalid path style '{s}' (expected off, windows or unc)"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            path: None,
+            skip_disk_check: false,
+            distro_name: DEFAULT_DISTRO_NAME.to_string(),
+            distro_user: DEFAULT_DISTRO_USER.to_string(),
+            flavor: Flavor::default(),
+            arch: None,
+            ubuntu_release: DEFAULT_UBUNTU_RELEASE.to_string(),
+            rootfs_url: None,
+            rootfs_sha256: None,
+            rootfs_file: None,
+            keep_rootfs: true,
+            docker_version: None,
+            engine_debs: None,
+            storage_dir: home_dir().join("wsl-distros"),
+            client: ClientConfig::default(),
+            daemon: DaemonConfig::default(),
+            agent: AgentConfig::default(),
+            output: OutputConfig::default(),
+            network: NetworkConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            detach_keys: DEFAULT_DETACH_KEYS.to_string(),
+            windows_credentials: true,
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time: "03:00".to_string(),
+            distro_updates: true,
+            engine_updates: true,
+        }
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_timeout: 15 * 60,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            ca_file: None,
+            mtu: None,
+            dns: vec![],
+            dns_target: DnsTarget::default(),
+            lan_ports: false,
+            lan_firewall: false,
+            lan_firewall_scope: "localsubnet".into(),
+            host_docker_internal: false,
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            buildkit: true,
+            start_timeout: 30,
+            alive_cache: 10,
+            auto_repair: false,
+            rootless: false,
+            systemd: true,
+            idle_shutdown: 0,
+            registry_mirrors: vec![],
+            insecure_registries: vec![],
+            log_driver: "json-file".into(),
+            log_max_size: "10m".into(),
+            log_max_file: 3,
+            address_pools: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location when `path` is `None`,
+    /// taking the settings of `profile` if given.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let given = path.map(Path::to_path_buf);
+        let path = path.map_or_else(config_file_path, Path::to_path_buf);
+        let table = read_table(&path)?;
+        let env_profile = env_var("DOCKER_WRAPPER_PROFILE");
+        let mut config = Self::from_table(table, profile.or(env_profile.as_deref()))
+            .with_context(|| format!("failed to load config '{}'", path.display()))?;
+        config.path = given;
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Builds the config of `profile`, or of the profile the table selects itself, by laying
+    /// the profile's keys over the top-level ones. A profile without a `distro_name` gets one
+    /// of its own derived from the top-level name. `default` names the top-level settings.
+    fn from_table(mut table: toml::Table, profile: Option<&str>) -> Result<Self> {
+        let mut profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => bail!("'profiles' must be a table"),
+            None => toml::Table::new(),
+        };
+        let selected = match table.remove("profile") {
+            Some(toml::Value::String(name)) => Some(name),
+            Some(_) => bail!("'profile' must be a string"),
+            None => None,
+        };
+        let profile = profile
+            .map(str::to_string)
+            .or(selected)
+            .filter(|name| name != "default");
+
+        if let Some(name) = &profile {
+            let overrides = match profiles.remove(name) {
+                Some(toml::Value::Table(overrides)) => overrides,
+                Some(_) => bail!("profile '{name}' must be a table"),
+                None => bail!("no profile '{name}' in the config"),
+            };
+            if !overrides.contains_key("distro_name") {
+                let base = table
+                    .get("distro_name")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or(DEFAULT_DISTRO_NAME);
+                table.insert("distro_name".into(), format!("{base}-{name}").into());
+            }
+            merge_tables(&mut table, overrides);
+        }
+
+        let mut config: Self = toml::Value::Table(table).try_into()?;
+        config.profile = profile;
+
+        Ok(config)
+    }
+
+    /// Sets the value at `keys` in the config file at `path`, or at the default location when
+    /// `path` is `None`, within `[profiles.<profile>]` if a profile is given. The file is only
+    /// written when the result is a valid config, and keeps its comments and layout.
+    pub fn set_in_file(
+        path: Option<&Path>,
+        profile: Option<&str>,
+        keys: &[&str],
+        value: impl Into<toml::Value>,
+    ) -> Result<()> {
+        let path = path.map_or_else(config_file_path, Path::to_path_buf);
+        let mut document = read_document(&path)?;
+        let profile = profile.filter(|name| *name != "default");
+        set_in_document(&mut document, profile, keys, value.into())
+            .with_context(|| format!("failed to update config '{}'", path.display()))?;
+        write_document(&path, &document, profile)
+    }
+
+    /// Adds `[profiles.<to>]` to the config file with the settings of `from`, or the top-level
+    /// ones when `from` is `None`, but a distro of its own.
+    pub fn copy_profile(path: Option<&Path>, from: Option<&str>, to: &str) -> Result<()> {
+        let path = path.map_or_else(config_file_path, Path::to_path_buf);
+        let mut document = read_document(&path)?;
+        copy_profile_in_document(&mut document, from, to)?;
+        write_document(&path, &document, Some(to))
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<()> {
+        if let Some(name) = env_var("DOCKER_WRAPPER_DISTRO_NAME") {
+            self.distro_name = name;
+        }
+        if let Some(user) = env_var("DOCKER_WRAPPER_DISTRO_USER") {
+            self.distro_user = user;
+        }
+        if let Some(flavor) = env_var("DOCKER_WRAPPER_FLAVOR") {
+            self.flavor = flavor.parse()?;
+        }
+        if let Some(arch) = env_var("DOCKER_WRAPPER_ARCH") {
+            self.arch = Some(arch.parse()?);
+        }
+        if let Some(release) = env_var("DOCKER_WRAPPER_UBUNTU_RELEASE") {
+            self.ubuntu_release = release;
+        }
+        if let Some(url) = env_var("DOCKER_WRAPPER_ROOTFS_URL") {
+            self.rootfs_url = Some(url);
+        }
+        if let Some(hash) = env_var("DOCKER_WRAPPER_ROOTFS_SHA256") {
+            self.rootfs_sha256 = Some(hash);
+        }
+        if let Some(file) = env_var("DOCKER_WRAPPER_ROOTFS_FILE") {
+            self.rootfs_file = Some(PathBuf::from(file));
+        }
+        if let Some(keep) = env_var("DOCKER_WRAPPER_KEEP_ROOTFS") {
+            self.keep_rootfs = parse_bool("DOCKER_WRAPPER_KEEP_ROOTFS", &keep)?;
+        }
+        if let Some(skip) = env_var("DOCKER_WRAPPER_SKIP_DISK_CHECK") {
+            self.skip_disk_check = parse_bool("DOCKER_WRAPPER_SKIP_DISK_CHECK", &skip)?;
+        }
+        if let Some(version) = env_var("DOCKER_WRAPPER_DOCKER_VERSION") {
+            self.docker_version = Some(version);
+        }
+        if let Some(dir) = env_var("DOCKER_WRAPPER_ENGINE_DEBS") {
+            self.engine_debs = Some(PathBuf::from(dir));
+        }
+        if let Some(dir) = env_var("DOCKER_WRAPPER_STORAGE_DIR") {
+            self.storage_dir = PathBuf::from(dir);
+        }
+        if let Some(keys) = env_var("DOCKER_WRAPPER_DETACH_KEYS") {
+            self.client.detach_keys = keys;
+        }
+        if let Some(buildkit) = env_var("DOCKER_WRAPPER_BUILDKIT") {
+            self.daemon.buildkit = parse_bool("DOCKER_WRAPPER_BUILDKIT", &buildkit)?;
+        }
+        if let Some(timeout) = env_var("DOCKER_WRAPPER_START_TIMEOUT") {
+            self.daemon.start_timeout = timeout.parse().with_context(|| {
+                format!("invalid timeout '{timeout}' in DOCKER_WRAPPER_START_TIMEOUT")
+            })?;
+        }
+        if let Some(minutes) = env_var("DOCKER_WRAPPER_IDLE_SHUTDOWN") {
+            self.daemon.idle_shutdown = minutes.parse().with_context(|| {
+                format!("invalid minutes '{minutes}' in DOCKER_WRAPPER_IDLE_SHUTDOWN")
+            })?;
+        }
+        if let Some(repair) = env_var("DOCKER_WRAPPER_AUTO_REPAIR") {
+            self.daemon.auto_repair = parse_bool("DOCKER_WRAPPER_AUTO_REPAIR", &repair)?;
+        }
+        if let Some(rootless) = env_var("DOCKER_WRAPPER_ROOTLESS") {
+            self.daemon.rootless = parse_bool("DOCKER_WRAPPER_ROOTLESS", &rootless)?;
+        }
+        if let Some(systemd) = env_var("DOCKER_WRAPPER_SYSTEMD") {
+            self.daemon.systemd = parse_bool("DOCKER_WRAPPER_SYSTEMD", &systemd)?;
+        }
+        if let Some(enabled) = env_var("DOCKER_WRAPPER_AGENT") {
+            self.agent.enabled = parse_bool("DOCKER_WRAPPER_AGENT", &enabled)?;
+        }
+        if let Some(style) = env_var("DOCKER_WRAPPER_TRANSLATE_PATHS") {
+            self.output.translate_paths = style.parse()?;
+        }
+        if let Some(verbosity) = env_var("DOCKER_WRAPPER_VERBOSITY") {
+            self.output.verbosity = verbosity.parse()?;
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        validate_detach_keys(&self.client.detach_keys)?;
+        let user = &self.distro_user;
+        ensure!(
+            user != "root"
+                && user.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && user
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-".contains(c)),
+            "invalid distro_user '{user}' (expected a non-root user name such as 'dockeruser')"
+        );
+        if let Some(hash) = &self.rootfs_sha256 {
+            ensure!(
+                hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+                "invalid rootfs_sha256 '{hash}' (expected 64 hex digits)"
+            );
+        }
+        let time = &self.maintenance.time;
+        let valid_time = time.split_once(':').is_some_and(|(hours, minutes)| {
+            hours.len() == 2
+                && minutes.len() == 2
+                && hours.parse::<u8>().is_ok_and(|hours| hours < 24)
+                && minutes.parse::<u8>().is_ok_and(|minutes| minutes < 60)
+        });
+        ensure!(
+            valid_time,
+            "invalid maintenance.time '{time}' (expected HH:MM, e.g. '03:00')"
+        );
+        for mirror in &self.daemon.registry_mirrors {
+            ensure!(
+                mirror.starts_with("https://") || mirror.starts_with("http://"),
+                "invalid registry mirror '{mirror}' (expected a URL such as 'https://mirror.corp')"
+            );
+        }
+        let size = &self.daemon.log_max_size;
+        let digits = size.strip_suffix(['k', 'm', 'g']).unwrap_or(size);
+        ensure!(
+            size.is_empty() || (!digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())),
+            "invalid daemon.log_max_size '{size}' (expected a size such as '10m')"
+        );
+        ensure!(
+            self.daemon.log_max_file > 0,
+            "daemon.log_max_file must be at least 1"
+        );
+        for pool in &self.daemon.address_pools {
+            let prefix = parse_cidr(&pool.base).map(|(_, prefix)| prefix);
+            ensure!(
+                prefix.is_some_and(|prefix| prefix <= pool.size && pool.size <= 30),
+                "invalid address pool {}/{} (expected a CIDR base such as '10.200.0.0/16' \
+                 and a size between its prefix and 30)",
+                pool.base,
+                pool.size
+            );
+        }
+        for server in &self.network.dns {
+            ensure!(
+                server.parse::<IpAddr>().is_ok(),
+                "invalid DNS server '{server}' (expected an IP address such as '8.8.8.8')"
+            );
+        }
+        let scope = &self.network.lan_firewall_scope;
+        ensure!(
+            !scope.is_empty() && !scope.contains(char::is_whitespace),
+            "invalid network.lan_firewall_scope '{scope}' (expected e.g. 'localsubnet' or \
+             '192.168.1.0/24')"
+        );
+        if let Some(mtu) = self.network.mtu {
+            ensure!(
+                mtu >= MIN_MTU,
+                "invalid network.mtu {mtu} (expected at least {MIN_MTU})"
+            );
+        }
+        if let Some(version) = &self.docker_version {
+            ensure!(
+                !version.is_empty()
+                    && version.split('.').all(|part| {
+                        !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())
+                    }),
+                "invalid docker_version '{version}' (e.g. '27.3' or '27.3.1')"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Directory where the rootfs tarball and the imported distro of the docker host live.
+    pub fn distro_dir(&self) -> PathBuf {
+        self.storage_dir.join(&self.distro_name)
+    }
+
+    /// Checks that a distro can be imported under `storage_dir`, creating it if needed. WSL
+    /// keeps the disk image open for as long as the distro runs, so it has to be on a local
+    /// drive rather than a network share.
+    pub fn validate_storage_dir(&self) -> Result<()> {
+        let dir = &self.storage_dir;
+        ensure!(
+            dir.is_absolute(),
+            "storage_dir '{}' must be an absolute path",
+            dir.display()
+        );
+        ensure!(
+            !dir.to_string_lossy().starts_with("\\\\"),
+            "storage_dir '{}' must be on a local drive, not a network share",
+            dir.display()
+        );
+
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create storage_dir '{}'", dir.display()))?;
+        let probe = dir.join(".docker-wrapper-probe");
+        fs::write(&probe, "")
+            .with_context(|| format!("storage_dir '{}' is not writable", dir.display()))?;
+        let _ = fs::remove_file(probe);
+
+        Ok(())
+    }
+}
+
+/// Reads the config file at `path` as a plain table, empty if there is none.
+fn read_table(path: &Path) -> Result<toml::Table> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config '{}'", path.display()))?;
+    content
+        .parse()
+        .with_context(|| format!("failed to parse config '{}'", path.display()))
+}
+
+/// Reads the config file at `path` for editing in place, empty if there is none.
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config '{}'", path.display()))?;
+    content
+        .parse()
+        .with_context(|| format!("failed to parse config '{}'", path.display()))
+}
+
+/// Writes `document` to `path` if it is a valid config with `profile` selected.
+fn write_document(path: &Path, document: &DocumentMut, profile: Option<&str>) -> Result<()> {
+    let content = document.to_string();
+    Config::from_table(content.parse()?, profile)?.validate()?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content).with_context(|| format!("failed to write config '{}'", path.display()))
+}
+
+fn set_in_document(
+    document: &mut DocumentMut,
+    profile: Option<&str>,
+    keys: &[&str],
+    value: toml::Value,
+) -> Result<()> {
+    let prefix = match profile {
+        Some(name) => vec!["profiles", name],
+        None => vec![],
+    };
+    let (last, parents) = keys.split_last().context("empty config key")?;
+    let mut current: &mut dyn TableLike = document.as_table_mut();
+    for key in prefix.iter().chain(parents) {
+        current = current
+            .entry(key)
+            .or_insert_with(|| {
+                // `[profiles.x]` goes without an empty `[profiles]` above it.
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                table.into()
+            })
+            .as_table_like_mut()
+            .with_context(|| format!("'{key}' is not a table"))?;
+    }
+    let value: toml_edit::Value = value.to_string().parse()?;
+    match current.get_mut(last).and_then(Item::as_value_mut) {
+        // Keep the comment after the old value.
+        Some(old) => {
+            let decor = old.decor().clone();
+            *old = value;
+            *old.decor_mut() = decor;
+        }
+        None => {
+            current.insert(last, value.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_profile_in_document(
+    document: &mut DocumentMut,
+    from: Option<&str>,
+    to: &str,
+) -> Result<()> {
+    let profiles = document
+        .entry("profiles")
+        .or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            table.into()
+        })
+        .as_table_like_mut()
+        .context("'profiles' must be a table")?;
+    ensure!(
+        to != "default" && !profiles.contains_key(to),
+        "profile '{to}' exists already"
+    );
+
+    let mut settings = match from.filter(|name| *name != "default") {
+        Some(name) => match profiles.get(name) {
+            Some(Item::Table(table)) => table.clone(),
+            Some(Item::Value(toml_edit::Value::InlineTable(table))) => table.clone().into_table(),
+            _ => bail!("no profile '{name}' in the config"),
+        },
+        None => toml_edit::Table::new(),
+    };
+    settings.remove("distro_name");
+    // The copy goes after the other profiles, without the comments of the original.
+    clear_positions(&mut settings);
+    settings.decor_mut().clear();
+    profiles.insert(to, settings.into());
+
+    Ok(())
+}
+
+fn clear_positions(table: &mut toml_edit::Table) {
+    table.set_position(None);
+    for (_, item) in table.iter_mut() {
+        if let Item::Table(table) = item {
+            clear_positions(table);
+        }
+    }
+}
+
+/// Lays `overrides` over `base`, merging nested tables key by key.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => {
+                merge_tables(base, value)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+pub fn home_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| panic!("critical error: failed to get home directory"))
+}
+
+/// Directory for state the wrapper keeps for itself, such as its logs.
+pub fn data_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| home_dir().join(".local").join("share"))
+        .join("docker-wrapper")
+}
+
+pub fn config_file_path() -> PathBuf {
+    match env_var("DOCKER_WRAPPER_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => config_dir().join("config.toml"),
+    }
+}
+
+fn config_dir() -> PathBuf {
+    home_dir().join(".config").join("docker-wrapper")
+}
+
+/// Checks a detach key sequence the way docker does: comma-separated keys that are either a
+/// single character or `ctrl-` followed by a letter or one of `@[\]^_`.
+fn validate_detach_keys(keys: &str) -> Result<()> {
+    for key in keys.split(',') {
+        let valid = match key.strip_prefix("ctrl-") {
+            Some(ctrl) => {
+                ctrl.len() == 1
+                    && ctrl
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || "@[\\]^_".contains(c))
+            }
+            None => key.chars().count() == 1,
+        };
+        ensure!(
+            valid,
+            "invalid detach keys '{keys}' (e.g. 'ctrl-^' or 'ctrl-p,ctrl-q')"
+        );
+    }
+
+    Ok(())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match &*value.to_ascii_lowercase() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => bail!("invalid boolean '{value}' in {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# Docker host for everyday work.
+distro_name = "docker-host"
+
+[daemon]
+rootless = false # the default
+alive_cache = 30
+
+[profiles.ci]
+flavor = "debian"
+
+[profiles.ci.daemon]
+rootless = true
+"#;
+
+    fn config(content: &str, profile: Option<&str>) -> Result<Config> {
+        Config::from_table(content.parse()?, profile)
+    }
+
+    #[test]
+    fn profiles_are_laid_over_the_top_level() {
+        let top = config(CONFIG, None).unwrap();
+        assert_eq!(top.distro_name, "docker-host");
+        assert_eq!(top.profile, None);
+        assert!(!top.daemon.rootless);
+
+        let ci = config(CONFIG, Some("ci")).unwrap();
+        assert_eq!(ci.profile.as_deref(), Some("ci"));
+        assert_eq!(ci.distro_name, "docker-host-ci");
+        assert_eq!(ci.flavor, Flavor::Debian);
+        assert!(ci.daemon.rootless);
+        assert_eq!(ci.daemon.alive_cache, 30);
+
+        let selected = format!("profile = \"ci\"\n{CONFIG}");
+        assert_eq!(
+            config(&selected, None).unwrap().profile.as_deref(),
+            Some("ci")
+        );
+        assert_eq!(config(&selected, Some("default")).unwrap().profile, None);
+        assert!(config(CONFIG, Some("missing")).is_err());
+    }
+
+    #[test]
+    fn values_are_set_in_place() {
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+        set_in_document(&mut document, None, &["daemon", "rootless"], true.into()).unwrap();
+        set_in_document(&mut document, Some("ci"), &["flavor"], "fedora".into()).unwrap();
+        set_in_document(
+            &mut document,
+            Some("dev"),
+            &["network", "lan_ports"],
+            true.into(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            r#"# Docker host for everyday work.
+distro_name = "docker-host"
+
+[daemon]
+rootless = true # the default
+alive_cache = 30
+
+[profiles.ci]
+flavor = "fedora"
+
+[profiles.ci.daemon]
+rootless = true
+
+[profiles.dev.network]
+lan_ports = true
+"#
+        );
+        assert!(set_in_document(&mut document, None, &["distro_name", "x"], true.into()).is_err());
+    }
+
+    #[test]
+    fn copied_profiles_get_a_distro_of_their_own() {
+        let mut document: DocumentMut =
+            format!("{CONFIG}\n[profiles.ci.network]\nlan_ports = true\n")
+                .parse()
+                .unwrap();
+        set_in_document(
+            &mut document,
+            Some("ci"),
+            &["distro_name"],
+            "ci-host".into(),
+        )
+        .unwrap();
+        copy_profile_in_document(&mut document, Some("ci"), "ci2").unwrap();
+        assert!(copy_profile_in_document(&mut document, None, "ci").is_err());
+
+        let copy = config(&document.to_string(), Some("ci2")).unwrap();
+        assert_eq!(copy.distro_name, "docker-host-ci2");
+        assert_eq!(copy.flavor, Flavor::Debian);
+        assert!(copy.daemon.rootless);
+        assert!(copy.network.lan_ports);
+        assert!(document
+            .to_string()
+            .starts_with("# Docker host for everyday work.\n"));
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(config(CONFIG, None).unwrap().validate().is_ok());
+        for invalid in [
+            "distro_user = \"root\"",
+            "distro_user = \"Docker User\"",
+            "rootfs_sha256 = \"abc\"",
+            "[maintenance]\ntime = \"25:00\"",
+            "[daemon]\nregistry_mirrors = [\"mirror.example.com\"]",
+        ] {
+            let config = config(invalid, None).unwrap();
+            assert!(config.validate().is_err(), "{invalid}");
+        }
+        assert!(config("no_such_key = 1", None).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Console handling around the wrapped docker process.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often the console size is checked during interactive sessions.
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Keeps the wrapper alive on Ctrl+C and Ctrl+Break while it lives.
+///
+/// The console delivers these events to every process attached to it, so `wsl.exe` still
+/// receives them and forwards SIGINT to docker inside the distro. The wrapper only has to
+/// not die on them itself, and then waits for docker to finish its own shutdown (stopping
+/// the container, `--rm` cleanup) before exiting with docker's exit code.
+pub struct CtrlCGuard(());
+
+impl CtrlCGuard {
+    pub fn install() -> Self {
+        #[cfg(windows)]
+        // SAFETY: the handler is a plain function that touches no state.
+        unsafe {
+            windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(imp::ignore_ctrl_c), 1);
+        }
+
+        Self(())
+    }
+}
+
+impl Drop for CtrlCGuard {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        // SAFETY: removes the handler registered in `install`.
+        unsafe {
+            windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(imp::ignore_ctrl_c), 0);
+        }
+    }
+}
+
+/// Puts the console into raw VT mode while it lives, restoring the previous modes on drop.
+///
+/// `wsl.exe` already gives an interactive session a pseudo-terminal inside the distro, but the
+/// Windows console in front of it still cooks the input: it echoes, edits lines itself and
+/// turns arrow keys and Ctrl sequences into console events that never reach the container.
+/// In raw VT mode keystrokes are passed on as the escape sequences a Linux terminal would
+/// send, and Ctrl+C reaches the container as a keystroke, the way native docker behaves.
+pub struct RawConsole {
+    #[cfg(windows)]
+    saved: imp::SavedModes,
+}
+
+impl RawConsole {
+    /// Switches to raw VT mode, or returns `None` when stdin or stdout is not a console.
+    #[cfg(windows)]
+    pub fn enable() -> Option<Self> {
+        imp::enable_raw_mode().map(|saved| Self { saved })
+    }
+
+    #[cfg(not(windows))]
+    pub fn enable() -> Option<Self> {
+        None
+    }
+}
+
+impl Drop for RawConsole {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        imp::restore_modes(&self.saved);
+    }
+}
+
+/// Watches the console for size changes while it lives, reporting each new size as
+/// `(rows, columns)`.
+pub struct ResizeWatcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl ResizeWatcher {
+    /// Starts watching, or returns `None` when stdout is not a console whose size is known.
+    pub fn spawn(mut on_resize: impl FnMut(u16, u16) + Send + 'static) -> Option<Self> {
+        let mut size = terminal_size()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(RESIZE_POLL_INTERVAL);
+                let Some(new_size) = terminal_size() else {
+                    continue;
+                };
+                if new_size != size {
+                    size = new_size;
+                    on_resize(size.0, size.1);
+                }
+            }
+        });
+
+        Some(Self { stop })
+    }
+}
+
+impl Drop for ResizeWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Size of the visible console window as `(rows, columns)`.
+#[cfg(windows)]
+pub fn terminal_size() -> Option<(u16, u16)> {
+    imp::terminal_size()
+}
+
+#[cfg(not(windows))]
+pub fn terminal_size() -> Option<(u16, u16)> {
+    None
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::mem::MaybeUninit;
+    use windows_sys::{
+        core::BOOL,
+        Win32::{
+            Foundation::HANDLE,
+            System::Console::{
+                GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, SetConsoleMode,
+                CONSOLE_MODE, CTRL_BREAK_EVENT, CTRL_C_EVENT, DISABLE_NEWLINE_AUTO_RETURN,
+                ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+                ENABLE_VIRTUAL_TERMINAL_INPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+                STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+            },
+        },
+    };
+
+    pub struct SavedModes {
+        input: (HANDLE, CONSOLE_MODE),
+        output: (HANDLE, CONSOLE_MODE),
+    }
+
+    pub fn enable_raw_mode() -> Option<SavedModes> {
+        let input = console_mode(STD_INPUT_HANDLE)?;
+        let output = console_mode(STD_OUTPUT_HANDLE)?;
+
+        let raw_input = (input.1
+            & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT))
+            | ENABLE_VIRTUAL_TERMINAL_INPUT;
+        let vt_output = output.1 | ENABLE_VIRTUAL_TERMINAL_PROCESSING | DISABLE_NEWLINE_AUTO_RETURN;
+        // SAFETY: both handles were just confirmed to be console handles.
+        unsafe {
+            if SetConsoleMode(input.0, raw_input) == 0 {
+                return None;
+            }
+            if SetConsoleMode(output.0, vt_output) == 0 {
+                SetConsoleMode(input.0, input.1);
+                return None;
+            }
+        }
+
+        Some(SavedModes { input, output })
+    }
+
+    pub fn restore_modes(saved: &SavedModes) {
+        // SAFETY: restores modes read from these handles in `enable_raw_mode`.
+        unsafe {
+            SetConsoleMode(saved.input.0, saved.input.1);
+            SetConsoleMode(saved.output.0, saved.output.1);
+        }
+    }
+
+    fn console_mode(std_handle: u32) -> Option<(HANDLE, CONSOLE_MODE)> {
+        let mut mode = 0;
+        // SAFETY: `GetConsoleMode` fails cleanly for handles that are not consoles.
+        unsafe {
+            let handle = GetStdHandle(std_handle);
+            (GetConsoleMode(handle, &mut mode) != 0).then_some((handle, mode))
+        }
+    }
+
+    pub fn terminal_size() -> Option<(u16, u16)> {
+        let mut info = MaybeUninit::uninit();
+        // SAFETY: `info` is only read after the call reports that it filled it in.
+        let info = unsafe {
+            let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+            if GetConsoleScreenBufferInfo(handle, info.as_mut_ptr()) == 0 {
+                return None;
+            }
+            info.assume_init()
+        };
+
+        let window = info.srWindow;
+        let rows = u16::try_from(window.Bottom - window.Top + 1).ok()?;
+        let columns = u16::try_from(window.Right - window.Left + 1).ok()?;
+        Some((rows, columns))
+    }
+
+    pub unsafe extern "system" fn ignore_ctrl_c(ctrl_type: u32) -> BOOL {
+        // Returning TRUE marks the event as handled so the default handler does not terminate
+        // the wrapper. Closing the console window and logoff still end the process as usual.
+        matches!(ctrl_type, CTRL_C_EVENT | CTRL_BREAK_EVENT).into()
+    }
+}
@@ -0,0 +1,123 @@
+//! `docker docker-wrapper context create`: registers an exposed endpoint as a context of the
+//! official Windows docker CLI, so that it can talk to the daemon without the wrapper.
+//!
+//! The CLI keeps contexts in `~/.docker/contexts`, each in a directory named after the
+//! SHA-256 of the context name, with `meta.json` describing the endpoint and the TLS files
+//! next to it under `tls/`.
+
+use crate::{
+    config::{home_dir, Config},
+    expose::tls_cert_dir,
+};
+use anyhow::{ensure, Context, Result};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{env, fs, path::PathBuf};
+
+/// Context created when no name is given.
+pub const DEFAULT_CONTEXT_NAME: &str = "wsl-wrapper";
+
+/// Description of the contexts the wrapper creates, by which they are told apart.
+const DESCRIPTION: &str = "docker daemon in WSL, served by docker-wrapper";
+
+/// Where the docker CLI reads a context from.
+pub enum Endpoint {
+    Pipe(String),
+    Tcp { port: u16, tls: bool },
+}
+
+/// Creates the context `name`, replacing an existing one of the same name.
+pub fn create(config: &Config, name: &str, endpoint: &Endpoint) -> Result<()> {
+    let id = context_id(name);
+    let contexts_dir = docker_config_dir().join("contexts");
+    let meta_dir = contexts_dir.join("meta").join(&id);
+    let tls_dir = contexts_dir.join("tls").join(&id).join("docker");
+
+    let host = match endpoint {
+        Endpoint::Pipe(pipe) => format!("npipe:////./pipe/{pipe}"),
+        Endpoint::Tcp { port, .. } => format!("tcp://localhost:{port}"),
+    };
+    let meta = json!({
+        "Name": name,
+        "Metadata": { "Description": DESCRIPTION },
+        "Endpoints": { "docker": { "Host": host, "SkipTLSVerify": false } },
+    });
+    fs::create_dir_all(&meta_dir)?;
+    fs::write(
+        meta_dir.join("meta.json"),
+        serde_json::to_string_pretty(&meta)?,
+    )
+    .with_context(|| format!("failed to write context '{name}'"))?;
+
+    // Stale certificates would make the CLI attempt TLS on a plain endpoint.
+    let _ = fs::remove_dir_all(&tls_dir);
+    if let Endpoint::Tcp { tls: true, .. } = endpoint {
+        let cert_dir = tls_cert_dir(config);
+        ensure!(
+            cert_dir.join("ca.pem").exists(),
+            "no TLS certificates found; run `docker docker-wrapper expose --tcp <PORT> --tls` first"
+        );
+        fs::create_dir_all(&tls_dir)?;
+        for file in ["ca.pem", "cert.pem", "key.pem"] {
+            fs::copy(cert_dir.join(file), tls_dir.join(file))
+                .with_context(|| format!("failed to copy '{file}' into context '{name}'"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes every context created by the wrapper, returning their names.
+pub fn remove_created() -> Result<Vec<String>> {
+    let contexts_dir = docker_config_dir().join("contexts");
+    let Ok(entries) = fs::read_dir(contexts_dir.join("meta")) else {
+        return Ok(vec![]);
+    };
+
+    let mut removed = vec![];
+    for entry in entries {
+        let meta_dir = entry?.path();
+        let Ok(meta) = fs::read_to_string(meta_dir.join("meta.json")) else {
+            continue;
+        };
+        let meta: serde_json::Value = serde_json::from_str(&meta).unwrap_or_default();
+        if meta["Metadata"]["Description"] != DESCRIPTION {
+            continue;
+        }
+
+        let id = meta_dir.file_name().unwrap_or_default();
+        let _ = fs::remove_dir_all(contexts_dir.join("tls").join(id));
+        fs::remove_dir_all(&meta_dir)?;
+        removed.push(meta["Name"].as_str().unwrap_or_default().to_string());
+    }
+
+    // The CLI refuses to run with a current context that is gone.
+    let cli_config_path = docker_config_dir().join("config.json");
+    if let Ok(content) = fs::read_to_string(&cli_config_path) {
+        let mut cli_config: serde_json::Value = serde_json::from_str(&content)?;
+        let current = cli_config["currentContext"].as_str().unwrap_or_default();
+        if removed.iter().any(|name| name == current) {
+            if let Some(object) = cli_config.as_object_mut() {
+                object.remove("currentContext");
+            }
+            fs::write(&cli_config_path, serde_json::to_string_pretty(&cli_config)?)?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Directory of the docker CLI configuration, honoring `DOCKER_CONFIG` like the CLI does.
+pub fn docker_config_dir() -> PathBuf {
+    env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir().join(".docker"))
+}
+
+/// Name of the directories a context is stored in: the hex SHA-256 of its name.
+fn context_id(name: &str) -> String {
+    Sha256::digest(name.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,192 @@
+//! Registry logins of the Windows docker CLI, copied into the client config of the distro so
+//! that pulls and pushes there are authenticated the same way. Logins kept by a credential
+//! helper such as `wincred` are read through the helper's executable.
+
+use crate::{
+    config::Config,
+    context::docker_config_dir,
+    flags::{first_positional, scan, GLOBAL_FLAGS},
+    state,
+    wsl::{self, read_json_object_in_wsl, run_in_wsl, write_file_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+use tracing::debug;
+
+/// Whether `args` run a command that may talk to a registry.
+pub fn needed(args: &[String]) -> bool {
+    let Some(command_index) = first_positional(&scan(args, GLOBAL_FLAGS, false)) else {
+        return false;
+    };
+
+    matches!(
+        &*args[command_index],
+        "pull"
+            | "push"
+            | "build"
+            | "buildx"
+            | "builder"
+            | "compose"
+            | "run"
+            | "create"
+            | "image"
+            | "container"
+            | "manifest"
+            | "search"
+    )
+}
+
+/// The `auths` of the Windows client config with the logins of its credential helpers filled
+/// in. Registries whose helper fails are left out.
+pub fn windows_auths() -> Result<Map<String, Value>> {
+    let path = docker_config_dir().join("config.json");
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(Map::new());
+    };
+    let client_config: Value = serde_json::from_str(&content)
+        .with_context(|| format!("'{}' is not valid JSON", path.display()))?;
+
+    let inline = client_config["auths"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    let helpers = client_config["credHelpers"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    let store = client_config["credsStore"].as_str();
+    let mut registries: Vec<String> = inline.keys().chain(helpers.keys()).cloned().collect();
+    if let Some(store) = store {
+        if let Some(Value::Object(listed)) = run_helper(store, "list", "") {
+            registries.extend(listed.keys().cloned());
+        }
+    }
+    registries.sort();
+    registries.dedup();
+
+    let mut auths = Map::new();
+    for registry in registries {
+        let entry = inline.get(&registry).and_then(Value::as_object);
+        if let Some(entry) =
+            entry.filter(|entry| entry.contains_key("auth") || entry.contains_key("identitytoken"))
+        {
+            auths.insert(registry, Value::Object(entry.clone()));
+            continue;
+        }
+
+        let helper = helpers.get(&registry).and_then(Value::as_str).or(store);
+        let Some(login) = helper.and_then(|helper| run_helper(helper, "get", &registry)) else {
+            debug!("no login for {registry}");
+            continue;
+        };
+        let username = login["Username"].as_str().unwrap_or_default();
+        let secret = login["Secret"].as_str().unwrap_or_default();
+        // Logins made with a token keep it as the secret of the user `<token>`.
+        let auth = match username {
+            "<token>" => json!({ "identitytoken": secret }),
+            _ => json!({ "auth": base64(format!("{username}:{secret}").as_bytes()) }),
+        };
+        auths.insert(registry, auth);
+    }
+
+    Ok(auths)
+}
+
+/// Runs `docker-credential-<helper> <action>` with `input`, returning its JSON output.
+fn run_helper(helper: &str, action: &str, input: &str) -> Option<Value> {
+    let mut child = Command::new(format!("docker-credential-{helper}"))
+        .arg(action)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(input.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        debug!("docker-credential-{helper} {action} failed");
+        return None;
+    }
+
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// Merges the Windows logins into the client config of the user docker runs as. Only a
+/// digest of the logins copied last is remembered, so that the distro is only touched when
+/// they change.
+pub fn sync(config: &Config) -> Result<()> {
+    let auths = windows_auths()?;
+    if auths.is_empty() {
+        return Ok(());
+    }
+    let digest: String = Sha256::digest(serde_json::to_string(&auths)?.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let marker = state::state_dir().join(format!("{}.credentials", config.distro_name));
+    if fs::read_to_string(&marker).is_ok_and(|copied| copied == digest) {
+        return Ok(());
+    }
+
+    let home = wsl::docker_home(config)?;
+    let path = format!("{home}/.docker/config.json");
+    let mut client_config = read_json_object_in_wsl(config, &path)?;
+    let distro_auths = client_config
+        .entry("auths")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !distro_auths.is_object() {
+        *distro_auths = Value::Object(Map::new());
+    }
+    distro_auths.as_object_mut().unwrap().extend(auths);
+
+    debug!("copying the registry logins of Windows into {path}");
+    let content = serde_json::to_string_pretty(&client_config)?;
+    let own = format!("chmod 600 {path} && chown -R \"$(stat -c %u:%g {home})\" {home}/.docker");
+    ensure!(
+        write_file_in_wsl(config, &path, &content)?
+            && run_in_wsl(config, &["sh", "-c", &own], true)?,
+        "failed to write {path}"
+    );
+
+    fs::create_dir_all(state::state_dir())?;
+    fs::write(marker, digest)?;
+    Ok(())
+}
+
+/// Standard base64 with padding, as used by the `auth` of docker's client config.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+        assert_eq!(base64(b""), "");
+    }
+}
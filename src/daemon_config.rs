@@ -0,0 +1,150 @@
+//! `/etc/docker/daemon.json`, edited key by key so that the settings the wrapper makes and
+//! those made by hand or by other tools live side by side.
+
+use crate::{
+    config::Config,
+    wsl::{output_in_wsl, shell_quote, write_file_in_wsl},
+};
+use anyhow::{bail, ensure, Context, Result};
+use serde_json::{Map, Value};
+
+pub const PATH: &str = "/etc/docker/daemon.json";
+
+/// Reads `daemon.json`; a missing or empty file reads as an empty object. Unlike
+/// `read_json_object_in_wsl`, a malformed file is an error, since writing back over it would
+/// lose what it holds.
+pub fn read(config: &Config) -> Result<Map<String, Value>> {
+    let script = format!("cat {} 2>/dev/null || true", shell_quote(PATH));
+    let content = output_in_wsl(config, &["sh", "-c", &script])?;
+    if content.trim().is_empty() {
+        return Ok(Map::new());
+    }
+
+    match serde_json::from_str(&content).with_context(|| format!("{PATH} is not valid JSON"))? {
+        Value::Object(object) => Ok(object),
+        _ => bail!("{PATH} does not hold a JSON object"),
+    }
+}
+
+pub fn write(config: &Config, daemon_json: &Map<String, Value>) -> Result<()> {
+    let content = serde_json::to_string_pretty(daemon_json)?;
+    ensure!(
+        write_file_in_wsl(config, PATH, &content)?,
+        "failed to write {PATH}"
+    );
+
+    Ok(())
+}
+
+/// Applies `edit` to `daemon.json`, writing it back only if that changed anything. Returns
+/// the content from before, or `None` if the file was left as it was.
+pub fn update(
+    config: &Config,
+    edit: impl FnOnce(&mut Map<String, Value>),
+) -> Result<Option<Map<String, Value>>> {
+    let before = read(config)?;
+    let mut daemon_json = before.clone();
+    edit(&mut daemon_json);
+    if daemon_json == before {
+        return Ok(None);
+    }
+
+    write(config, &daemon_json)?;
+    Ok(Some(before))
+}
+
+/// Value at a dotted `key` such as `log-opts.max-size`.
+pub fn get<'a>(daemon_json: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    let (parents, last) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut object = daemon_json;
+    for parent in parents.split('.').filter(|parent| !parent.is_empty()) {
+        object = object.get(parent)?.as_object()?;
+    }
+
+    object.get(last)
+}
+
+/// Sets a dotted `key`, creating the objects above it and replacing values that are not.
+pub fn set(daemon_json: &mut Map<String, Value>, key: &str, value: Value) {
+    let (parents, last) = key.rsplit_once('.').unwrap_or(("", key));
+    object_mut(daemon_json, parents).insert(last.into(), value);
+}
+
+/// Object at a dotted `key`, made the same way as by `set` when it is not there. An empty
+/// key is `daemon_json` itself.
+pub fn object_mut<'a>(
+    daemon_json: &'a mut Map<String, Value>,
+    key: &str,
+) -> &'a mut Map<String, Value> {
+    let mut object = daemon_json;
+    for parent in key.split('.').filter(|parent| !parent.is_empty()) {
+        let entry = object
+            .entry(parent)
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !entry.is_object() {
+            *entry = Value::Object(Map::new());
+        }
+        object = entry.as_object_mut().unwrap();
+    }
+
+    object
+}
+
+/// Removes a dotted `key`, along with the objects above it it leaves empty. Returns whether
+/// the key was there.
+pub fn unset(daemon_json: &mut Map<String, Value>, key: &str) -> bool {
+    match key.split_once('.') {
+        None => daemon_json.remove(key).is_some(),
+        Some((parent, rest)) => {
+            let Some(Value::Object(object)) = daemon_json.get_mut(parent) else {
+                return false;
+            };
+            let removed = unset(object, rest);
+            if object.is_empty() {
+                daemon_json.remove(parent);
+            }
+            removed
+        }
+    }
+}
+
+/// Value given on the command line: JSON such as `true`, `3` or `["a"]`, or else a string.
+pub fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn dotted_keys_merge_into_nested_objects() {
+        let mut daemon_json = json!({
+            "features": { "buildkit": true },
+            "log-driver": "json-file",
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+
+        set(
+            &mut daemon_json,
+            "features.containerd-snapshotter",
+            json!(true),
+        );
+        set(&mut daemon_json, "log-opts.max-size", parse_value("10m"));
+        assert_eq!(get(&daemon_json, "features.buildkit"), Some(&json!(true)));
+        assert_eq!(get(&daemon_json, "log-opts.max-size"), Some(&json!("10m")));
+
+        assert!(unset(&mut daemon_json, "log-opts.max-size"));
+        assert!(!unset(&mut daemon_json, "log-opts.max-file"));
+        assert_eq!(
+            Value::Object(daemon_json),
+            json!({
+                "features": { "buildkit": true, "containerd-snapshotter": true },
+                "log-driver": "json-file",
+            })
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! The virtual disk of the distro, `root\ext4.vhdx` in the directory it was imported into.
+//! It grows as docker writes to it and never shrinks on its own.
+
+use crate::{
+    config::Config,
+    state,
+    status::format_size,
+    wsl::{output_in_wsl, run, run_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+pub fn vhdx_path(config: &Config) -> PathBuf {
+    match state::installation(&config.distro_name) {
+        Some(installation) if installation.adopted => installation.dir.join("ext4.vhdx"),
+        installation => installation
+            .map_or_else(|| config.distro_dir(), |installation| installation.dir)
+            .join("root")
+            .join("ext4.vhdx"),
+    }
+}
+
+pub fn vhdx_size(config: &Config) -> Option<u64> {
+    fs::metadata(vhdx_path(config)).ok().map(|m| m.len())
+}
+
+/// Compacts the virtual disk at `path`, which no VM may have attached. `Optimize-VHD` comes
+/// with Hyper-V, which not every edition of Windows has, so `diskpart` does it otherwise.
+/// Both take an elevated prompt.
+pub fn compact_vhdx(path: &Path) -> Result<()> {
+    let quoted = path.display().to_string().replace('\'', "''");
+    let script = format!(
+        "if (-not (Get-Command Optimize-VHD -ErrorAction SilentlyContinue)) {{ exit 2 }}; \
+         Optimize-VHD -Path '{quoted}' -Mode Full"
+    );
+    if run(&["powershell", "-NoProfile", "-Command", &script], true)? {
+        return Ok(());
+    }
+    debug!("Optimize-VHD is unavailable or failed, using diskpart");
+
+    let script_path = env::temp_dir().join("docker-wrapper-compact.txt");
+    fs::write(
+        &script_path,
+        format!(
+            "select vdisk file=\"{}\"\r\nattach vdisk readonly\r\ncompact vdisk\r\ndetach vdisk\r\n",
+            path.display()
+        ),
+    )
+    .context("failed to write the diskpart script")?;
+    let compacted = run(
+        &["diskpart", "/s", &script_path.display().to_string()],
+        false,
+    );
+    let _ = fs::remove_file(&script_path);
+    ensure!(
+        compacted?,
+        "failed to compact '{}'; run the command from an elevated terminal",
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Size of the rootfs tarballs kept for the distro, complete or partly downloaded, which are
+/// the files next to the `root` directory holding its disk.
+pub fn rootfs_cache_size(config: &Config) -> u64 {
+    let mut dirs = vec![config.distro_dir()];
+    dirs.extend(
+        state::installation(&config.distro_name)
+            .filter(|installation| !installation.adopted && installation.dir != config.distro_dir())
+            .map(|installation| installation.dir),
+    );
+    dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Prints what takes up space: what docker itself holds, the virtual disk it is stored in and
+/// the room left in it, and the cached rootfs.
+pub fn print_usage(config: &Config) -> Result<()> {
+    ensure!(
+        run_in_wsl(config, &["docker", "system", "df"], false)?,
+        "`docker system df` failed"
+    );
+    println!();
+
+    let vhdx = vhdx_path(config);
+    match vhdx_size(config) {
+        Some(size) => println!("virtual disk: {} ({})", vhdx.display(), format_size(size)),
+        None => println!("virtual disk: {} (not found)", vhdx.display()),
+    }
+    // `df -Pk` prints the size, used and available space in KiB on its second line.
+    let df = output_in_wsl(config, &["df", "-Pk", "/"])?;
+    let fields: Vec<u64> = df
+        .lines()
+        .nth(1)
+        .unwrap_or_default()
+        .split_whitespace()
+        .skip(1)
+        .take(3)
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    if let [size, used, available] = fields[..] {
+        println!(
+            "in the distro: {} used, {} free of {}",
+            format_size(used * 1024),
+            format_size(available * 1024),
+            format_size(size * 1024)
+        );
+    }
+    println!("rootfs cache: {}", format_size(rootfs_cache_size(config)));
+
+    Ok(())
+}
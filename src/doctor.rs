@@ -0,0 +1,369 @@
+//! `docker docker-wrapper doctor`: checks of everything the wrapper depends on, each with a
+//! hint on how to fix it.
+
+use crate::{
+    config::Config,
+    network::{cidrs_overlap, parse_cidr},
+    wsl::{distro_info, output, output_in_wsl},
+};
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Free space below which docker is likely to fail pulling or building images.
+const MIN_FREE_SPACE_KB: u64 = 5 * 1024 * 1024;
+
+/// Difference between the Windows and the distro clock that breaks TLS and token validation.
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+    /// Not checked because something it needs already failed.
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+            Self::Skip => "skip",
+        })
+    }
+}
+
+pub struct Finding {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// How to fix the problem, for anything that did not pass.
+    pub hint: Option<&'static str>,
+}
+
+impl Finding {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        hint: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn skip(name: &'static str) -> Self {
+        Self {
+            name,
+            status: Status::Skip,
+            detail: "depends on a failed check".to_string(),
+            hint: None,
+        }
+    }
+}
+
+/// Runs every check in order. Checks inside the distro are skipped when it is not usable.
+pub fn run_checks(config: &Config) -> Vec<Finding> {
+    let mut findings = vec![check_wsl(), check_virtualization()];
+    let distro = check_distro(config);
+    let distro_ok = distro.status == Status::Pass;
+    findings.push(distro);
+
+    if !distro_ok {
+        for name in [
+            "docker daemon",
+            "disk space",
+            "clock",
+            "network",
+            "address pools",
+        ] {
+            findings.push(Finding::skip(name));
+        }
+        return findings;
+    }
+
+    findings.push(check_daemon(config));
+    findings.push(check_disk_space(config));
+    findings.push(check_clock(config));
+    findings.push(check_network(config));
+    findings.push(check_address_pools(config));
+
+    findings
+}
+
+/// Prints the findings and returns whether none of them failed.
+pub fn report(findings: &[Finding]) -> bool {
+    for finding in findings {
+        println!("[{}] {}: {}", finding.status, finding.name, finding.detail);
+        if let Some(hint) = finding.hint {
+            println!("       hint: {hint}");
+        }
+    }
+
+    findings
+        .iter()
+        .all(|finding| finding.status != Status::Fail)
+}
+
+fn check_wsl() -> Finding {
+    const NAME: &str = "wsl";
+    match output(&["wsl", "--version"]) {
+        Ok(version) => {
+            let version = version
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            Finding::pass(NAME, version)
+        }
+        Err(_) => Finding::problem(
+            NAME,
+            Status::Fail,
+            "`wsl --version` failed; WSL is missing or the inbox version is too old",
+            "run `wsl --install` or `wsl --update` from an elevated terminal",
+        ),
+    }
+}
+
+fn check_virtualization() -> Finding {
+    const NAME: &str = "virtualization";
+    let script = "(Get-CimInstance Win32_ComputerSystem).HypervisorPresent";
+    match output(&["powershell", "-NoProfile", "-Command", script]).map(|s| s.trim().to_string()) {
+        Ok(present) if present.eq_ignore_ascii_case("true") => {
+            Finding::pass(NAME, "hypervisor is running")
+        }
+        Ok(_) => Finding::problem(
+            NAME,
+            Status::Fail,
+            "no hypervisor is running, which WSL 2 requires",
+            "enable virtualization in the firmware settings and the \
+             'Virtual Machine Platform' Windows feature",
+        ),
+        Err(_) => Finding::problem(
+            NAME,
+            Status::Warn,
+            "could not be determined",
+            "check 'Virtualization' on the Performance tab of the Task Manager",
+        ),
+    }
+}
+
+fn check_distro(config: &Config) -> Finding {
+    const NAME: &str = "distro";
+    let entry = distro_info(&config.distro_name);
+
+    match entry {
+        None => Finding::problem(
+            NAME,
+            Status::Fail,
+            format!("'{}' is not registered", config.distro_name),
+            "run any docker command to provision it, or `docker docker-wrapper reset-registration`",
+        ),
+        Some(info) if info.version != "2" => Finding::problem(
+            NAME,
+            Status::Fail,
+            format!("'{}' runs on WSL {}", config.distro_name, info.version),
+            "convert it with `wsl --set-version <distro> 2`",
+        ),
+        Some(info) => Finding::pass(
+            NAME,
+            format!("'{}' is {}", config.distro_name, info.state.to_lowercase()),
+        ),
+    }
+}
+
+fn check_daemon(config: &Config) -> Finding {
+    const NAME: &str = "docker daemon";
+    let args = ["docker", "version", "--format", "{{.Server.Version}}"];
+    match output_in_wsl(config, &args) {
+        Ok(version) => Finding::pass(NAME, format!("engine {} is reachable", version.trim())),
+        Err(_) => Finding::problem(
+            NAME,
+            Status::Fail,
+            "dockerd is not reachable",
+            "start it by running any docker command, or inspect `docker docker-wrapper logs`",
+        ),
+    }
+}
+
+fn check_disk_space(config: &Config) -> Finding {
+    const NAME: &str = "disk space";
+    // The second line of `df -Pk` holds the available kilobytes in the fourth column.
+    let available = output_in_wsl(config, &["df", "-Pk", "/var/lib"])
+        .ok()
+        .and_then(|df| {
+            df.lines()
+                .nth(1)?
+                .split_whitespace()
+                .nth(3)?
+                .parse::<u64>()
+                .ok()
+        });
+
+    match available {
+        Some(kb) if kb >= MIN_FREE_SPACE_KB => {
+            Finding::pass(NAME, format!("{} GiB free", kb / 1024 / 1024))
+        }
+        Some(kb) => Finding::problem(
+            NAME,
+            Status::Warn,
+            format!("only {} MiB free", kb / 1024),
+            "remove unused data with `docker system prune`",
+        ),
+        None => Finding::problem(
+            NAME,
+            Status::Warn,
+            "could not be determined",
+            "check `df -h` inside the distro",
+        ),
+    }
+}
+
+fn check_clock(config: &Config) -> Finding {
+    const NAME: &str = "clock";
+    let host = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64);
+    let distro = output_in_wsl(config, &["date", "+%s"])
+        .ok()
+        .and_then(|date| date.trim().parse::<i64>().ok());
+
+    match distro.map(|distro| (distro - host).abs()) {
+        Some(skew) if skew <= MAX_CLOCK_SKEW_SECS => Finding::pass(NAME, "in sync with Windows"),
+        Some(skew) => Finding::problem(
+            NAME,
+            Status::Fail,
+            format!("the distro is {skew}s off the Windows clock"),
+            "resync it with `wsl --shutdown`, which usually happens after sleep or hibernation",
+        ),
+        None => Finding::problem(
+            NAME,
+            Status::Warn,
+            "could not be determined",
+            "compare `date` inside the distro with the Windows clock",
+        ),
+    }
+}
+
+fn check_network(config: &Config) -> Finding {
+    const NAME: &str = "network";
+    let registry = "registry-1.docker.io";
+    if output_in_wsl(config, &["getent", "hosts", registry]).is_err() {
+        return Finding::problem(
+            NAME,
+            Status::Fail,
+            format!("'{registry}' does not resolve"),
+            "check the nameserver in the distro's /etc/resolv.conf, or VPN and DNS settings",
+        );
+    }
+
+    let url = format!("https://{registry}/v2/");
+    let curl = ["curl", "-sS", "--max-time", "10", "-o", "/dev/null", &url];
+    match output_in_wsl(config, &curl) {
+        Ok(_) => Finding::pass(NAME, format!("'{registry}' is reachable")),
+        Err(_) => Finding::problem(
+            NAME,
+            Status::Fail,
+            format!("'{registry}' resolves but cannot be reached"),
+            "check proxy and firewall settings, and the MTU when on a VPN",
+        ),
+    }
+}
+
+fn check_address_pools(config: &Config) -> Finding {
+    const NAME: &str = "address pools";
+    let script = "docker network inspect \
+                  --format '{{.Name}}{{range .IPAM.Config}} {{.Subnet}}{{end}}' \
+                  $(docker network ls -q)";
+    let networks = match output_in_wsl(config, &["sh", "-c", script]) {
+        Ok(networks) => networks,
+        Err(_) => {
+            return Finding::problem(
+                NAME,
+                Status::Warn,
+                "docker networks could not be listed",
+                "check that the daemon runs with `docker network ls`",
+            )
+        }
+    };
+    let Some(routes) = windows_routes() else {
+        return Finding::problem(
+            NAME,
+            Status::Warn,
+            "the Windows routes could not be read",
+            "compare `docker network inspect` with `route print` on Windows",
+        );
+    };
+
+    let mut overlaps = vec![];
+    for line in networks.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(network) = fields.next() else {
+            continue;
+        };
+        for subnet in fields {
+            // IPv6 subnets and other unparsable entries are not compared.
+            let Some(range) = parse_cidr(subnet) else {
+                continue;
+            };
+            if let Some((_, route)) = routes
+                .iter()
+                .find(|(route, _)| cidrs_overlap(*route, range))
+            {
+                overlaps.push(format!("'{network}' ({subnet}) overlaps route {route}"));
+            }
+        }
+    }
+
+    match overlaps.is_empty() {
+        true => Finding::pass(NAME, "no docker network overlaps a Windows route"),
+        false => Finding::problem(
+            NAME,
+            Status::Warn,
+            overlaps.join(", "),
+            "move docker to free ranges with `[[daemon.address_pools]]` and `bip` in \
+             daemon.json, then recreate the networks",
+        ),
+    }
+}
+
+/// IPv4 routes of Windows, leaving out the default route, its own loopback, multicast and
+/// broadcast ones and the routes to WSL itself, which every docker network sits behind.
+fn windows_routes() -> Option<Vec<((Ipv4Addr, u8), String)>> {
+    let script = "Get-NetRoute -AddressFamily IPv4 \
+                  | Where-Object { $_.InterfaceAlias -notlike 'vEthernet (WSL*' } \
+                  | ForEach-Object { $_.DestinationPrefix }";
+    let routes = output(&["powershell", "-NoProfile", "-Command", script]).ok()?;
+
+    Some(
+        routes
+            .lines()
+            .filter_map(|route| Some((parse_cidr(route)?, route.trim().to_string())))
+            .filter(|((address, prefix), _)| {
+                *prefix > 0
+                    && !address.is_loopback()
+                    && !address.is_multicast()
+                    && !address.is_broadcast()
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,214 @@
+//! HTTP downloads done by the wrapper itself rather than through `curl`, which not every
+//! machine has. Large files resume where an earlier attempt stopped.
+
+use crate::{config::Config, log, network, status::format_size};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+use ureq::{
+    tls::{parse_pem, PemItem, RootCerts, TlsConfig, TlsProvider},
+    Agent, Proxy,
+};
+
+/// Attempts made at a download before giving up on transient errors.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Agent for fetching `url`, through the configured proxy and trusting `network.ca_file`
+/// when one is set, or else the certificate store of the system.
+fn agent(config: &Config, url: &str) -> Result<Agent> {
+    let proxy = match network::Proxy::resolve(config).for_url(url) {
+        Some(proxy) => {
+            debug!("fetching '{url}' through '{proxy}'");
+            Some(Proxy::new(proxy).with_context(|| format!("invalid proxy '{proxy}'"))?)
+        }
+        None => None,
+    };
+    let root_certs = match &config.network.ca_file {
+        Some(ca_file) => {
+            let pem = fs::read(ca_file)
+                .with_context(|| format!("failed to read CA file '{}'", ca_file.display()))?;
+            let mut certs = vec![];
+            for item in parse_pem(&pem) {
+                if let PemItem::Certificate(cert) = item? {
+                    certs.push(cert);
+                }
+            }
+            RootCerts::from(certs)
+        }
+        None => RootCerts::PlatformVerifier,
+    };
+
+    Ok(Agent::config_builder()
+        .tls_config(
+            TlsConfig::builder()
+                .provider(TlsProvider::NativeTls)
+                .root_certs(root_certs)
+                .build(),
+        )
+        .proxy(proxy)
+        .http_status_as_error(false)
+        .timeout_connect(Some(Duration::from_secs(30)))
+        .build()
+        .into())
+}
+
+/// Fetches a small text document such as a checksum list.
+pub fn fetch_text(config: &Config, url: &str) -> Result<String> {
+    debug!("fetching '{url}'");
+    let response = agent(config, url)?.get(url).call()?;
+    if !response.status().is_success() {
+        bail!("fetching '{url}' failed with {}", response.status());
+    }
+
+    Ok(response.into_body().read_to_string()?)
+}
+
+/// Downloads `url` into `path`. Whatever `path` already holds is taken as the beginning of
+/// the file and only the rest is requested; transient errors are retried the same way.
+pub fn fetch_to_file(config: &Config, url: &str, path: &Path) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match fetch_remaining(config, url, path) {
+            Ok(()) => return Ok(()),
+            Err(Failure::Permanent(err)) => return Err(err),
+            Err(Failure::Transient(err)) if attempt < MAX_ATTEMPTS => {
+                let delay = Duration::from_secs(2u64.pow(attempt));
+                warn!("download interrupted ({err:#}); resuming in {delay:?}...");
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(Failure::Transient(err)) => {
+                return Err(err.context(format!("failed to download '{url}'")))
+            }
+        }
+    }
+}
+
+enum Failure {
+    /// Worth another attempt: the connection broke or the server had a hiccup.
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Failure {
+    fn from(err: E) -> Self {
+        Self::Transient(err.into())
+    }
+}
+
+fn fetch_remaining(config: &Config, url: &str, path: &Path) -> Result<(), Failure> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| Failure::Permanent(err.into()))?;
+    let offset = file.metadata()?.len();
+
+    let mut request = agent(config, url).map_err(Failure::Permanent)?.get(url);
+    if offset > 0 {
+        debug!("resuming '{url}' at {offset} bytes");
+        request = request.header("Range", format!("bytes={offset}-"));
+    }
+    let response = request.call()?;
+    let status = response.status().as_u16();
+    match status {
+        // The file is complete already.
+        416 if offset > 0 => return Ok(()),
+        // The server ignored the range, so the file starts over.
+        200 if offset > 0 => file.set_len(0)?,
+        200 | 206 => {}
+        500.. => {
+            return Err(Failure::Transient(anyhow::anyhow!(
+                "server returned {status}"
+            )))
+        }
+        _ => {
+            return Err(Failure::Permanent(anyhow::anyhow!(
+                "downloading '{url}' failed with {status}"
+            )))
+        }
+    }
+
+    let start = if status == 206 { offset } else { 0 };
+    let total = response.body().content_length().map(|len| start + len);
+    let mut body = response.into_body().into_reader();
+    let mut progress = Progress::new(start, total);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = body.read(&mut buf).context("connection lost")?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])
+            .map_err(|err| Failure::Permanent(err.into()))?;
+        progress.advance(n as u64);
+    }
+    progress.finish();
+
+    if let Some(total) = total {
+        if file.metadata()?.len() < total {
+            return Err(Failure::Transient(anyhow::anyhow!(
+                "connection closed early"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Progress line on stderr, redrawn at most a few times per second and only shown on a
+/// terminal when the wrapper is not asked to be quiet.
+struct Progress {
+    done: u64,
+    total: Option<u64>,
+    shown: bool,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    fn new(done: u64, total: Option<u64>) -> Self {
+        Self {
+            done,
+            total,
+            shown: log::show_progress(),
+            last_draw: None,
+        }
+    }
+
+    fn advance(&mut self, n: u64) {
+        self.done += n;
+        if !self.shown
+            || self
+                .last_draw
+                .is_some_and(|t| t.elapsed() < Duration::from_millis(200))
+        {
+            return;
+        }
+
+        self.last_draw = Some(Instant::now());
+        let line = match self.total {
+            Some(total) if total > 0 => format!(
+                "{} / {} ({}%)",
+                format_size(self.done),
+                format_size(total),
+                self.done * 100 / total
+            ),
+            _ => format_size(self.done),
+        };
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r\x1b[2K{line}");
+        let _ = stderr.flush();
+    }
+
+    fn finish(&self) {
+        if self.shown && self.last_draw.is_some() {
+            eprintln!();
+        }
+    }
+}
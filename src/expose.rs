@@ -0,0 +1,405 @@
+//! `docker docker-wrapper expose`: serves the Docker API of the daemon in the distro to
+//! native Windows tools, so that they work without going through the wrapper binary.
+//!
+//! Every client connection is relayed through its own `docker system dial-stdio` in the
+//! distro, which connects its stdin and stdout to the daemon socket. TCP with TLS is served by
+//! the daemon itself instead, since it verifies client certificates natively.
+
+use crate::{
+    config::{data_dir, Config},
+    daemon_config,
+    flavor::SYSTEMD_RUNNING,
+    wsl::{output_in_wsl, run_in_wsl, write_file_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+};
+use tracing::{debug, info, warn};
+
+/// Pipe name the Windows docker CLI and most SDKs connect to by default.
+pub const DEFAULT_PIPE_NAME: &str = "docker_engine";
+
+/// Serves `\\.\pipe\<name>` until the wrapper is interrupted.
+#[cfg(windows)]
+pub fn serve_pipe(config: &Config, name: &str) -> Result<()> {
+    let mut server = pipe::PipeServer::new(name);
+    info!(r"serving the Docker API on \\.\pipe\{name}");
+    loop {
+        let client = server.accept().with_context(|| {
+            format!(r"failed to serve \\.\pipe\{name}; is another engine such as Docker Desktop using it?")
+        })?;
+        debug!("client connected to the pipe");
+
+        let distro_name = config.distro_name.clone();
+        thread::spawn(move || {
+            let (reader, closer) = (client.clone(), client.clone());
+            let result = relay(&distro_name, reader, client, move || closer.disconnect());
+            if let Err(err) = result {
+                debug!("relaying a pipe connection failed: {err:#}");
+            }
+        });
+    }
+}
+
+#[cfg(not(windows))]
+pub fn serve_pipe(_config: &Config, _name: &str) -> Result<()> {
+    anyhow::bail!("named pipes are only available on Windows")
+}
+
+/// Serves plain HTTP on `127.0.0.1:<port>` until the wrapper is interrupted. Anyone able to
+/// connect to the port controls the daemon, which is why it only listens on loopback.
+pub fn serve_tcp(config: &Config, port: u16) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("failed to listen on 127.0.0.1:{port}"))?;
+    info!("serving the Docker API on tcp://127.0.0.1:{port}");
+    warn!("connections are not authenticated; use --tls to require client certificates");
+
+    for client in listener.incoming() {
+        let client = client?;
+        debug!("client connected from {}", client.peer_addr()?);
+
+        let distro_name = config.distro_name.clone();
+        thread::spawn(move || {
+            let result = (|| {
+                let (reader, closer) = (client.try_clone()?, client.try_clone()?);
+                relay(&distro_name, reader, client, move || {
+                    let _ = closer.shutdown(Shutdown::Both);
+                })
+            })();
+            if let Err(err) = result {
+                debug!("relaying a TCP connection failed: {err:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Where the daemon keeps the certificates generated for its TLS listener.
+const TLS_DIR_IN_WSL: &str = "/etc/docker/tls";
+
+/// Generates a CA, a server and a client certificate once, in the layout docker expects in
+/// `DOCKER_CERT_PATH` (`ca.pem`, `cert.pem`, `key.pem`) plus the server's own files.
+const GENERATE_CERTS_SCRIPT: &str = r#"set -e
+dir="$0"
+[ -f "$dir/ca.pem" ] && [ -f "$dir/cert.pem" ] && exit 0
+mkdir -p "$dir" && cd "$dir"
+openssl genrsa -out ca-key.pem 4096 2>/dev/null
+openssl req -new -x509 -days 3650 -sha256 -key ca-key.pem -subj "/CN=docker-wrapper CA" -out ca.pem
+openssl genrsa -out server-key.pem 4096 2>/dev/null
+openssl req -new -key server-key.pem -subj "/CN=localhost" -out server.csr
+printf 'subjectAltName = DNS:localhost,IP:127.0.0.1
+extendedKeyUsage = serverAuth
+' > server.ext
+openssl x509 -req -days 3650 -sha256 -in server.csr -CA ca.pem -CAkey ca-key.pem \
+    -CAcreateserial -extfile server.ext -out server-cert.pem 2>/dev/null
+openssl genrsa -out key.pem 4096 2>/dev/null
+openssl req -new -key key.pem -subj "/CN=client" -out client.csr
+printf 'extendedKeyUsage = clientAuth
+' > client.ext
+openssl x509 -req -days 3650 -sha256 -in client.csr -CA ca.pem -CAkey ca-key.pem \
+    -CAcreateserial -extfile client.ext -out cert.pem 2>/dev/null
+rm -f server.csr server.ext client.csr client.ext
+chmod 0400 ca-key.pem server-key.pem key.pem
+"#;
+
+/// Drop-in making the docker unit start dockerd without `-H fd://`, which dockerd refuses to
+/// combine with `hosts` in `daemon.json`.
+const HOSTS_DROP_IN: &str = "/etc/systemd/system/docker.service.d/docker-wrapper-hosts.conf";
+const HOSTS_DROP_IN_CONTENT: &str = "[Service]
+ExecStart=
+ExecStart=/usr/bin/dockerd --containerd=/run/containerd/containerd.sock";
+
+fn reload_units() -> String {
+    format!("! {SYSTEMD_RUNNING} || systemctl daemon-reload")
+}
+
+/// Makes the daemon serve TLS with client verification on `127.0.0.1:<port>` of the distro,
+/// which WSL forwards to `localhost` on Windows. Returns the directory holding the client
+/// certificates on the Windows side, along with `daemon.json` from before if it changed. The
+/// daemon has to be restarted to pick the change up.
+pub fn enable_tls_listener(
+    config: &Config,
+    port: u16,
+) -> Result<(PathBuf, Option<Map<String, Value>>)> {
+    ensure!(
+        run_in_wsl(
+            config,
+            &["sh", "-c", GENERATE_CERTS_SCRIPT, TLS_DIR_IN_WSL],
+            true
+        )?,
+        "failed to generate TLS certificates"
+    );
+
+    let cert_dir = tls_cert_dir(config);
+    fs::create_dir_all(&cert_dir)?;
+    for file in ["ca.pem", "cert.pem", "key.pem"] {
+        let content = output_in_wsl(config, &["cat", &format!("{TLS_DIR_IN_WSL}/{file}")])?;
+        fs::write(cert_dir.join(file), content)?;
+    }
+
+    ensure!(
+        write_file_in_wsl(config, HOSTS_DROP_IN, HOSTS_DROP_IN_CONTENT)?
+            && run_in_wsl(config, &["sh", "-c", &reload_units()], true)?,
+        "failed to start dockerd without -H under systemd"
+    );
+
+    let before = daemon_config::update(config, |daemon_json| {
+        let hosts = [
+            "unix:///var/run/docker.sock".to_string(),
+            format!("tcp://127.0.0.1:{port}"),
+        ];
+        daemon_json.insert("hosts".into(), hosts.into_iter().collect());
+        daemon_json.insert("tlsverify".into(), true.into());
+        for (key, file) in [
+            ("tlscacert", "ca.pem"),
+            ("tlscert", "server-cert.pem"),
+            ("tlskey", "server-key.pem"),
+        ] {
+            daemon_json.insert(key.into(), format!("{TLS_DIR_IN_WSL}/{file}").into());
+        }
+    })?;
+
+    Ok((cert_dir, before))
+}
+
+/// Directory on the Windows side holding the client certificates for the distro's listener.
+pub fn tls_cert_dir(config: &Config) -> PathBuf {
+    data_dir().join("tls").join(&config.distro_name)
+}
+
+/// Removes the TLS listener again, leaving the daemon on its socket only. The generated
+/// certificates are kept for the next time. Returns `daemon.json` from before if it changed.
+pub fn disable_tls_listener(config: &Config) -> Result<Option<Map<String, Value>>> {
+    let remove = format!(
+        "[ ! -e {HOSTS_DROP_IN} ] || {{ rm -f {HOSTS_DROP_IN} && {}; }}",
+        reload_units()
+    );
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", &remove], true)?,
+        "failed to restore the docker unit"
+    );
+
+    daemon_config::update(config, |daemon_json| {
+        for key in ["hosts", "tlsverify", "tlscacert", "tlscert", "tlskey"] {
+            daemon_json.remove(key);
+        }
+    })
+}
+
+/// Relays a client connection to the daemon until either side closes it. `close` tears the
+/// client connection down, unblocking the thread that is still reading from it.
+fn relay(
+    distro_name: &str,
+    mut from_client: impl Read + Send + 'static,
+    mut to_client: impl Write,
+    close: impl FnOnce(),
+) -> Result<()> {
+    let mut child = Command::new("wsl")
+        .args([
+            "-d",
+            distro_name,
+            "-u",
+            "root",
+            "-e",
+            "docker",
+            "system",
+            "dial-stdio",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut to_daemon = child.stdin.take().expect("stdin is piped");
+    let mut from_daemon = child.stdout.take().expect("stdout is piped");
+
+    // Closing the daemon's stdin once the client is done lets it finish the last response.
+    thread::spawn(move || io::copy(&mut from_client, &mut to_daemon));
+    let result = io::copy(&mut from_daemon, &mut to_client);
+    close();
+    let _ = child.kill();
+    let _ = child.wait();
+
+    result.map(drop).map_err(Into::into)
+}
+
+#[cfg(windows)]
+mod pipe {
+    use std::{
+        io::{self, Read, Write},
+        iter,
+        mem::zeroed,
+        ptr::{null, null_mut},
+        sync::Arc,
+    };
+    use windows_sys::Win32::{
+        Foundation::{
+            CloseHandle, GetLastError, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_PIPE_CONNECTED,
+            HANDLE, INVALID_HANDLE_VALUE,
+        },
+        Storage::FileSystem::{
+            FlushFileBuffers, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE,
+            FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX,
+        },
+        System::{
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+            Threading::CreateEventW,
+            IO::{GetOverlappedResult, OVERLAPPED},
+        },
+    };
+
+    const BUFFER_SIZE: u32 = 64 * 1024;
+
+    /// Creates one pipe instance per client, the way named pipe servers work.
+    pub struct PipeServer {
+        name: Vec<u16>,
+        first: bool,
+    }
+
+    impl PipeServer {
+        pub fn new(name: &str) -> Self {
+            let name = format!(r"\\.\pipe\{name}");
+            Self {
+                name: name.encode_utf16().chain(iter::once(0)).collect(),
+                first: true,
+            }
+        }
+
+        /// Waits for the next client. The first instance fails if someone else already
+        /// serves the pipe, rather than silently sharing it.
+        pub fn accept(&mut self) -> io::Result<Pipe> {
+            let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+            if self.first {
+                open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            }
+
+            // SAFETY: the name is NUL-terminated and outlives the call.
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    self.name.as_ptr(),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    BUFFER_SIZE,
+                    BUFFER_SIZE,
+                    0,
+                    null(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+            self.first = false;
+
+            let pipe = Pipe(Arc::new(Handle(handle)));
+            // SAFETY: the handle was opened for overlapped I/O.
+            match overlapped(handle, |ov| unsafe { ConnectNamedPipe(handle, ov) }) {
+                Ok(_) => Ok(pipe),
+                Err(err) if err.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => Ok(pipe),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    struct Handle(HANDLE);
+
+    // SAFETY: pipe handles may be used from any thread; overlapped I/O allows a read and a
+    // write to be in flight at the same time.
+    unsafe impl Send for Handle {}
+    unsafe impl Sync for Handle {}
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: the handle is owned and closed exactly once.
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+
+    /// A connected pipe instance, shared by the threads reading and writing it.
+    #[derive(Clone)]
+    pub struct Pipe(Arc<Handle>);
+
+    impl Pipe {
+        /// Ends the connection, failing any read still waiting for the client.
+        pub fn disconnect(&self) {
+            // SAFETY: the handle is a connected server end of a pipe.
+            unsafe {
+                FlushFileBuffers(self.0 .0);
+                DisconnectNamedPipe(self.0 .0);
+            }
+        }
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let handle = self.0 .0;
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            // SAFETY: `buf` outlives the operation, which is waited for.
+            let result = overlapped(handle, |ov| unsafe {
+                ReadFile(handle, buf.as_mut_ptr(), len, null_mut(), ov)
+            });
+            match result {
+                Ok(read) => Ok(read as usize),
+                Err(err) if err.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let handle = self.0 .0;
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            // SAFETY: `buf` outlives the operation, which is waited for.
+            let written = overlapped(handle, |ov| unsafe {
+                WriteFile(handle, buf.as_ptr(), len, null_mut(), ov)
+            })?;
+            Ok(written as usize)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Starts an overlapped operation with `start` and waits for it, returning the number of
+    /// bytes transferred.
+    fn overlapped(handle: HANDLE, start: impl FnOnce(*mut OVERLAPPED) -> i32) -> io::Result<u32> {
+        // SAFETY: the event and the `OVERLAPPED` outlive the operation, which is waited for
+        // before either is released.
+        unsafe {
+            let event = CreateEventW(null(), 1, 0, null());
+            if event.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut ov: OVERLAPPED = zeroed();
+            ov.hEvent = event;
+            let result = if start(&mut ov) != 0 || GetLastError() == ERROR_IO_PENDING {
+                let mut transferred = 0;
+                if GetOverlappedResult(handle, &ov, &mut transferred, 1) != 0 {
+                    Ok(transferred)
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            } else {
+                Err(io::Error::last_os_error())
+            };
+            CloseHandle(event);
+
+            result
+        }
+    }
+}
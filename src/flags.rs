@@ -0,0 +1,931 @@
+//! Table-driven model of the docker CLI, just detailed enough for the argument rewriter to
+//! tell flags, flag values and positionals apart the way docker's own parser does.
+
+use ValueKind::{ComposeFile, Exporter, HostPath, Mount, Opaque, Secret, SecurityOpt, Ssh, Volume};
+
+/// What the value of a flag means to the rewriter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Boolean flag that takes no value.
+    None,
+    /// Value that is passed on as it is.
+    Opaque,
+    /// Path on the Windows side.
+    HostPath,
+    /// `--mount` value with a `source=` field.
+    Mount,
+    /// `--secret` value, whose `src=` field is a file on the Windows side.
+    Secret,
+    /// `--ssh` value, whose sockets or keys after the id are on the Windows side.
+    Ssh,
+    /// `--security-opt` value, of which `seccomp=` names a profile on the Windows side.
+    SecurityOpt,
+    /// `--output` and cache value of buildx, whose `dest=` and `src=` fields are paths on
+    /// the Windows side.
+    Exporter,
+    /// `-v` value whose host side may be a Windows path.
+    Volume,
+    /// Compose file, rewritten as a whole by the compose support.
+    ComposeFile,
+    /// Bake file, rewritten as a whole by the bake support.
+    BakeFile,
+    /// `--set` override of bake, whose `context` and `dockerfile` may be Windows paths.
+    BakeSet,
+}
+
+pub struct FlagSpec {
+    pub names: &'static [&'static str],
+    pub value: ValueKind,
+}
+
+/// What the positional arguments of a command mean to the rewriter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Positionals {
+    Opaque,
+    /// The first positional is a local file or directory unless it is a URL or `-`.
+    LocalPath,
+    /// `docker cp` operands, one of which is local and the other in a container.
+    CpOperands,
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Management commands the command is also reachable through (`docker image build`).
+    pub parents: &'static [&'static str],
+    pub flags: &'static [FlagSpec],
+    /// Whether flags may follow positionals. `run`, `create` and `exec` stop parsing at the
+    /// image or container, since everything after it is the command run in the container.
+    pub interspersed: bool,
+    pub positionals: Positionals,
+    /// Whether unmodeled arguments containing backslashes are still tried as Windows paths.
+    pub fallback: bool,
+}
+
+const fn flag(names: &'static [&'static str], value: ValueKind) -> FlagSpec {
+    FlagSpec { names, value }
+}
+
+/// Flags of `docker` itself, given before the subcommand.
+pub static GLOBAL_FLAGS: &[FlagSpec] = &[
+    flag(&["--config"], HostPath),
+    flag(&["-c", "--context"], Opaque),
+    flag(&["-D", "--debug"], ValueKind::None),
+    flag(&["-H", "--host"], Opaque),
+    flag(&["-l", "--log-level"], Opaque),
+    flag(&["--tls"], ValueKind::None),
+    flag(&["--tlscacert"], HostPath),
+    flag(&["--tlscert"], HostPath),
+    flag(&["--tlskey"], HostPath),
+    flag(&["--tlsverify"], ValueKind::None),
+    flag(&["-v", "--version"], ValueKind::None),
+];
+
+/// Management commands whose subcommands are modeled, with the flags they take themselves.
+pub static PARENT_COMMANDS: &[(&str, &[FlagSpec])] = &[
+    ("builder", &[]),
+    ("buildx", &[flag(&["--builder"], Opaque)]),
+    ("config", &[]),
+    ("container", &[]),
+    ("context", &[]),
+    ("image", &[]),
+    ("network", &[]),
+    ("node", &[]),
+    ("plugin", &[]),
+    ("secret", &[]),
+    ("service", &[]),
+    ("volume", &[]),
+];
+
+/// Global flags of `docker compose`, given before the compose subcommand.
+pub static COMPOSE_GLOBAL_FLAGS: &[FlagSpec] = &[
+    flag(&["--all-resources"], ValueKind::None),
+    flag(&["--ansi"], Opaque),
+    flag(&["--compatibility"], ValueKind::None),
+    flag(&["--dry-run"], ValueKind::None),
+    flag(&["--env-file"], HostPath),
+    flag(&["-f", "--file"], ComposeFile),
+    flag(&["--parallel"], Opaque),
+    flag(&["--profile"], Opaque),
+    flag(&["--progress"], Opaque),
+    flag(&["--project-directory"], HostPath),
+    flag(&["-p", "--project-name"], Opaque),
+];
+
+/// Flags shared by `docker run` and `docker create`.
+static CONTAINER_CREATE_FLAGS: &[FlagSpec] = &[
+    flag(&["--add-host"], Opaque),
+    flag(&["--annotation"], Opaque),
+    flag(&["-a", "--attach"], Opaque),
+    flag(&["--blkio-weight"], Opaque),
+    flag(&["--blkio-weight-device"], Opaque),
+    flag(&["--cap-add"], Opaque),
+    flag(&["--cap-drop"], Opaque),
+    flag(&["--cgroup-parent"], Opaque),
+    flag(&["--cgroupns"], Opaque),
+    // The docker CLI inside the distro writes the cidfile itself, so pointing it at the
+    // translated path makes the file land on the Windows filesystem.
+    flag(&["--cidfile"], HostPath),
+    flag(&["--cpu-count"], Opaque),
+    flag(&["--cpu-percent"], Opaque),
+    flag(&["--cpu-period"], Opaque),
+    flag(&["--cpu-quota"], Opaque),
+    flag(&["--cpu-rt-period"], Opaque),
+    flag(&["--cpu-rt-runtime"], Opaque),
+    flag(&["-c", "--cpu-shares"], Opaque),
+    flag(&["--cpus"], Opaque),
+    flag(&["--cpuset-cpus"], Opaque),
+    flag(&["--cpuset-mems"], Opaque),
+    flag(&["-d", "--detach"], ValueKind::None),
+    flag(&["--detach-keys"], Opaque),
+    flag(&["--device"], Opaque),
+    flag(&["--device-cgroup-rule"], Opaque),
+    flag(&["--device-read-bps"], Opaque),
+    flag(&["--device-read-iops"], Opaque),
+    flag(&["--device-write-bps"], Opaque),
+    flag(&["--device-write-iops"], Opaque),
+    flag(&["--disable-content-trust"], ValueKind::None),
+    flag(&["--dns"], Opaque),
+    flag(&["--dns-option"], Opaque),
+    flag(&["--dns-search"], Opaque),
+    flag(&["--domainname"], Opaque),
+    flag(&["--entrypoint"], Opaque),
+    flag(&["-e", "--env"], Opaque),
+    flag(&["--env-file"], HostPath),
+    flag(&["--expose"], Opaque),
+    flag(&["--gpus"], Opaque),
+    flag(&["--group-add"], Opaque),
+    flag(&["--health-cmd"], Opaque),
+    flag(&["--health-interval"], Opaque),
+    flag(&["--health-retries"], Opaque),
+    flag(&["--health-start-interval"], Opaque),
+    flag(&["--health-start-period"], Opaque),
+    flag(&["--health-timeout"], Opaque),
+    flag(&["-h", "--hostname"], Opaque),
+    flag(&["--init"], ValueKind::None),
+    flag(&["-i", "--interactive"], ValueKind::None),
+    flag(&["--ip"], Opaque),
+    flag(&["--ip6"], Opaque),
+    flag(&["--ipc"], Opaque),
+    flag(&["--isolation"], Opaque),
+    flag(&["--kernel-memory"], Opaque),
+    flag(&["-l", "--label"], Opaque),
+    flag(&["--label-file"], HostPath),
+    flag(&["--link"], Opaque),
+    flag(&["--link-local-ip"], Opaque),
+    flag(&["--log-driver"], Opaque),
+    flag(&["--log-opt"], Opaque),
+    flag(&["--mac-address"], Opaque),
+    flag(&["-m", "--memory"], Opaque),
+    flag(&["--memory-reservation"], Opaque),
+    flag(&["--memory-swap"], Opaque),
+    flag(&["--memory-swappiness"], Opaque),
+    flag(&["--mount"], Mount),
+    flag(&["--name"], Opaque),
+    flag(&["--network", "--net"], Opaque),
+    flag(&["--network-alias", "--net-alias"], Opaque),
+    flag(&["--no-healthcheck"], ValueKind::None),
+    flag(&["--oom-kill-disable"], ValueKind::None),
+    flag(&["--oom-score-adj"], Opaque),
+    flag(&["--pid"], Opaque),
+    flag(&["--pids-limit"], Opaque),
+    flag(&["--platform"], Opaque),
+    flag(&["--privileged"], ValueKind::None),
+    flag(&["-p", "--publish"], Opaque),
+    flag(&["-P", "--publish-all"], ValueKind::None),
+    flag(&["--pull"], Opaque),
+    flag(&["-q", "--quiet"], ValueKind::None),
+    flag(&["--read-only"], ValueKind::None),
+    flag(&["--restart"], Opaque),
+    flag(&["--rm"], ValueKind::None),
+    flag(&["--runtime"], Opaque),
+    flag(&["--security-opt"], SecurityOpt),
+    flag(&["--shm-size"], Opaque),
+    flag(&["--sig-proxy"], ValueKind::None),
+    flag(&["--stop-signal"], Opaque),
+    flag(&["--stop-timeout"], Opaque),
+    flag(&["--storage-opt"], Opaque),
+    flag(&["--sysctl"], Opaque),
+    flag(&["-t", "--tty"], ValueKind::None),
+    flag(&["--tmpfs"], Opaque),
+    flag(&["--ulimit"], Opaque),
+    flag(&["-u", "--user"], Opaque),
+    flag(&["--userns"], Opaque),
+    flag(&["--uts"], Opaque),
+    flag(&["-v", "--volume"], Volume),
+    flag(&["--volume-driver"], Opaque),
+    flag(&["--volumes-from"], Opaque),
+    flag(&["-w", "--workdir"], Opaque),
+];
+
+/// Flags of `docker service create`.
+static SERVICE_CREATE_FLAGS: &[FlagSpec] = &[
+    flag(&["--cap-add"], Opaque),
+    flag(&["--cap-drop"], Opaque),
+    flag(&["--config"], Opaque),
+    flag(&["--constraint"], Opaque),
+    flag(&["--container-label"], Opaque),
+    flag(&["--credential-spec"], Opaque),
+    flag(&["-d", "--detach"], ValueKind::None),
+    flag(&["--dns"], Opaque),
+    flag(&["--dns-option"], Opaque),
+    flag(&["--dns-search"], Opaque),
+    flag(&["--endpoint-mode"], Opaque),
+    flag(&["--entrypoint"], Opaque),
+    flag(&["-e", "--env"], Opaque),
+    flag(&["--env-file"], HostPath),
+    flag(&["--generic-resource"], Opaque),
+    flag(&["--group"], Opaque),
+    flag(&["--health-cmd"], Opaque),
+    flag(&["--health-interval"], Opaque),
+    flag(&["--health-retries"], Opaque),
+    flag(&["--health-start-interval"], Opaque),
+    flag(&["--health-start-period"], Opaque),
+    flag(&["--health-timeout"], Opaque),
+    flag(&["--host"], Opaque),
+    flag(&["--hostname"], Opaque),
+    flag(&["--init"], ValueKind::None),
+    flag(&["--isolation"], Opaque),
+    flag(&["-l", "--label"], Opaque),
+    flag(&["--limit-cpu"], Opaque),
+    flag(&["--limit-memory"], Opaque),
+    flag(&["--limit-pids"], Opaque),
+    flag(&["--log-driver"], Opaque),
+    flag(&["--log-opt"], Opaque),
+    flag(&["--max-concurrent"], Opaque),
+    flag(&["--mode"], Opaque),
+    flag(&["--mount"], Mount),
+    flag(&["--name"], Opaque),
+    flag(&["--network"], Opaque),
+    flag(&["--no-healthcheck"], ValueKind::None),
+    flag(&["--no-resolve-image"], ValueKind::None),
+    flag(&["--oom-score-adj"], Opaque),
+    flag(&["--placement-pref"], Opaque),
+    flag(&["-p", "--publish"], Opaque),
+    flag(&["-q", "--quiet"], ValueKind::None),
+    flag(&["--read-only"], ValueKind::None),
+    flag(&["--replicas"], Opaque),
+    flag(&["--replicas-max-per-node"], Opaque),
+    flag(&["--reserve-cpu"], Opaque),
+    flag(&["--reserve-memory"], Opaque),
+    flag(&["--restart-condition"], Opaque),
+    flag(&["--restart-delay"], Opaque),
+    flag(&["--restart-max-attempts"], Opaque),
+    flag(&["--restart-window"], Opaque),
+    flag(&["--rollback-delay"], Opaque),
+    flag(&["--rollback-failure-action"], Opaque),
+    flag(&["--rollback-max-failure-ratio"], Opaque),
+    flag(&["--rollback-monitor"], Opaque),
+    flag(&["--rollback-order"], Opaque),
+    flag(&["--rollback-parallelism"], Opaque),
+    flag(&["--secret"], Opaque),
+    flag(&["--stop-grace-period"], Opaque),
+    flag(&["--stop-signal"], Opaque),
+    flag(&["--sysctl"], Opaque),
+    flag(&["-t", "--tty"], ValueKind::None),
+    flag(&["--ulimit"], Opaque),
+    flag(&["--update-delay"], Opaque),
+    flag(&["--update-failure-action"], Opaque),
+    flag(&["--update-max-failure-ratio"], Opaque),
+    flag(&["--update-monitor"], Opaque),
+    flag(&["--update-order"], Opaque),
+    flag(&["--update-parallelism"], Opaque),
+    flag(&["-u", "--user"], Opaque),
+    flag(&["--with-registry-auth"], ValueKind::None),
+    flag(&["-w", "--workdir"], Opaque),
+];
+
+/// Flags of `docker compose run`, following the compose global flags.
+static COMPOSE_RUN_FLAGS: &[FlagSpec] = &[
+    flag(&["--build"], ValueKind::None),
+    flag(&["--cap-add"], Opaque),
+    flag(&["--cap-drop"], Opaque),
+    flag(&["-d", "--detach"], ValueKind::None),
+    flag(&["--entrypoint"], Opaque),
+    flag(&["-e", "--env"], Opaque),
+    flag(&["--env-from-file"], HostPath),
+    flag(&["-i", "--interactive"], ValueKind::None),
+    flag(&["-l", "--label"], Opaque),
+    flag(&["--name"], Opaque),
+    flag(&["-T", "--no-TTY"], ValueKind::None),
+    flag(&["--no-deps"], ValueKind::None),
+    flag(&["-p", "--publish"], Opaque),
+    flag(&["--pull"], Opaque),
+    flag(&["-q", "--quiet"], ValueKind::None),
+    flag(&["--quiet-build"], ValueKind::None),
+    flag(&["--quiet-pull"], ValueKind::None),
+    flag(&["--remove-orphans"], ValueKind::None),
+    flag(&["--rm"], ValueKind::None),
+    flag(&["-P", "--service-ports"], ValueKind::None),
+    flag(&["--use-aliases"], ValueKind::None),
+    flag(&["-u", "--user"], Opaque),
+    flag(&["-v", "--volume"], Volume),
+    flag(&["-w", "--workdir"], Opaque),
+];
+
+static BUILD_FLAGS: &[FlagSpec] = &[
+    flag(&["--add-host"], Opaque),
+    flag(&["--allow"], Opaque),
+    flag(&["--annotation"], Opaque),
+    flag(&["--attest"], Opaque),
+    flag(&["--build-arg"], Opaque),
+    flag(&["--build-context"], Opaque),
+    flag(&["--builder"], Opaque),
+    flag(&["--cache-from"], Exporter),
+    flag(&["--cache-to"], Exporter),
+    flag(&["--call"], Opaque),
+    flag(&["--cgroup-parent"], Opaque),
+    flag(&["--check"], ValueKind::None),
+    flag(&["--compress"], ValueKind::None),
+    flag(&["--cpu-period"], Opaque),
+    flag(&["--cpu-quota"], Opaque),
+    flag(&["-c", "--cpu-shares"], Opaque),
+    flag(&["--cpuset-cpus"], Opaque),
+    flag(&["--cpuset-mems"], Opaque),
+    flag(&["--disable-content-trust"], ValueKind::None),
+    flag(&["-f", "--file"], HostPath),
+    flag(&["--force-rm"], ValueKind::None),
+    flag(&["--iidfile"], HostPath),
+    flag(&["--isolation"], Opaque),
+    flag(&["--label"], Opaque),
+    flag(&["--load"], ValueKind::None),
+    flag(&["-m", "--memory"], Opaque),
+    flag(&["--memory-swap"], Opaque),
+    flag(&["--metadata-file"], HostPath),
+    flag(&["--network"], Opaque),
+    flag(&["--no-cache"], ValueKind::None),
+    flag(&["--no-cache-filter"], Opaque),
+    flag(&["-o", "--output"], Exporter),
+    flag(&["--platform"], Opaque),
+    flag(&["--progress"], Opaque),
+    flag(&["--provenance"], Opaque),
+    flag(&["--pull"], ValueKind::None),
+    flag(&["--push"], ValueKind::None),
+    flag(&["-q", "--quiet"], ValueKind::None),
+    flag(&["--rm"], ValueKind::None),
+    flag(&["--sbom"], Opaque),
+    flag(&["--secret"], Secret),
+    flag(&["--security-opt"], Opaque),
+    flag(&["--shm-size"], Opaque),
+    flag(&["--squash"], ValueKind::None),
+    flag(&["--ssh"], Ssh),
+    flag(&["-t", "--tag"], Opaque),
+    flag(&["--target"], Opaque),
+    flag(&["--ulimit"], Opaque),
+];
+
+pub static BAKE_FLAGS: &[FlagSpec] = &[
+    flag(&["--allow"], Opaque),
+    flag(&["--builder"], Opaque),
+    flag(&["--call"], Opaque),
+    flag(&["--check"], ValueKind::None),
+    flag(&["--debug"], ValueKind::None),
+    flag(&["-f", "--file"], ValueKind::BakeFile),
+    flag(&["--list"], Opaque),
+    flag(&["--load"], ValueKind::None),
+    flag(&["--metadata-file"], HostPath),
+    flag(&["--no-cache"], ValueKind::None),
+    flag(&["--print"], ValueKind::None),
+    flag(&["--progress"], Opaque),
+    flag(&["--provenance"], Opaque),
+    flag(&["--pull"], ValueKind::None),
+    flag(&["--push"], ValueKind::None),
+    flag(&["--sbom"], Opaque),
+    flag(&["--set"], ValueKind::BakeSet),
+];
+
+static EXEC_FLAGS: &[FlagSpec] = &[
+    flag(&["-d", "--detach"], ValueKind::None),
+    flag(&["--detach-keys"], Opaque),
+    flag(&["-e", "--env"], Opaque),
+    flag(&["--env-file"], HostPath),
+    flag(&["-i", "--interactive"], ValueKind::None),
+    flag(&["--privileged"], ValueKind::None),
+    flag(&["-t", "--tty"], ValueKind::None),
+    flag(&["-u", "--user"], Opaque),
+    flag(&["-w", "--workdir"], Opaque),
+];
+
+static CP_FLAGS: &[FlagSpec] = &[
+    flag(&["-a", "--archive"], ValueKind::None),
+    flag(&["-L", "--follow-link"], ValueKind::None),
+    flag(&["-q", "--quiet"], ValueKind::None),
+];
+
+static SAVE_FLAGS: &[FlagSpec] = &[
+    flag(&["-o", "--output"], HostPath),
+    flag(&["--platform"], Opaque),
+];
+
+static LOAD_FLAGS: &[FlagSpec] = &[
+    flag(&["-i", "--input"], HostPath),
+    flag(&["--platform"], Opaque),
+    flag(&["-q", "--quiet"], ValueKind::None),
+];
+
+static EXPORT_FLAGS: &[FlagSpec] = &[flag(&["-o", "--output"], HostPath)];
+
+static IMPORT_FLAGS: &[FlagSpec] = &[
+    flag(&["-c", "--change"], Opaque),
+    flag(&["-m", "--message"], Opaque),
+    flag(&["--platform"], Opaque),
+];
+
+static INSPECT_FLAGS: &[FlagSpec] = &[
+    flag(&["-f", "--format"], Opaque),
+    flag(&["--pretty"], ValueKind::None),
+    flag(&["-s", "--size"], ValueKind::None),
+    flag(&["--type"], Opaque),
+];
+
+pub static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "run",
+        parents: &["container"],
+        flags: CONTAINER_CREATE_FLAGS,
+        interspersed: false,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "create",
+        parents: &["container"],
+        flags: CONTAINER_CREATE_FLAGS,
+        interspersed: false,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "exec",
+        parents: &["container"],
+        flags: EXEC_FLAGS,
+        interspersed: false,
+        positionals: Positionals::Opaque,
+        fallback: false,
+    },
+    CommandSpec {
+        name: "build",
+        parents: &["buildx", "image", "builder"],
+        flags: BUILD_FLAGS,
+        interspersed: true,
+        positionals: Positionals::LocalPath,
+        fallback: true,
+    },
+    // After the container commands, which `docker create` resolves to.
+    CommandSpec {
+        name: "create",
+        parents: &["service"],
+        flags: SERVICE_CREATE_FLAGS,
+        interspersed: false,
+        positionals: Positionals::Opaque,
+        fallback: false,
+    },
+    CommandSpec {
+        name: "bake",
+        parents: &["buildx"],
+        flags: BAKE_FLAGS,
+        interspersed: true,
+        positionals: Positionals::Opaque,
+        fallback: false,
+    },
+    CommandSpec {
+        name: "cp",
+        parents: &["container"],
+        flags: CP_FLAGS,
+        interspersed: true,
+        positionals: Positionals::CpOperands,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "save",
+        parents: &["image"],
+        flags: SAVE_FLAGS,
+        interspersed: true,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "load",
+        parents: &["image"],
+        flags: LOAD_FLAGS,
+        interspersed: true,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "export",
+        parents: &["container"],
+        flags: EXPORT_FLAGS,
+        interspersed: true,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "import",
+        parents: &["image"],
+        flags: IMPORT_FLAGS,
+        interspersed: true,
+        positionals: Positionals::LocalPath,
+        fallback: true,
+    },
+    CommandSpec {
+        name: "inspect",
+        parents: &[
+            "config",
+            "container",
+            "context",
+            "image",
+            "network",
+            "node",
+            "plugin",
+            "secret",
+            "service",
+            "volume",
+        ],
+        flags: INSPECT_FLAGS,
+        interspersed: true,
+        positionals: Positionals::Opaque,
+        fallback: true,
+    },
+];
+
+/// `docker compose` as far as its global flags go. The compose subcommand and everything
+/// after it are positionals.
+pub static COMPOSE: CommandSpec = CommandSpec {
+    name: "compose",
+    parents: &[],
+    flags: COMPOSE_GLOBAL_FLAGS,
+    interspersed: false,
+    positionals: Positionals::Opaque,
+    fallback: true,
+};
+
+/// `docker compose run`, the compose subcommand taking bind mounts of its own.
+pub static COMPOSE_RUN: CommandSpec = CommandSpec {
+    name: "run",
+    parents: &["compose"],
+    flags: COMPOSE_RUN_FLAGS,
+    interspersed: false,
+    positionals: Positionals::Opaque,
+    fallback: false,
+};
+
+static COMPOSE_CP_FLAGS: &[FlagSpec] = &[
+    flag(&["--all"], ValueKind::None),
+    flag(&["-a", "--archive"], ValueKind::None),
+    flag(&["-L", "--follow-link"], ValueKind::None),
+    flag(&["--index"], Opaque),
+];
+
+/// `docker compose cp`, copying between a service container and a local path.
+pub static COMPOSE_CP: CommandSpec = CommandSpec {
+    name: "cp",
+    parents: &["compose"],
+    flags: COMPOSE_CP_FLAGS,
+    interspersed: true,
+    positionals: Positionals::CpOperands,
+    fallback: false,
+};
+
+/// Location of a flag value: the index of the argument holding it and the offset at which the
+/// value starts, which is non-zero for `--file=x` and `-fx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueRef {
+    pub index: usize,
+    pub offset: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum Token {
+    /// A flag, `None` as spec when it is not modeled. Unmodeled flags are assumed to be
+    /// boolean, which errs on the side of rewriting less.
+    Flag {
+        /// Index of the argument naming the flag.
+        index: usize,
+        spec: Option<&'static FlagSpec>,
+        value: Option<ValueRef>,
+    },
+    Positional(usize),
+}
+
+/// Splits `args` into flags and positionals following the rules of docker's flag parser:
+/// `--name=value`, `--name value`, combined short flags (`-it`), attached short values
+/// (`-fDockerfile`, `-f=Dockerfile`), `-` as a positional, and `--` ending the flags.
+pub fn scan(args: &[String], flags: &'static [FlagSpec], interspersed: bool) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut only_positionals = false;
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        if only_positionals || arg == "-" || !arg.starts_with('-') {
+            tokens.push(Token::Positional(index));
+            only_positionals |= !interspersed;
+            index += 1;
+            continue;
+        }
+
+        if arg == "--" {
+            only_positionals = true;
+            index += 1;
+            continue;
+        }
+
+        let flag_index = index;
+        let (spec, value) = if let Some(long) = arg.strip_prefix("--") {
+            let name_len = long.find('=').unwrap_or(long.len()) + 2;
+            let spec = lookup(flags, &arg[..name_len]);
+            let value = if name_len < arg.len() {
+                Some(ValueRef {
+                    index,
+                    offset: name_len + 1,
+                })
+            } else if takes_value(spec) && index + 1 < args.len() {
+                index += 1;
+                Some(ValueRef { index, offset: 0 })
+            } else {
+                None
+            };
+
+            (spec, value)
+        } else {
+            let mut spec = None;
+            let mut value = None;
+            for (pos, c) in arg.char_indices().skip(1) {
+                spec = lookup(flags, &format!("-{c}"));
+                if !takes_value(spec) {
+                    continue;
+                }
+
+                let rest = pos + c.len_utf8();
+                if rest < arg.len() {
+                    let offset = if arg[rest..].starts_with('=') {
+                        rest + 1
+                    } else {
+                        rest
+                    };
+                    value = Some(ValueRef { index, offset });
+                } else if index + 1 < args.len() {
+                    index += 1;
+                    value = Some(ValueRef { index, offset: 0 });
+                }
+                break;
+            }
+
+            (spec, value)
+        };
+
+        tokens.push(Token::Flag {
+            index: flag_index,
+            spec,
+            value,
+        });
+        index += 1;
+    }
+
+    tokens
+}
+
+/// Resolves the command `args` invokes, returning its spec and the index at which its own
+/// arguments start. Management commands are followed to their subcommand.
+pub fn resolve_command(args: &[String]) -> Option<(&'static CommandSpec, usize)> {
+    let name = args.first()?;
+    if let Some(spec) = COMMANDS.iter().find(|spec| spec.name == name) {
+        return Some((spec, 1));
+    }
+
+    let (parent, parent_flags) = PARENT_COMMANDS.iter().find(|(parent, _)| parent == name)?;
+    let index =
+        scan(&args[1..], parent_flags, false)
+            .into_iter()
+            .find_map(|token| match token {
+                Token::Positional(index) => Some(index + 1),
+                Token::Flag { .. } => None,
+            })?;
+    let spec = COMMANDS
+        .iter()
+        .find(|spec| spec.name == args[index] && spec.parents.contains(parent))?;
+
+    Some((spec, index + 1))
+}
+
+/// Whether `args` is a `run` or `exec` that allocates a TTY for the container (`-t`), i.e.
+/// an interactive session drawn on the console.
+pub fn requests_tty(args: &[String]) -> bool {
+    let Some(command_index) = first_positional(&scan(args, GLOBAL_FLAGS, false)) else {
+        return false;
+    };
+    let args = &args[command_index..];
+    let Some((spec, start)) = resolve_command(args) else {
+        return false;
+    };
+    if !matches!(spec.name, "run" | "exec") {
+        return false;
+    }
+
+    let args = &args[start..];
+    scan(args, spec.flags, spec.interspersed)
+        .into_iter()
+        .any(|token| {
+            let Token::Flag { index, .. } = token else {
+                return false;
+            };
+
+            let arg = &args[index];
+            if let Some(long) = arg.strip_prefix("--") {
+                return long == "tty" || long == "tty=true";
+            }
+
+            // `-t` may be combined with other short flags, up to one taking a value (`-itu0`).
+            for c in arg.chars().skip(1) {
+                if c == 't' {
+                    return true;
+                }
+                if takes_value(lookup(spec.flags, &format!("-{c}"))) {
+                    break;
+                }
+            }
+
+            false
+        })
+}
+
+pub fn first_positional(tokens: &[Token]) -> Option<usize> {
+    tokens.iter().find_map(|token| match token {
+        Token::Positional(index) => Some(*index),
+        Token::Flag { .. } => None,
+    })
+}
+
+fn lookup(flags: &'static [FlagSpec], name: &str) -> Option<&'static FlagSpec> {
+    flags.iter().find(|flag| flag.names.contains(&name))
+}
+
+fn takes_value(spec: Option<&FlagSpec>) -> bool {
+    spec.is_some_and(|spec| spec.value != ValueKind::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// `tokens` as the flag or positional they start at, with the flag values.
+    fn render(args: &[String], tokens: &[Token]) -> Vec<(String, Option<String>)> {
+        tokens
+            .iter()
+            .map(|token| match *token {
+                Token::Flag { index, value, .. } => (
+                    args[index].clone(),
+                    value.map(|value| args[value.index][value.offset..].to_string()),
+                ),
+                Token::Positional(index) => (args[index].clone(), None),
+            })
+            .collect()
+    }
+
+    fn scanned(
+        raw: &[&str],
+        flags: &'static [FlagSpec],
+        interspersed: bool,
+    ) -> Vec<(String, Option<String>)> {
+        let args = args(raw);
+        render(&args, &scan(&args, flags, interspersed))
+    }
+
+    fn owned(expected: &[(&str, Option<&str>)]) -> Vec<(String, Option<String>)> {
+        expected
+            .iter()
+            .map(|(arg, value)| (arg.to_string(), value.map(str::to_string)))
+            .collect()
+    }
+
+    #[test]
+    fn flag_values_are_found_in_every_form() {
+        assert_eq!(
+            scanned(
+                &[
+                    "-fDockerfile.win",
+                    "-f=Dockerfile",
+                    "--file=x",
+                    "--tag",
+                    "app",
+                    "."
+                ],
+                BUILD_FLAGS,
+                true
+            ),
+            owned(&[
+                ("-fDockerfile.win", Some("Dockerfile.win")),
+                ("-f=Dockerfile", Some("Dockerfile")),
+                ("--file=x", Some("x")),
+                ("--tag", Some("app")),
+                (".", None),
+            ])
+        );
+    }
+
+    #[test]
+    fn grouped_short_flags_end_at_one_taking_a_value() {
+        assert_eq!(
+            scanned(
+                &["-it", "-itv", r"C:\x:/y", "-itu0", "image"],
+                CONTAINER_CREATE_FLAGS,
+                false
+            ),
+            owned(&[
+                ("-it", None),
+                ("-itv", Some(r"C:\x:/y")),
+                ("-itu0", Some("0")),
+                ("image", None),
+            ])
+        );
+    }
+
+    #[test]
+    fn dashes_are_positionals() {
+        assert_eq!(
+            scanned(&["-", "--", "-f", "x"], BUILD_FLAGS, true),
+            owned(&[("-", None), ("-f", None), ("x", None)])
+        );
+    }
+
+    #[test]
+    fn unmodeled_flags_take_no_value() {
+        let args = args(&["--unknown", "x"]);
+        let tokens = scan(&args, BUILD_FLAGS, true);
+        assert!(matches!(
+            tokens[0],
+            Token::Flag {
+                spec: None,
+                value: None,
+                ..
+            }
+        ));
+        assert!(matches!(tokens[1], Token::Positional(1)));
+    }
+
+    #[test]
+    fn flags_after_positionals_depend_on_interspersing() {
+        assert_eq!(
+            scanned(&[".", "-t", "app"], BUILD_FLAGS, true),
+            owned(&[(".", None), ("-t", Some("app"))])
+        );
+        assert_eq!(
+            scanned(&["image", "-t", "sh"], CONTAINER_CREATE_FLAGS, false),
+            owned(&[("image", None), ("-t", None), ("sh", None)])
+        );
+    }
+
+    #[test]
+    fn management_commands_resolve_to_their_subcommand() {
+        // The name and parents tell the specs apart.
+        let resolved = |raw: &[&str]| {
+            resolve_command(&args(raw)).map(|(spec, start)| (spec.name, spec.parents[0], start))
+        };
+
+        assert_eq!(resolved(&["run", "image"]), Some(("run", "container", 1)));
+        assert_eq!(
+            resolved(&["container", "run", "image"]),
+            Some(("run", "container", 2))
+        );
+        assert_eq!(
+            resolved(&["buildx", "--builder", "b", "bake", "-f", "x"]),
+            Some(("bake", "buildx", 4))
+        );
+        assert_eq!(
+            resolved(&["service", "create", "image"]),
+            Some(("create", "service", 2))
+        );
+        assert_eq!(
+            resolved(&["create", "image"]),
+            Some(("create", "container", 1))
+        );
+        assert_eq!(
+            resolved(&["volume", "inspect", "data"]),
+            Some(("inspect", "config", 2))
+        );
+        assert_eq!(resolved(&["image", "ls"]), None);
+        // `docker compose` goes through its own specs.
+        assert_eq!(resolved(&["compose", "run", "app"]), None);
+        assert_eq!(
+            scanned(
+                &["-f", "a.yml", "run", "-v", "x:/y", "app"],
+                COMPOSE.flags,
+                false
+            ),
+            owned(&[
+                ("-f", Some("a.yml")),
+                ("run", None),
+                ("-v", None),
+                ("x:/y", None),
+                ("app", None),
+            ])
+        );
+        assert_eq!(
+            scanned(&["-v", "x:/y", "app", "-v"], COMPOSE_RUN.flags, false),
+            owned(&[("-v", Some("x:/y")), ("app", None), ("-v", None)])
+        );
+    }
+}
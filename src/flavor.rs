@@ -0,0 +1,395 @@
+//! Base distros the docker host can be built from, with what differs between them: where the
+//! rootfs comes from, how docker is installed and which init scripts run the daemon.
+
+use crate::{config::Config, download};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use tracing::debug;
+
+/// Index of the Ubuntu WSL images, one directory per release codename.
+const UBUNTU_WSL_IMAGES: &str = "https://cloud-images.ubuntu.com/wsl";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Flavor {
+    /// Ubuntu from the official WSL images, of the release set by `ubuntu_release`.
+    #[default]
+    Ubuntu,
+    /// Debian 12 from the rootfs of the official container image.
+    Debian,
+    /// Alpine Linux, the smallest host, with docker from its own packages and OpenRC.
+    Alpine,
+    /// Fedora 40 from the rootfs of the official container image.
+    Fedora,
+}
+
+/// Processor architecture of the docker host, which has to match Windows to run natively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Arch {
+    Amd64,
+    Arm64,
+}
+
+impl Arch {
+    /// Architecture of Windows itself. An x64 build of the wrapper runs emulated on ARM64
+    /// Windows, so the architecture it was compiled for says nothing.
+    #[cfg(windows)]
+    pub fn host() -> Self {
+        use windows_sys::Win32::System::{
+            SystemInformation::IMAGE_FILE_MACHINE_ARM64, Threading::GetCurrentProcess,
+            Threading::IsWow64Process2,
+        };
+
+        let (mut process, mut native) = (0, 0);
+        // SAFETY: both out pointers are valid for the duration of the call.
+        let ok = unsafe { IsWow64Process2(GetCurrentProcess(), &mut process, &mut native) };
+        if ok != 0 && native == IMAGE_FILE_MACHINE_ARM64 {
+            Self::Arm64
+        } else {
+            Self::Amd64
+        }
+    }
+
+    #[cfg(not(windows))]
+    pub fn host() -> Self {
+        match std::env::consts::ARCH {
+            "aarch64" => Self::Arm64,
+            _ => Self::Amd64,
+        }
+    }
+
+    /// Name in Debian and Ubuntu terms.
+    fn debian_name(self) -> &'static str {
+        match self {
+            Self::Amd64 => "amd64",
+            Self::Arm64 => "arm64",
+        }
+    }
+
+    /// Name in kernel terms, used by Alpine and Fedora.
+    fn kernel_name(self) -> &'static str {
+        match self {
+            Self::Amd64 => "x86_64",
+            Self::Arm64 => "aarch64",
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "amd64" => Ok(Self::Amd64),
+            "arm64" => Ok(Self::Arm64),
+            _ => bail!("invalid architecture '{s}' (expected amd64 or arm64)"),
+        }
+    }
+}
+
+impl Flavor {
+    /// Name of the flavor in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ubuntu => "ubuntu",
+            Self::Debian => "debian",
+            Self::Alpine => "alpine",
+            Self::Fedora => "fedora",
+        }
+    }
+
+    /// Rootfs of the flavors that do not come in several releases.
+    fn default_rootfs_url(self, arch: Arch) -> String {
+        let kernel = arch.kernel_name();
+        match self {
+            Self::Ubuntu => unreachable!("the Ubuntu rootfs depends on the release"),
+            Self::Debian => {
+                // The artifacts name their branches after the architecture tags of docker.
+                let branch = match arch {
+                    Arch::Amd64 => "amd64",
+                    Arch::Arm64 => "arm64v8",
+                };
+                format!("https://github.com/debuerreotype/docker-debian-artifacts/raw/dist-{branch}/bookworm/rootfs.tar.xz")
+            }
+            Self::Alpine => format!("https://dl-cdn.alpinelinux.org/alpine/v3.20/releases/{kernel}/alpine-minirootfs-3.20.3-{kernel}.tar.gz"),
+            Self::Fedora => format!("https://github.com/fedora-cloud/docker-brew-fedora/raw/40/{kernel}/fedora-40-{kernel}.tar.xz"),
+        }
+    }
+
+    /// Rough size of the rootfs tarball, rounded up.
+    pub fn download_size(self) -> u64 {
+        const MIB: u64 = 1 << 20;
+        match self {
+            Self::Ubuntu => 500 * MIB,
+            Self::Debian | Self::Fedora => 100 * MIB,
+            Self::Alpine => 10 * MIB,
+        }
+    }
+
+    /// Rough size of the disk image once imported and with docker installed, rounded up.
+    pub fn installed_size(self) -> u64 {
+        const MIB: u64 = 1 << 20;
+        match self {
+            Self::Ubuntu => 3 * 1024 * MIB,
+            Self::Debian | Self::Fedora => 2 * 1024 * MIB,
+            Self::Alpine => 1024 * MIB,
+        }
+    }
+
+    /// Shell script installing the docker engine and CLI into a freshly imported rootfs, of
+    /// `version` if given, such as `27.3` or `27.3.1`, and else the latest.
+    pub fn install_script(self, version: Option<&str>) -> String {
+        let get_docker = match version {
+            Some(version) => {
+                format!("curl -fsSL https://get.docker.com/ | sh -s -- --version {version}")
+            }
+            None => "curl -fsSL https://get.docker.com/ | sh".to_string(),
+        };
+        match self {
+            Self::Ubuntu | Self::Fedora => get_docker,
+            // The container rootfs comes without curl.
+            Self::Debian => format!(
+                "apt-get update && apt-get install -y curl ca-certificates && {get_docker}"
+            ),
+            // `=~` picks the newest package of that version prefix.
+            Self::Alpine => match version {
+                Some(version) => format!(
+                    "apk add --no-cache docker=~{version} docker-cli=~{version} docker-cli-compose openrc"
+                ),
+                None => "apk add --no-cache docker docker-cli-compose openrc".to_string(),
+            },
+        }
+    }
+
+    /// Shell script installing the compose and buildx plugins of the CLI, which the engine
+    /// does not always come with.
+    pub fn plugins_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => {
+                "apt-get install -y docker-compose-plugin docker-buildx-plugin"
+            }
+            Self::Alpine => "apk add --no-cache docker-cli-compose docker-cli-buildx",
+            Self::Fedora => "dnf install -y docker-compose-plugin docker-buildx-plugin",
+        }
+    }
+
+    /// Shell script installing the NVIDIA container toolkit from NVIDIA's repository, `None`
+    /// for Alpine, which it does not support.
+    pub fn nvidia_toolkit_script(self) -> Option<&'static str> {
+        match self {
+            Self::Ubuntu | Self::Debian => Some(
+                "apt-get update && apt-get install -y curl gnupg \
+                 && curl -fsSL https://nvidia.github.io/libnvidia-container/gpgkey \
+                 | gpg --batch --yes --dearmor -o /usr/share/keyrings/nvidia-container-toolkit-keyring.gpg \
+                 && curl -fsSL https://nvidia.github.io/libnvidia-container/stable/deb/nvidia-container-toolkit.list \
+                 | sed 's#deb https://#deb [signed-by=/usr/share/keyrings/nvidia-container-toolkit-keyring.gpg] https://#' \
+                 > /etc/apt/sources.list.d/nvidia-container-toolkit.list \
+                 && apt-get update && apt-get install -y nvidia-container-toolkit",
+            ),
+            Self::Fedora => Some(
+                "curl -fsSL https://nvidia.github.io/libnvidia-container/stable/rpm/nvidia-container-toolkit.repo \
+                 > /etc/yum.repos.d/nvidia-container-toolkit.repo \
+                 && dnf install -y nvidia-container-toolkit",
+            ),
+            Self::Alpine => None,
+        }
+    }
+
+    /// Shell script installing `socat`, which relays the ssh agent of Windows.
+    pub fn socat_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => "apt-get update -qq && apt-get install -y socat",
+            Self::Alpine => "apk add --no-cache socat",
+            Self::Fedora => "dnf install -y socat",
+        }
+    }
+
+    /// Shell script printing the docker packages that have an update, one per line.
+    pub fn check_updates_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => {
+                "apt-get update -qq >/dev/null \
+                 && apt list --upgradable 2>/dev/null | grep -E '^(docker-|containerd)' || true"
+            }
+            Self::Alpine => {
+                "apk update -q >/dev/null \
+                 && apk version -l '<' docker docker-cli docker-cli-compose containerd | tail -n +2"
+            }
+            Self::Fedora => {
+                "dnf -q check-update 'docker-*' containerd.io | grep -E '^(docker-|containerd)' || true"
+            }
+        }
+    }
+
+    /// Shell script upgrading the docker packages that are installed.
+    pub fn upgrade_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => {
+                "apt-get update && apt-get install -y --only-upgrade docker-ce docker-ce-cli \
+                 containerd.io docker-buildx-plugin docker-compose-plugin"
+            }
+            Self::Alpine => {
+                "apk upgrade --no-cache docker docker-cli docker-cli-compose containerd"
+            }
+            Self::Fedora => "dnf upgrade -y 'docker-*' containerd.io",
+        }
+    }
+
+    /// Shell script starting the daemon, doing nothing if it already runs. Under systemd the
+    /// docker unit is started, and otherwise the init scripts of the flavor are used, as on
+    /// versions of WSL without systemd support.
+    pub fn start_script(self) -> String {
+        under_systemd("systemctl start docker", self.init_start_script())
+    }
+
+    pub fn stop_script(self) -> String {
+        under_systemd("systemctl stop docker", self.init_stop_script())
+    }
+
+    /// Shell script restarting the daemon to apply a changed `daemon.json`.
+    pub fn restart_script(self) -> String {
+        under_systemd("systemctl restart docker", self.init_restart_script())
+    }
+
+    fn init_start_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => "/sbin/service docker start",
+            // OpenRC refuses to start services until it believes it has booted.
+            Self::Alpine => {
+                "mkdir -p /run/openrc && touch /run/openrc/softlevel \
+                 && (rc-service docker status >/dev/null 2>&1 || rc-service docker start)"
+            }
+            // The docker packages for Fedora only ship a systemd unit, so dockerd is started
+            // directly, logging where the init scripts of the other flavors do, with the
+            // environment the unit would read.
+            Self::Fedora => {
+                "kill -0 \"$(cat /var/run/docker.pid 2>/dev/null)\" 2>/dev/null \
+                 || (set -a; . /etc/docker/docker-wrapper-proxy.env 2>/dev/null; set +a; \
+                     nohup dockerd >>/var/log/docker.log 2>&1 </dev/null &)"
+            }
+        }
+    }
+
+    fn init_stop_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => "/sbin/service docker stop",
+            Self::Alpine => {
+                "mkdir -p /run/openrc && touch /run/openrc/softlevel && rc-service docker stop"
+            }
+            Self::Fedora => {
+                "pid=\"$(cat /var/run/docker.pid 2>/dev/null)\"; \
+                 [ -z \"$pid\" ] || { kill \"$pid\"; while kill -0 \"$pid\" 2>/dev/null; do sleep 0.1; done; }"
+            }
+        }
+    }
+
+    fn init_restart_script(self) -> &'static str {
+        match self {
+            Self::Ubuntu | Self::Debian => "/sbin/service docker restart",
+            Self::Alpine => {
+                "mkdir -p /run/openrc && touch /run/openrc/softlevel && rc-service docker restart"
+            }
+            Self::Fedora => {
+                "pid=\"$(cat /var/run/docker.pid 2>/dev/null)\"; \
+                 [ -n \"$pid\" ] && kill \"$pid\" && while kill -0 \"$pid\" 2>/dev/null; do sleep 0.1; done; \
+                 (set -a; . /etc/docker/docker-wrapper-proxy.env 2>/dev/null; set +a; \
+                  nohup dockerd >>/var/log/docker.log 2>&1 </dev/null &)"
+            }
+        }
+    }
+}
+
+/// Shell test passing when systemd is the init of the running distro.
+pub const SYSTEMD_RUNNING: &str = "[ -d /run/systemd/system ]";
+
+fn under_systemd(systemd: &str, fallback: &str) -> String {
+    format!("if {SYSTEMD_RUNNING}; then {systemd}; else {fallback}; fi")
+}
+
+impl fmt::Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Ubuntu => "Ubuntu",
+            Self::Debian => "Debian 12",
+            Self::Alpine => "Alpine Linux",
+            Self::Fedora => "Fedora 40",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ubuntu" => Ok(Self::Ubuntu),
+            "debian" => Ok(Self::Debian),
+            "alpine" => Ok(Self::Alpine),
+            "fedora" => Ok(Self::Fedora),
+            _ => bail!("invalid flavor '{s}' (expected ubuntu, debian, alpine or fedora)"),
+        }
+    }
+}
+
+/// Where the rootfs of the configured distro is downloaded from, resolving the Ubuntu
+/// release against the image index when needed.
+pub fn rootfs_url(config: &Config) -> Result<String> {
+    if let Some(url) = &config.rootfs_url {
+        return Ok(url.clone());
+    }
+
+    let arch = config.arch.unwrap_or_else(Arch::host);
+    match config.flavor {
+        Flavor::Ubuntu => ubuntu_rootfs_url(config, arch),
+        flavor => Ok(flavor.default_rootfs_url(arch)),
+    }
+}
+
+/// Finds the WSL rootfs in the `current` images of `release`, a codename such as `noble` or
+/// `latest` for the newest release that has one. The file names differ between releases,
+/// newer ones coming as `.wsl` archives.
+fn ubuntu_rootfs_url(config: &Config, arch: Arch) -> Result<String> {
+    let release = &config.ubuntu_release;
+    let arch_tag = format!("wsl-{}", arch.debian_name());
+    let releases = if release == "latest" {
+        let index = download::fetch_text(config, &format!("{UBUNTU_WSL_IMAGES}/"))
+            .context("failed to list the Ubuntu releases")?;
+        let mut releases: Vec<_> = directory_links(&index)
+            .filter(|name| name.chars().all(|c| c.is_ascii_lowercase()))
+            .collect();
+        // Codenames have been alphabetical since 17.10, which covers every WSL image.
+        releases.sort_unstable();
+        releases.into_iter().rev().map(str::to_string).collect()
+    } else {
+        vec![release.to_string()]
+    };
+
+    for release in releases {
+        let dir = format!("{UBUNTU_WSL_IMAGES}/{release}/current");
+        let Ok(index) = download::fetch_text(config, &format!("{dir}/")) else {
+            debug!("no current images for Ubuntu '{release}'");
+            continue;
+        };
+        let rootfs = index.split('"').find(|link| {
+            link.contains(&arch_tag) && (link.ends_with(".rootfs.tar.gz") || link.ends_with(".wsl"))
+        });
+        if let Some(rootfs) = rootfs {
+            debug!("resolved Ubuntu release '{release}'");
+            return Ok(format!("{dir}/{rootfs}"));
+        }
+    }
+
+    bail!("found no WSL rootfs for Ubuntu '{release}' at {UBUNTU_WSL_IMAGES}")
+}
+
+/// Subdirectories linked from a directory listing, without the trailing slash.
+fn directory_links(index: &str) -> impl Iterator<Item = &str> {
+    index
+        .split("href=\"")
+        .skip(1)
+        .filter_map(|link| link.split('"').next()?.strip_suffix('/'))
+}
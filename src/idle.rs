@@ -0,0 +1,54 @@
+//! Idle shutdown: a task of the Windows Task Scheduler checks every few minutes whether the
+//! distro is still used, and stops it otherwise so that the WSL VM gives its memory back.
+
+use crate::{config::Config, state, task, wsl};
+use anyhow::{ensure, Result};
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Minutes between two checks of the task.
+const CHECK_INTERVAL: &str = "5";
+
+pub fn install(config: &Config) -> Result<()> {
+    ensure!(
+        config.daemon.idle_shutdown > 0,
+        "idle shutdown is off; set `daemon.idle_shutdown` to a number of minutes first"
+    );
+
+    let task = task::name(config, "idle check");
+    task::create(
+        config,
+        &task,
+        &["/SC", "MINUTE", "/MO", CHECK_INTERVAL],
+        "idle check",
+    )?;
+    info!(
+        "'{task}' stops '{}' after {} idle minutes",
+        config.distro_name, config.daemon.idle_shutdown
+    );
+
+    Ok(())
+}
+
+pub fn uninstall(config: &Config) -> Result<()> {
+    task::delete(&task::name(config, "idle check"))
+}
+
+/// Whether the running distro has had no docker command for `daemon.idle_shutdown` minutes
+/// and runs no container. Stopped distros are not idle, as there is nothing to stop.
+pub fn is_idle(config: &Config) -> Result<bool> {
+    let timeout = Duration::from_secs(config.daemon.idle_shutdown * 60);
+    if config.daemon.idle_shutdown == 0
+        || !wsl::distro_info(&config.distro_name).is_some_and(|info| info.is_running())
+    {
+        return Ok(false);
+    }
+    if let Some(unused_for) = state::unused_for(config).filter(|unused| *unused < timeout) {
+        debug!("last used {unused_for:?} ago");
+        return Ok(false);
+    }
+
+    let containers = wsl::output_in_wsl(config, &["docker", "ps", "-q"])?;
+    debug!("running containers: {:?}", containers.trim());
+    Ok(containers.trim().is_empty())
+}
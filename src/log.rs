@@ -0,0 +1,75 @@
+//! Logging of the wrapper's own work: messages on stderr according to the verbosity, and
+//! everything down to debug level in a log file rotated daily, for troubleshooting setups.
+
+use crate::config::{data_dir, Verbosity};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::Targets, fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// Environment variable holding the filter of the log file, such as `trace`.
+const LOG_FILTER_ENV: &str = "DOCKER_WRAPPER_LOG";
+
+/// Number of daily log files kept.
+const MAX_LOG_FILES: usize = 7;
+
+/// Whether the verbosity allows progress output, set once by [`init`].
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Whether to draw progress of long operations such as downloads on stderr.
+pub fn show_progress() -> bool {
+    PROGRESS.load(Ordering::Relaxed) && io::stderr().is_terminal()
+}
+
+/// Installs the logger for the rest of the process. A log file that cannot be opened only
+/// disables the file, never the wrapper.
+pub fn init(verbosity: Verbosity) {
+    let stderr_level = match verbosity {
+        Verbosity::Quiet => LevelFilter::ERROR,
+        Verbosity::Normal => LevelFilter::INFO,
+        Verbosity::Verbose => LevelFilter::DEBUG,
+    };
+    PROGRESS.store(verbosity != Verbosity::Quiet, Ordering::Relaxed);
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .with_level(false)
+        // Libraries such as the HTTP client log their internals, which only belong in the file.
+        .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), stderr_level));
+
+    let file = log_file().map(|appender| {
+        let filter = env::var(LOG_FILTER_ENV)
+            .ok()
+            .and_then(|filter| EnvFilter::try_new(filter).ok())
+            .unwrap_or_else(|| EnvFilter::new("debug"));
+        fmt::layer()
+            .with_writer(appender)
+            .with_ansi(false)
+            .with_filter(filter)
+    });
+
+    let _ = tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .try_init();
+}
+
+fn log_file() -> Option<RollingFileAppender> {
+    let dir = data_dir().join("logs");
+    fs::create_dir_all(&dir).ok()?;
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("docker-wrapper")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(dir)
+        .ok()
+}
@@ -1,250 +1,2009 @@
-use anyhow::{ensure, Result};
+mod agent;
+mod args;
+mod bake;
+mod cli;
+mod compose;
+mod config;
+mod console;
+mod context;
+mod credentials;
+mod daemon_config;
+mod disk;
+mod doctor;
+mod download;
+mod expose;
+mod flags;
+mod flavor;
+mod idle;
+mod log;
+mod maintenance;
+mod network;
+mod output;
+mod portproxy;
+mod ports;
+mod preset;
+mod resume;
+mod rootfs;
+mod rootless;
+mod snapshot;
+mod ssh_agent;
+mod state;
+mod status;
+mod task;
+mod vm;
+mod volume;
+mod wsl;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use args::modify_args;
+use cli::{
+    AgentCommand, AutostartCommand, ConfigCommand, ConfigKey, ContextCommand, DaemonConfigCommand,
+    IdleCommand, Invocation, MaintenanceCommand, NetworkCommand, PortsCommand, VmCommand,
+    VolumeCommand, WrapperCli, WrapperCommand, WslConfCommand,
+};
+use config::Config;
+use console::{CtrlCGuard, RawConsole, ResizeWatcher};
+use flags::requests_tty;
+use flavor::Flavor;
+use output::{output_path_style, translate_output};
+use preset::Preset;
+use status::format_size;
 use std::{
     fs,
-    path::PathBuf,
-    process::{Command, Stdio},
+    io::{self, IsTerminal},
+    iter,
+    path::Path,
+    process::{self, Command, ExitCode, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+use wsl::{
+    command_in_wsl_at_cwd, convert_path, output, output_in_wsl, read_json_object_in_wsl,
+    resize_tty_in_wsl, run, run_in_wsl, write_file_in_wsl, RECORD_TTY_SCRIPT,
 };
 
-const DISTRO_ROOTFS_URL: &str =
-    "https://cloud-images.ubuntu.com/wsl/jammy/current/ubuntu-jammy-wsl-amd64-wsl.rootfs.tar.gz";
-const DISTRO_NAME: &str = "custom-docker-host";
+fn ensure_docker(config: &Config) -> Result<()> {
+    if state::daemon_recently_alive(config) {
+        debug!("docker daemon was alive moments ago, skipping the check");
+        return Ok(());
+    }
 
-fn home_dir() -> PathBuf {
-    dirs::home_dir().unwrap_or_else(|| panic!("critical error: failed to get home directory"))
-}
+    let _lock = state::lock_setup(config)?;
+    match completed_setup_steps(config) {
+        Ok(done) if setup_complete(&done) => upgrade_installation_record(config)?,
+        Ok(_) => {
+            info!("resuming the setup of '{}'...", config.distro_name);
+            setup_docker_on_distro(config)?;
+        }
+        Err(_) if wsl::distro_info(&config.distro_name).is_none() => {
+            debug!("'{}' is not registered", config.distro_name);
+            if let Some(previous) = previous_installation(config) {
+                bail!(
+                    "'{}' is not registered, but '{}' set up by an earlier version is; run \
+                     `docker docker-wrapper migrate` to rename it to '{}', `migrate --adopt` to \
+                     keep using it under its name, or `migrate --ignore` to set up a new distro",
+                    config.distro_name,
+                    previous.distro_name,
+                    config.distro_name
+                );
+            }
+            setup_docker_distro(config)?;
+        }
+        Err(err) => {
+            repair(
+                config,
+                err.context(format!("'{}' does not run commands", config.distro_name)),
+            )?;
+        }
+    }
+
+    if resume::resync_clock(config)? && !resume::daemon_responds(config)? {
+        warn!("the docker daemon does not answer after Windows resumed; restarting it...");
+        run_in_wsl(config, &["sh", "-c", &daemon_restart_script(config)], true)?;
+    }
+
+    if network::sync_daemon_proxy(config)? {
+        info!("the proxy settings changed; restarting the docker daemon...");
+        run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+    }
+
+    if let Err(err) = start_daemon(config) {
+        let problem = if docker_installed(config).unwrap_or(false) {
+            err
+        } else {
+            anyhow!("docker is missing from '{}'", config.distro_name)
+        };
+        repair(config, problem)?;
+        start_daemon(config)?;
+    }
+    state::mark_daemon_alive(config);
 
-fn distro_dir_path(name: &str) -> PathBuf {
-    let home = home_dir();
-    home.join("wsl-distros").join(name)
+    Ok(())
 }
 
-fn output(args: &[&str]) -> Result<String> {
-    eprintln!("output: {:?}", args);
-    let mut cmd = Command::new(args[0]);
-    cmd.args(&args[1..]);
-    let output = cmd.output()?;
-    ensure!(output.status.success(), "command failed");
+/// Rewrites the record of a distro imported by an earlier version in the current layout.
+fn upgrade_installation_record(config: &Config) -> Result<()> {
+    let Some(installation) = state::installation(&config.distro_name) else {
+        return Ok(());
+    };
+    if installation.layout < state::LAYOUT {
+        debug!(
+            "upgrading the record of '{}' from layout {}",
+            config.distro_name, installation.layout
+        );
+        state::record_distro_dir(config, &installation.dir)?;
+    }
 
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(())
 }
 
-fn output_in_wsl(args_in_wsl: &[&str]) -> Result<String> {
-    let mut args = vec!["wsl", "-d", DISTRO_NAME, "-e"];
-    args.extend(args_in_wsl);
-    output(&args)
+/// A registered distro recorded for the same profile under another name, which a change of
+/// naming between versions would otherwise leave behind with its images.
+fn previous_installation(config: &Config) -> Option<state::Installation> {
+    state::installations().into_iter().find(|installation| {
+        installation.distro_name != config.distro_name
+            && installation.profile == config.profile
+            && wsl::distro_info(&installation.distro_name).is_some()
+    })
 }
 
-fn run(args: &[&str], silent: bool) -> Result<bool> {
-    eprintln!("run: {:?}", args);
-    let (stdout, stderr) = if silent {
-        (Stdio::null(), Stdio::null())
-    } else {
-        (Stdio::inherit(), Stdio::inherit())
+/// Carries the installation found by `previous_installation` over: renamed to the configured
+/// name, adopted under its own name, or forgotten so that a new distro is set up.
+fn migrate(config: &Config, config_path: Option<&Path>, adopt: bool, ignore: bool) -> Result<()> {
+    let previous = previous_installation(config)
+        .context("no installation of an earlier version to migrate")?;
+    let old = Config {
+        distro_name: previous.distro_name.clone(),
+        ..config.clone()
     };
 
-    let mut cmd = Command::new(args[0]);
-    cmd.args(&args[1..]).stdout(stdout).stderr(stderr);
-    let status = cmd.spawn()?.wait()?;
+    if ignore {
+        state::forget_distro_dir(&old);
+        info!(
+            "'{}' is left alone; the next command sets '{}' up",
+            old.distro_name, config.distro_name
+        );
+    } else if adopt {
+        Config::set_in_file(
+            config_path,
+            config.profile.as_deref(),
+            &["distro_name"],
+            old.distro_name.as_str(),
+        )?;
+        state::record_distro_dir(&old, &previous.dir)?;
+        info!("using '{}' as it is", old.distro_name);
+    } else {
+        rename_distro(&old, config_path, &config.distro_name)?;
+    }
 
-    Ok(status.success())
+    Ok(())
 }
 
-fn run_in_wsl(args_in_wsl: &[&str], silent: bool) -> Result<bool> {
-    let mut args = vec!["wsl", "-d", DISTRO_NAME, "-e"];
-    args.extend(args_in_wsl);
-    run(&args, silent)
+/// Starts the daemon, then runs the start hooks of the presets enabled in the distro.
+fn start_daemon(config: &Config) -> Result<()> {
+    let mut script = format!(
+        "{}; status=$?; \
+         for hook in {}/*; do [ -x \"$hook\" ] && \"$hook\"; done; exit $status",
+        daemon_start_script(config),
+        preset::START_HOOKS_DIR
+    );
+    if let Some(mtu) = config.network.mtu {
+        script = format!("{}; {script}", network::mtu_script(mtu));
+    }
+    let started = run_in_wsl(config, &["sh", "-c", &script], true)?;
+    debug!(started, "started the docker daemon");
+    wait_for_daemon(config)
+}
+
+/// Deals with a registered distro that does not come up. With `daemon.auto_repair` it is
+/// rebuilt, keeping the docker data if it can still be read; otherwise the problem is
+/// reported along with the commands that fix it.
+fn repair(config: &Config, problem: anyhow::Error) -> Result<()> {
+    if !config.daemon.auto_repair {
+        bail!(
+            "{problem:#}\n'{}' looks broken; run `docker docker-wrapper reprovision` to rebuild \
+             it keeping images and volumes, `docker docker-wrapper reset-registration` to start \
+             over, or pass --auto-repair to let the wrapper decide",
+            config.distro_name
+        );
+    }
+
+    warn!("{problem:#}; repairing '{}'...", config.distro_name);
+    let has_data = &["test", "-d", "/var/lib/docker", "-a", "-d", "/etc/docker"];
+    if run_in_wsl(config, has_data, true).unwrap_or(false) {
+        return rebuild_distro(config);
+    }
+
+    warn!(
+        "the data of '{}' cannot be saved; setting it up from scratch",
+        config.distro_name
+    );
+    state::forget_daemon_alive(config);
+    run(&["wsl", "--unregister", &config.distro_name], true)?;
+    state::forget_distro_dir(config);
+    setup_docker_distro(config)
 }
 
-fn ensure_docker() -> Result<()> {
-    if !run_in_wsl(&["which", "docker"], true)? {
-        setup_docker_distro()?;
+/// Whether docker is installed in the distro. `which` is missing from some flavors.
+fn docker_installed(config: &Config) -> Result<bool> {
+    run_in_wsl(config, &["sh", "-c", "command -v docker"], true)
+}
+
+/// Shell script starting the daemon of the configured mode, doing nothing if it already runs.
+fn daemon_start_script(config: &Config) -> String {
+    match config.daemon.rootless {
+        true => rootless::start_script(),
+        false => config.flavor.start_script(),
     }
-    run_in_wsl(&["/sbin/service", "docker", "start"], true)?;
+}
+
+fn daemon_stop_script(config: &Config) -> String {
+    match config.daemon.rootless {
+        true => rootless::stop_script(),
+        false => config.flavor.stop_script(),
+    }
+}
+
+fn daemon_restart_script(config: &Config) -> String {
+    match config.daemon.rootless {
+        true => rootless::restart_script(),
+        false => config.flavor.restart_script(),
+    }
+}
+
+/// Restarts the daemon to apply a changed `daemon.json`, waiting until it is back.
+fn restart_daemon(config: &Config) -> Result<()> {
+    state::forget_daemon_alive(config);
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", &daemon_restart_script(config)], true)?,
+        "failed to restart the docker daemon; see `docker docker-wrapper logs`"
+    );
+    wait_for_daemon(config)?;
+    state::mark_daemon_alive(config);
 
     Ok(())
 }
 
-fn setup_docker_distro() -> Result<()> {
-    eprintln!("setup Ubuntu 22.04 from '{}'...", DISTRO_ROOTFS_URL);
-    download_and_import_rootfs()?;
+/// Polls the daemon with exponential backoff until it accepts connections. Starting the
+/// service returns before dockerd listens, so the first command after a cold boot would fail.
+fn wait_for_daemon(config: &Config) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(config.daemon.start_timeout);
+    let mut delay = Duration::from_millis(50);
+    loop {
+        if run_in_wsl(config, &["docker", "version"], true)? {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            bail!(
+                "docker daemon did not become ready within {}s; see `docker docker-wrapper logs`",
+                config.daemon.start_timeout
+            );
+        }
 
-    eprintln!("setup docker engine...");
-    setup_docker_on_distro()?;
+        debug!("docker daemon is not ready yet, retrying in {delay:?}");
+        thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(Duration::from_secs(2));
+    }
+}
+
+fn setup_docker_distro(config: &Config) -> Result<()> {
+    let tarball = match &config.rootfs_file {
+        Some(file) => {
+            info!("setup {} from '{}'...", config.flavor, file.display());
+            rootfs::validate(file)?;
+            rootfs::check_free_space(config, None, 0)?;
+            if let Some(hash) = &config.rootfs_sha256 {
+                rootfs::verify(file, &hash.to_ascii_lowercase())?;
+            }
+            file.clone()
+        }
+        None => {
+            let rootfs_url = flavor::rootfs_url(config)?;
+            info!("setup {} from '{rootfs_url}'...", config.flavor);
+            rootfs::check_free_space(config, Some(&rootfs_url), 0)?;
+            rootfs::download(config, &rootfs_url)?
+        }
+    };
+    rootfs::import(config, &tarball)?;
+    if config.rootfs_file.is_none() && !config.keep_rootfs {
+        debug!("deleting '{}'", tarball.display());
+        fs::remove_file(&tarball)?;
+    }
+
+    info!("setup docker engine...");
+    setup_docker_on_distro(config)?;
 
     Ok(())
 }
 
-fn download_and_import_rootfs() -> Result<()> {
-    // TODO
-    let path = distro_dir_path(DISTRO_NAME);
-    let distro_root_path = path.join("root");
-    let download_path = path.join("rootfs.tar.gz");
+/// File in the distro listing the setup steps completed so far, one per line.
+const SETUP_STATE: &str = "/var/lib/docker-wrapper/setup-steps";
 
-    fs::create_dir_all(&distro_root_path)?;
+type SetupStep = fn(&Config) -> Result<()>;
 
-    if !download_path.exists() {
+/// Steps provisioning an imported distro, in order. Each is recorded in `SETUP_STATE` once
+/// done, so that a setup that failed halfway resumes at the step that failed.
+const SETUP_STEPS: &[(&str, SetupStep)] = &[
+    ("network", configure_network),
+    ("engine", install_engine),
+    ("user", create_user),
+    ("plugins", install_plugins),
+    ("rootless", configure_rootless),
+    ("systemd", configure_systemd),
+    ("supervision", configure_supervision),
+    ("detach-keys", configure_detach_keys),
+    ("daemon-json", write_daemon_json),
+];
+
+fn setup_docker_on_distro(config: &Config) -> Result<()> {
+    let done = completed_setup_steps(config)?;
+    for (name, step) in SETUP_STEPS {
+        if done.iter().any(|done| done == name) {
+            debug!("setup step '{name}' is done already");
+            continue;
+        }
+
+        debug!("running setup step '{name}'");
+        step(config)?;
+        let script =
+            format!("mkdir -p \"$(dirname {SETUP_STATE})\" && echo {name} >>{SETUP_STATE}");
         ensure!(
-            run(
-                &[
-                    "curl",
-                    "-L",
-                    DISTRO_ROOTFS_URL,
-                    "-o",
-                    &download_path.display().to_string(),
-                ],
-                false,
-            )?,
-            "failed to download rootfs"
+            run_in_wsl(config, &["sh", "-c", &script], true)?,
+            "failed to record setup step '{name}'"
+        );
+    }
+
+    Ok(())
+}
+
+/// Setup steps recorded as done in the distro. Distros set up before the steps were tracked
+/// count as complete when they have docker, and keep running docker as root.
+fn completed_setup_steps(config: &Config) -> Result<Vec<String>> {
+    let script = format!(
+        "cat {SETUP_STATE} 2>/dev/null || {{ command -v docker >/dev/null && echo legacy; }} || true"
+    );
+    let state = output_in_wsl(config, &["sh", "-c", &script])?;
+    if state.trim() == "legacy" {
+        return Ok(SETUP_STEPS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect());
+    }
+
+    Ok(state.lines().map(str::to_string).collect())
+}
+
+fn setup_complete(done: &[String]) -> bool {
+    SETUP_STEPS
+        .iter()
+        .all(|(name, _)| done.iter().any(|done| done == name))
+}
+
+fn configure_network(config: &Config) -> Result<()> {
+    network::configure_distro(config, &network::Proxy::resolve(config))
+}
+
+fn install_engine(config: &Config) -> Result<()> {
+    match &config.engine_debs {
+        Some(dir) => install_engine_debs(config, dir)?,
+        None => install_engine_online(config)?,
+    }
+
+    if let Some(expected) = &config.docker_version {
+        let installed = output_in_wsl(
+            config,
+            &["docker", "version", "--format", "{{.Client.Version}}"],
+        )?;
+        let installed = installed.trim();
+        ensure!(
+            version_matches(installed, expected),
+            "installed docker {installed}, but docker_version is {expected}"
         );
     }
 
+    Ok(())
+}
+
+fn install_engine_online(config: &Config) -> Result<()> {
+    let proxy_env: Vec<_> = network::Proxy::resolve(config)
+        .env()
+        .into_iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    let script = config
+        .flavor
+        .install_script(config.docker_version.as_deref());
+    let mut install = vec!["env"];
+    install.extend(proxy_env.iter().map(String::as_str));
+    install.extend(["sh", "-c", &script]);
+    // The scripts piping curl into sh succeed even when the download fails.
+    ensure!(
+        run_in_wsl(config, &install, false)? && docker_installed(config)?,
+        "failed to install docker engine"
+    );
+
+    Ok(())
+}
+
+/// Installs the engine from the `.deb` packages in `dir` on the Windows side, for machines
+/// without internet access. The packages of docker-ce, its CLI and containerd are copied into
+/// the distro and installed with dpkg, so any dependency the rootfs lacks has to be among them.
+fn install_engine_debs(config: &Config, dir: &Path) -> Result<()> {
+    ensure!(
+        matches!(config.flavor, Flavor::Ubuntu | Flavor::Debian),
+        "engine_debs needs a flavor that uses dpkg, not {}",
+        config.flavor
+    );
+    let has_debs = fs::read_dir(dir)
+        .with_context(|| format!("failed to read engine_debs '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.path().extension().is_some_and(|ext| ext == "deb"));
+    ensure!(has_debs, "no .deb packages in '{}'", dir.display());
+
+    info!("installing docker engine from '{}'...", dir.display());
+    let dir_in_wsl = convert_path(config, &dir.display().to_string())?;
+    let script = "rm -rf /tmp/docker-wrapper-debs && mkdir -p /tmp/docker-wrapper-debs \
+                  && cp \"$1\"/*.deb /tmp/docker-wrapper-debs/ \
+                  && dpkg -i /tmp/docker-wrapper-debs/*.deb; status=$?; \
+                  rm -rf /tmp/docker-wrapper-debs; exit $status";
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", script, "sh", &dir_in_wsl], false)?
+            && docker_installed(config)?,
+        "failed to install docker engine from '{}'",
+        dir.display()
+    );
+
+    Ok(())
+}
+
+/// Whether `version` is `prefix` or a release of it, so that `27.3` matches `27.3.1` but
+/// not `27.30.0`.
+fn version_matches(version: &str, prefix: &str) -> bool {
+    version
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '-', '+']))
+}
+
+/// Creates `distro_user` in the docker group and makes the user docker runs as the default
+/// user of the distro, which wrapped commands run as. A client config that earlier versions
+/// wrote for root is carried over. The distro is terminated for the default to apply.
+fn create_user(config: &Config) -> Result<()> {
+    let user = &config.distro_user;
+    let script = format!(
+        "id -u {user} >/dev/null 2>&1 || useradd -m -s /bin/sh {user} || adduser -D -s /bin/sh {user} || exit 1; \
+         (usermod -aG docker {user} || addgroup {user} docker) && \
+         if [ -f /root/.docker/config.json ] && [ ! -f /home/{user}/.docker/config.json ]; then \
+             mkdir -p /home/{user}/.docker && cp /root/.docker/config.json /home/{user}/.docker/ \
+             && chown -R {user}: /home/{user}/.docker; \
+         fi"
+    );
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", &script], true)?,
+        "failed to create user '{user}'"
+    );
+    wsl::set_wsl_conf(config, "user", "default", wsl::docker_user(config))?;
+    wsl::terminate(config)
+}
+
+/// Boots the distro with systemd and lets it start dockerd, when `daemon.systemd` is on and
+/// the rootfs has systemd. The rootless daemon is started by the wrapper either way, so the
+/// unit of the root daemon is disabled in that mode.
+fn configure_systemd(config: &Config) -> Result<()> {
+    if !config.daemon.systemd {
+        return Ok(());
+    }
+    let has_systemd = output_in_wsl(
+        config,
+        &[
+            "sh",
+            "-c",
+            "[ -x /lib/systemd/systemd ] || [ -x /usr/lib/systemd/systemd ] && echo yes || true",
+        ],
+    )?;
+    if has_systemd.trim() != "yes" {
+        info!("the rootfs has no systemd; dockerd is run by its init scripts");
+        return Ok(());
+    }
+
+    let units = match config.daemon.rootless {
+        true => "systemctl disable docker.service docker.socket",
+        false => "systemctl enable docker.service containerd.service",
+    };
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", units], true)?,
+        "failed to configure the docker units of systemd"
+    );
+    wsl::set_wsl_conf(config, "boot", "systemd", "true")?;
+    wsl::terminate(config)
+}
+
+/// Has systemd restart a dockerd that crashed, a few times in a row at most so that a daemon
+/// failing on its config is not restarted forever. Without systemd the wrapper restarts the
+/// daemon when a command finds it gone.
+fn configure_supervision(config: &Config) -> Result<()> {
+    let drop_in = "[Unit]\n\
+                   StartLimitIntervalSec=60\n\
+                   StartLimitBurst=5\n\
+                   \n\
+                   [Service]\n\
+                   Restart=on-failure\n\
+                   RestartSec=2";
+    ensure!(
+        write_file_in_wsl(
+            config,
+            "/etc/systemd/system/docker.service.d/docker-wrapper.conf",
+            drop_in
+        )?,
+        "failed to configure the restart of dockerd"
+    );
+    let reload = format!("! {} || systemctl daemon-reload", flavor::SYSTEMD_RUNNING);
+    run_in_wsl(config, &["sh", "-c", &reload], true)?;
+
+    Ok(())
+}
+
+/// Makes sure `docker compose` and `docker buildx` work, installing the plugins unless the
+/// engine came with them.
+fn install_plugins(config: &Config) -> Result<()> {
+    let works = || -> Result<bool> {
+        Ok(run_in_wsl(config, &["docker", "compose", "version"], true)?
+            && run_in_wsl(config, &["docker", "buildx", "version"], true)?)
+    };
+    if works()? {
+        return Ok(());
+    }
+
+    info!("installing the compose and buildx plugins...");
+    let installed = run_in_wsl(config, &["sh", "-c", config.flavor.plugins_script()], false)?;
+    ensure!(
+        installed && works()?,
+        if config.engine_debs.is_some() {
+            "failed to install the compose and buildx plugins; add their packages to engine_debs"
+        } else {
+            "failed to install the compose and buildx plugins"
+        }
+    );
+
+    Ok(())
+}
+
+/// Provisions the rootless daemon when the distro is set up in that mode. The mode is fixed
+/// at setup; changing it takes a reprovision.
+fn configure_rootless(config: &Config) -> Result<()> {
+    if config.daemon.rootless {
+        info!("setting up rootless docker...");
+        rootless::provision(config)?;
+    }
+
+    Ok(())
+}
+
+/// Merges the wrapper's settings into `daemon.json`, keeping whatever else it holds.
+fn write_daemon_json(config: &Config) -> Result<()> {
+    let daemon = &config.daemon;
+    network::sync_daemon_proxy(config)?;
+    daemon_config::update(config, |daemon_json| {
+        daemon_config::set(daemon_json, "features.buildkit", daemon.buildkit.into());
+        daemon_json.insert("log-driver".into(), daemon.log_driver.clone().into());
+        let rotated = matches!(&*daemon.log_driver, "json-file" | "local");
+        if rotated && !daemon.log_max_size.is_empty() {
+            daemon_config::set(
+                daemon_json,
+                "log-opts.max-size",
+                daemon.log_max_size.clone().into(),
+            );
+            // The daemon only takes strings in `log-opts`.
+            daemon_config::set(
+                daemon_json,
+                "log-opts.max-file",
+                daemon.log_max_file.to_string().into(),
+            );
+        }
+        if let Some(mtu) = config.network.mtu {
+            network::set_daemon_mtu(daemon_json, mtu);
+        }
+        if !daemon.address_pools.is_empty() {
+            let pools = serde_json::to_value(&daemon.address_pools).unwrap_or_default();
+            daemon_json.insert("default-address-pools".into(), pools);
+        }
+        for (name, values) in [
+            ("registry-mirrors", &daemon.registry_mirrors),
+            ("insecure-registries", &daemon.insecure_registries),
+            ("dns", &network::daemon_dns(config)),
+        ] {
+            if !values.is_empty() {
+                daemon_json.insert(name.into(), values.clone().into());
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Sets a list in `daemon.json` of an installed distro, removing it when empty, and restarts
+/// the daemon if that changed anything.
+fn set_daemon_list(config: &Config, name: &str, values: &[String]) -> Result<()> {
+    if !docker_installed(config)? {
+        return Ok(());
+    }
+
+    let changed = daemon_config::update(config, |daemon_json| {
+        match values.is_empty() {
+            true => daemon_json.remove(name),
+            false => daemon_json.insert(name.into(), values.into()),
+        };
+    })?;
+    if let Some(before) = changed {
+        restart_daemon_with(config, before)?;
+    }
+
+    Ok(())
+}
+
+/// Applies an edit of `daemon.json` by restarting the daemon. If it does not come back with
+/// the new settings, `before` is written back so that the daemon is left working.
+fn restart_daemon_with(
+    config: &Config,
+    before: serde_json::Map<String, serde_json::Value>,
+) -> Result<()> {
+    let Err(err) = restart_daemon(config) else {
+        return Ok(());
+    };
+    warn!("the daemon did not start with the new settings; restoring the previous ones");
+    daemon_config::write(config, &before)?;
+    restart_daemon(config)?;
+
+    Err(err.context(format!(
+        "the setting was rejected and {} was restored",
+        daemon_config::PATH
+    )))
+}
+
+/// Stops the daemon and the distro if they are idle. The setup lock keeps a command that is
+/// starting the daemon from having it stopped under it.
+fn shut_down_if_idle(config: &Config) -> Result<()> {
+    let _lock = state::lock_setup(config)?;
+    if !idle::is_idle(config)? {
+        return Ok(());
+    }
+
+    info!("'{}' is idle; stopping it", config.distro_name);
+    state::forget_daemon_alive(config);
+    agent::stop(config);
+    run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+    wsl::terminate(config)
+}
+
+/// Enables what was asked for among the sparse disk and the memory reclaim, skipping with a
+/// warning what the installed WSL does not support.
+fn optimize(
+    config: &Config,
+    sparse: bool,
+    memory_reclaim: Option<vm::MemoryReclaim>,
+) -> Result<()> {
+    let version = wsl::wsl_version().unwrap_or_default();
+    let supports = |since: &[u32]| version.as_slice() >= since;
+
+    if let Some(mode) = memory_reclaim {
+        if supports(vm::MEMORY_RECLAIM_VERSION) {
+            vm::set(
+                "experimental",
+                &[("autoMemoryReclaim".into(), mode.as_str().into())],
+            )?;
+            info!(
+                "memory reclaim is {}; it applies after `wsl --shutdown`",
+                mode.as_str()
+            );
+        } else {
+            warn!("memory reclaim needs WSL 1.3.10 or later; run `wsl --update`");
+        }
+    }
+
+    if sparse {
+        if supports(vm::SPARSE_VERSION) {
+            ensure!(
+                wsl::distro_info(&config.distro_name).is_some(),
+                "'{}' is not set up yet",
+                config.distro_name
+            );
+            // The disk cannot be changed while the distro runs.
+            if wsl::distro_info(&config.distro_name).is_some_and(|info| info.is_running()) {
+                state::forget_daemon_alive(config);
+                run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+                wsl::terminate(config)?;
+            }
+            ensure!(
+                run(
+                    &[
+                        "wsl",
+                        "--manage",
+                        &config.distro_name,
+                        "--set-sparse",
+                        "true"
+                    ],
+                    false
+                )?,
+                "failed to make the disk of '{}' sparse",
+                config.distro_name
+            );
+            info!("the disk of '{}' is sparse", config.distro_name);
+        } else {
+            warn!("sparse disks need WSL 2.0.0 or later; run `wsl --update`");
+        }
+    }
+
+    Ok(())
+}
+
+/// Exports the distro into `path` with the daemon stopped, so that its data is consistent.
+/// The caller holds the setup lock.
+fn export_distro(config: &Config, path: &Path) -> Result<()> {
+    info!(
+        "exporting '{}' to '{}'...",
+        config.distro_name,
+        path.display()
+    );
+    state::forget_daemon_alive(config);
+    agent::stop(config);
+    run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
     ensure!(
         run(
             &[
                 "wsl",
-                "--import",
-                DISTRO_NAME,
-                &distro_root_path.display().to_string(),
-                &download_path.display().to_string()
+                "--export",
+                &config.distro_name,
+                &path.display().to_string()
             ],
-            false,
+            false
         )?,
-        "failed to import distro"
+        "failed to export '{}'",
+        config.distro_name
     );
 
     Ok(())
 }
 
-fn setup_docker_on_distro() -> Result<()> {
+/// Saves the distro with its images, containers and volumes into `file`, along with the
+/// metadata `restore` goes by.
+fn backup(config: &Config, file: &Path) -> Result<()> {
     ensure!(
-        run_in_wsl(
-            &["sh", "-c", "curl -fsSL https://get.docker.com/ | sh"],
-            false
-        )?,
-        "failed to install docker engine"
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; nothing to back up",
+        config.distro_name
     );
+    let docker_version = output_in_wsl(
+        config,
+        &["docker", "version", "--format", "{{.Client.Version}}"],
+    )
+    .ok()
+    .map(|version| version.trim().to_string());
+
+    let _lock = state::lock_setup(config)?;
+    export_distro(config, file)?;
+    snapshot::write_metadata(file, &snapshot::Metadata::new(config, docker_version))?;
+    info!("backed '{}' up to '{}'", config.distro_name, file.display());
 
+    Ok(())
+}
+
+/// Imports the snapshot in `file` as the distro, replacing the one registered if `replace`
+/// allows it. The flavor and mode the snapshot ran with are stored in the config, as the
+/// wrapper drives the daemon differently for each.
+fn restore(current: &Config, config_path: Option<&Path>, file: &Path, replace: bool) -> Result<()> {
+    ensure!(file.is_file(), "'{}' does not exist", file.display());
+    let metadata = snapshot::read_metadata(file)?;
+    let profile = current.profile.as_deref();
+    let mut config = current.clone();
+    match &metadata {
+        Some(metadata) => {
+            info!(
+                "restoring '{}' taken from '{}' with docker {}",
+                file.display(),
+                metadata.distro_name,
+                metadata
+                    .docker_version
+                    .as_deref()
+                    .unwrap_or("of unknown version")
+            );
+            // The config only follows the snapshot once it is imported.
+            config.flavor = metadata.flavor;
+            config.daemon.rootless = metadata.rootless;
+        }
+        None => warn!(
+            "'{}' has no metadata; restoring it as a {} distro",
+            file.display(),
+            config.flavor
+        ),
+    }
+
+    let _lock = state::lock_setup(&config)?;
+    if wsl::distro_info(&config.distro_name).is_some() {
+        ensure!(
+            replace,
+            "'{}' exists; pass --replace to overwrite it with the snapshot",
+            config.distro_name
+        );
+        state::forget_daemon_alive(&config);
+        agent::stop(&config);
+        run(&["wsl", "--unregister", &config.distro_name], true)?;
+        state::forget_distro_dir(&config);
+    }
+    rootfs::check_free_space(&config, None, fs::metadata(file)?.len())?;
+    rootfs::import(&config, file)?;
+    if let Some(metadata) = &metadata {
+        if metadata.flavor != current.flavor {
+            Config::set_in_file(config_path, profile, &["flavor"], metadata.flavor.name())?;
+        }
+        if metadata.rootless != current.daemon.rootless {
+            Config::set_in_file(
+                config_path,
+                profile,
+                &["daemon", "rootless"],
+                metadata.rootless,
+            )?;
+        }
+    }
+    info!("restored '{}'", config.distro_name);
+
+    Ok(())
+}
+
+/// Moves the distro under `storage_dir`: it is exported next to where it goes, so that a full
+/// drive it leaves is not needed for the copy, then imported there in place of the old one.
+fn move_distro(config: &Config, storage_dir: &Path) -> Result<()> {
+    ensure!(
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; set `storage_dir` instead",
+        config.distro_name
+    );
+    let moved = Config {
+        storage_dir: storage_dir.to_path_buf(),
+        ..config.clone()
+    };
+    let old_dir = state::recorded_distro_dir(config).unwrap_or_else(|| config.distro_dir());
+    ensure!(
+        moved.distro_dir() != old_dir,
+        "'{}' is in '{}' already",
+        config.distro_name,
+        old_dir.display()
+    );
+    // The export and the disk imported from it are both there until the export is deleted.
+    let size = disk::vhdx_size(config).unwrap_or(0);
+    rootfs::check_free_space(&moved, None, 2 * size)?;
+
+    let _lock = state::lock_setup(config)?;
+    fs::create_dir_all(moved.distro_dir())?;
+    let export = moved.distro_dir().join("move.tar");
+    export_distro(config, &export)?;
+
+    run(&["wsl", "--unregister", &config.distro_name], true)?;
+    rootfs::import(&moved, &export).with_context(|| {
+        format!(
+            "the distro is saved in '{}'; import it with `wsl --import`",
+            export.display()
+        )
+    })?;
+    fs::remove_file(&export)?;
+    info!(
+        "moved '{}' to '{}'; '{}' only holds cached downloads now",
+        config.distro_name,
+        moved.distro_dir().display(),
+        old_dir.display()
+    );
+
+    Ok(())
+}
+
+/// Copies the distro into a new `profile` of the config, through an export next to where the
+/// copy goes.
+fn clone_distro(config: &Config, config_path: Option<&Path>, profile: &str) -> Result<()> {
+    ensure!(
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; nothing to clone",
+        config.distro_name
+    );
+    Config::copy_profile(config_path, config.profile.as_deref(), profile)?;
+    let clone = Config {
+        skip_disk_check: config.skip_disk_check,
+        ..Config::load(config_path, Some(profile))?
+    };
+    ensure!(
+        wsl::distro_info(&clone.distro_name).is_none(),
+        "'{}' is registered already",
+        clone.distro_name
+    );
+    let size = disk::vhdx_size(config).unwrap_or(0);
+    rootfs::check_free_space(&clone, None, 2 * size)?;
+
+    let lock = state::lock_setup(config)?;
+    fs::create_dir_all(clone.distro_dir())?;
+    let export = clone.distro_dir().join("clone.tar");
+    export_distro(config, &export)?;
+    drop(lock);
+    rootfs::import(&clone, &export)?;
+    fs::remove_file(&export)?;
+    info!(
+        "cloned '{}' into '{}'; use it with `--profile {profile}`",
+        config.distro_name, clone.distro_name
+    );
+
+    Ok(())
+}
+
+/// Imports a copy of the distro under `new_name` and switches the config over to it. The old
+/// distro is unregistered only after docker started in the new one; if it does not, the copy
+/// is removed instead.
+fn rename_distro(config: &Config, config_path: Option<&Path>, new_name: &str) -> Result<()> {
+    ensure!(
+        !new_name.is_empty()
+            && new_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)),
+        "invalid distro name '{new_name}'"
+    );
+    ensure!(
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; set `distro_name` instead",
+        config.distro_name
+    );
+    ensure!(
+        wsl::distro_info(new_name).is_none(),
+        "'{new_name}' is registered already"
+    );
+    let renamed = Config {
+        distro_name: new_name.to_string(),
+        ..config.clone()
+    };
+    let size = disk::vhdx_size(config).unwrap_or(0);
+    rootfs::check_free_space(&renamed, None, 2 * size)?;
+
+    let lock = state::lock_setup(config)?;
+    fs::create_dir_all(renamed.distro_dir())?;
+    let export = renamed.distro_dir().join("rename.tar");
+    export_distro(config, &export)?;
+    rootfs::import(&renamed, &export)?;
+    fs::remove_file(&export)?;
+    drop(lock);
+    if let Err(err) = ensure_docker(&renamed) {
+        run(&["wsl", "--unregister", new_name], true)?;
+        state::forget_distro_dir(&renamed);
+        return Err(err.context(format!(
+            "docker does not start in '{new_name}', which was removed again; '{}' is kept as it was",
+            config.distro_name
+        )));
+    }
+
+    Config::set_in_file(
+        config_path,
+        config.profile.as_deref(),
+        &["distro_name"],
+        new_name,
+    )?;
+    state::forget_daemon_alive(config);
+    run(&["wsl", "--unregister", &config.distro_name], true)?;
+    state::forget_distro_dir(config);
+    info!("renamed '{}' to '{new_name}'", config.distro_name);
+
+    Ok(())
+}
+
+/// Removes the distro and what the wrapper created for it on the Windows side.
+fn uninstall(config: &Config, yes: bool) -> Result<()> {
+    ensure!(
+        yes,
+        "this deletes '{}' with all its images, containers and volumes; pass --yes to go ahead",
+        config.distro_name
+    );
+
+    if let Err(err) = portproxy::remove_all(config) {
+        warn!("{err:#}; remove the rules left with `netsh interface portproxy delete`");
+    }
+    let adopted =
+        state::installation(&config.distro_name).is_some_and(|installation| installation.adopted);
+
+    let _lock = state::lock_setup(config)?;
+    agent::stop(config);
+    if adopted {
+        info!(
+            "'{}' was adopted rather than set up by the wrapper, so it is left registered",
+            config.distro_name
+        );
+    } else {
+        if wsl::distro_info(&config.distro_name).is_some() {
+            info!("unregistering '{}'...", config.distro_name);
+            run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+            ensure!(
+                run(&["wsl", "--unregister", &config.distro_name], true)?,
+                "failed to unregister '{}'",
+                config.distro_name
+            );
+        }
+        for dir in iter::once(config.distro_dir()).chain(state::recorded_distro_dir(config)) {
+            if dir.exists() {
+                fs::remove_dir_all(&dir)
+                    .with_context(|| format!("failed to delete '{}'", dir.display()))?;
+            }
+        }
+    }
+    // Tasks that were never registered fail to be deleted, which is fine.
+    for purpose in ["maintenance", "idle check", "autostart"] {
+        let _ = task::delete(&task::name(config, purpose));
+    }
+    for name in context::remove_created()? {
+        info!("removed docker context '{name}'");
+    }
+    let cert_dir = expose::tls_cert_dir(config);
+    if cert_dir.exists() {
+        fs::remove_dir_all(&cert_dir)?;
+        info!(
+            "removed the TLS certificates; environment variables stored with `enable --persist` \
+             and ~/.testcontainers.properties still point at them"
+        );
+    }
+    state::forget_distro(config);
+    info!("uninstalled '{}'", config.distro_name);
+
+    Ok(())
+}
+
+/// Makes the distro `name`, which has docker installed already, the docker host of the
+/// config instead of setting one up. Its flavor is told from its `os-release`.
+fn adopt(config: &Config, config_path: Option<&Path>, name: &str) -> Result<()> {
+    let info = wsl::distro_info(name).with_context(|| format!("'{name}' is not registered"))?;
+    ensure!(
+        info.version == "2",
+        "'{name}' runs on WSL {}; docker needs WSL 2",
+        info.version
+    );
+    let adopted = Config {
+        distro_name: name.to_string(),
+        ..config.clone()
+    };
+    let os_release = output_in_wsl(&adopted, &["sh", "-c", ". /etc/os-release && echo $ID"])?;
+    let flavor: Flavor = os_release
+        .trim()
+        .parse()
+        .with_context(|| format!("'{name}' is not a distro the wrapper supports"))?;
+    ensure!(
+        output_in_wsl(&adopted, &["sh", "-c", "command -v docker"]).is_ok(),
+        "docker is not installed in '{name}'"
+    );
+
+    let profile = config.profile.as_deref();
+    Config::set_in_file(config_path, profile, &["distro_name"], name)?;
+    Config::set_in_file(config_path, profile, &["flavor"], flavor.name())?;
+    let adopted = Config::load(config_path, profile)?;
+    let dir = wsl::distro_base_path(name).unwrap_or_else(|| adopted.distro_dir());
+    state::record_adopted(&adopted, &dir)?;
+    ensure_docker(&adopted)?;
+
+    // Commands run as the default user of the distro, which needs access to the socket.
+    if output(&["wsl", "-d", name, "-e", "docker", "version"]).is_err() {
+        warn!(
+            "the default user of '{name}' cannot reach the daemon; add it to the docker group \
+             with `sudo usermod -aG docker $USER`"
+        );
+    }
+    info!("'{name}' is the docker host now");
+
+    Ok(())
+}
+
+/// Trims the filesystem of the distro so that its free blocks can be dropped, then compacts
+/// the virtual disk with WSL shut down, as the disk stays attached until then.
+fn compact(config: &Config) -> Result<()> {
+    ensure!(
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; nothing to compact",
+        config.distro_name
+    );
+    let vhdx = disk::vhdx_path(config);
+    let before = disk::vhdx_size(config)
+        .with_context(|| format!("the disk '{}' is missing", vhdx.display()))?;
+
+    run_in_wsl(config, &["sh", "-c", "fstrim -a || true"], true)?;
+    state::forget_daemon_alive(config);
+    run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+    agent::stop(config);
+    info!("shutting WSL down and compacting '{}'...", vhdx.display());
+    run(&["wsl", "--shutdown"], true)?;
+    disk::compact_vhdx(&vhdx)?;
+
+    let after = disk::vhdx_size(config).unwrap_or(before);
+    info!(
+        "'{}' went from {} to {}, {} reclaimed",
+        vhdx.display(),
+        format_size(before),
+        format_size(after),
+        format_size(before.saturating_sub(after))
+    );
+
+    Ok(())
+}
+
+/// Moves an installed distro onto a freshly downloaded base of the configured flavor and
+/// release, carrying images, containers, volumes and `/etc/docker` over.
+fn reprovision(config: &Config) -> Result<()> {
+    ensure!(
+        wsl::distro_info(&config.distro_name).is_some(),
+        "'{}' is not set up yet; nothing to reprovision",
+        config.distro_name
+    );
+    let lock = state::lock_setup(config)?;
+    rebuild_distro(config)?;
+    drop(lock);
+    ensure_docker(config)
+}
+
+/// Replaces the distro with a fresh one, saving the docker data beforehand and restoring it
+/// afterwards. The caller holds the setup lock.
+fn rebuild_distro(config: &Config) -> Result<()> {
+    ensure!(
+        !state::installation(&config.distro_name).is_some_and(|installation| installation.adopted),
+        "'{}' was adopted; the wrapper does not rebuild distros it did not set up",
+        config.distro_name
+    );
+    // Resolved up front, so that an unreachable image index fails before anything is touched.
+    let rootfs_url = match config.rootfs_file {
+        Some(_) => None,
+        None => Some(flavor::rootfs_url(config)?),
+    };
+    let stale_download = rootfs_url
+        .as_ref()
+        .map(|url| rootfs::download_path(config, url));
+    // The data is both in the backup and in the new distro once it is restored.
+    let data_size = output_in_wsl(config, &["du", "-skx", "/var/lib/docker"])
+        .ok()
+        .and_then(|du| du.split_whitespace().next()?.parse::<u64>().ok())
+        .unwrap_or(0)
+        * 1024;
+    rootfs::check_free_space(config, rootfs_url.as_deref(), 2 * data_size)?;
+
+    // The backup lives next to the distro on the Windows side, outside of what is replaced.
+    let backup = config.distro_dir().join("reprovision.tar");
+    let backup_in_wsl = convert_path(config, &backup.display().to_string())?;
+    info!("saving docker data to '{}'...", backup.display());
+    state::forget_daemon_alive(config);
+    run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
     ensure!(
         run_in_wsl(
+            config,
             &[
-                "sh",
-                "-c",
-                r#"mkdir -p ~/.docker && echo '{"detachKeys":"ctrl-^"}' > ~/.docker/config"#
+                "tar",
+                "--numeric-owner",
+                "-C",
+                "/",
+                "-cf",
+                &backup_in_wsl,
+                "var/lib/docker",
+                "etc/docker"
             ],
-            true
+            false
         )?,
-        "failed to set up detach keys"
+        "failed to save docker data"
     );
 
+    run(&["wsl", "--unregister", &config.distro_name], true)?;
+    state::forget_distro_dir(config);
+    if let Some(download) = stale_download {
+        let _ = fs::remove_file(rootfs::part_path(&download));
+        let _ = fs::remove_file(download);
+    }
+    setup_docker_distro(config)?;
+
+    info!("restoring docker data...");
+    run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
     ensure!(
         run_in_wsl(
-            &[
-                "sh",
-                "-c",
-                r#"mkdir -p /etc/docker && echo '{"features":{"buildkit":true}}' > /etc/docker/daemon.json"#
-            ],
-            true
+            config,
+            &["tar", "--numeric-owner", "-C", "/", "-xpf", &backup_in_wsl],
+            false
         )?,
-        "failed to set up buildkit"
+        format!(
+            "failed to restore docker data; the backup is kept at '{}'",
+            backup.display()
+        )
     );
+    fs::remove_file(&backup)?;
 
     Ok(())
 }
 
-fn convert_path(from: &str) -> Result<String> {
-    output_in_wsl(&["wslpath", "-u", from]).map(|s| s.trim().to_string())
-}
+/// Upgrades the docker packages in the distro, reporting the versions before and after, or
+/// with `check` only lists the available updates.
+fn upgrade_engine(config: &Config, check: bool) -> Result<()> {
+    if let Some(version) = &config.docker_version {
+        bail!("docker_version pins the engine to {version}; change it and reprovision to upgrade");
+    }
+    ensure_docker(config)?;
 
-fn modify_args(args: &mut [String]) -> Result<()> {
-    if args.is_empty() {
+    let updates = output_in_wsl(config, &["sh", "-c", config.flavor.check_updates_script()])?;
+    let updates = updates.trim();
+    if updates.is_empty() {
+        println!("docker engine is up to date");
+        return Ok(());
+    }
+    if check {
+        println!("{updates}");
         return Ok(());
     }
 
-    if args[0] == "create" {
-        fix_bind_mount_path(args)?;
+    let version = || {
+        output_in_wsl(
+            config,
+            &["docker", "version", "--format", "{{.Client.Version}}"],
+        )
+        .map(|version| version.trim().to_string())
+    };
+    let old = version()?;
+    info!("upgrading docker engine...");
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", config.flavor.upgrade_script()], false)?,
+        "failed to upgrade docker engine"
+    );
+    restart_daemon(config)?;
+    let new = version()?;
+    if new == old {
+        println!("docker engine {old} is unchanged");
+    } else {
+        println!("docker engine upgraded: {old} -> {new}");
     }
 
-    if args[0] != "exec" {
-        for arg in args {
-            fix_arg_containing_backslash(arg)?;
-        }
+    Ok(())
+}
+
+/// Applies the updates of `[maintenance]`, logging what the package managers did and the
+/// engine versions before and after.
+fn run_maintenance(config: &Config) -> Result<()> {
+    let script = maintenance::update_script(config);
+    if script.is_empty() {
+        info!("maintenance has no updates to apply");
+        return Ok(());
     }
+    ensure_docker(config)?;
+
+    let version = || {
+        output_in_wsl(
+            config,
+            &["docker", "version", "--format", "{{.Client.Version}}"],
+        )
+        .map(|version| version.trim().to_string())
+    };
+    let old = version()?;
+    let date = output_in_wsl(config, &["date", "-Iseconds"])?;
+    info!("applying updates to '{}'...", config.distro_name);
+    // The status is printed last rather than failing the command, so that the output of a
+    // failed update still makes it into the log.
+    let wrapped = format!("({script}) 2>&1; echo \"status=$?\"");
+    let output = output_in_wsl(config, &["sh", "-c", &wrapped])?;
+    let (output, status) = output
+        .trim_end()
+        .rsplit_once('\n')
+        .unwrap_or(("", output.trim_end()));
+    let succeeded = status == "status=0";
+
+    restart_daemon(config)?;
+    let new = version()?;
+    let summary = match (succeeded, old == new) {
+        (false, _) => format!("updates failed ({status}); docker engine {new}"),
+        (true, true) => format!("updated; docker engine {new} is unchanged"),
+        (true, false) => format!("updated; docker engine {old} -> {new}"),
+    };
+    maintenance::append_log(
+        &format!("{} {}: {summary}", date.trim(), config.distro_name),
+        output,
+    )?;
+    ensure!(
+        succeeded,
+        "maintenance of '{}' failed; see '{}'",
+        config.distro_name,
+        maintenance::log_path().display()
+    );
+    info!("{summary}");
 
     Ok(())
 }
 
-fn fix_bind_mount_path(args: &mut [String]) -> Result<()> {
-    let mut is_mount_option = false;
-    for arg in args {
-        if is_mount_option {
-            is_mount_option = false;
-            let mut opts: Vec<String> = arg.split(',').map(|s| s.to_string()).collect();
-            for opt in &mut opts {
-                if opt.starts_with("source=") {
-                    let path = &opt["source=".len()..];
-                    let path = convert_path(path)?;
-                    *opt = format!("source={path}");
-                }
+/// Writes the configured detach keys into the client config of the user docker runs as,
+/// keeping whatever else is in it. Also removes the `~/.docker/config` of root that earlier
+/// versions wrote by mistake.
+fn configure_detach_keys(config: &Config) -> Result<()> {
+    let home = wsl::docker_home(config)?;
+    let path = format!("{home}/.docker/config.json");
+    let mut client_config = read_json_object_in_wsl(config, &path)?;
+    client_config.insert(
+        "detachKeys".into(),
+        config.client.detach_keys.clone().into(),
+    );
+
+    let content = serde_json::to_string_pretty(&client_config)?;
+    ensure!(
+        write_file_in_wsl(config, &path, &content)?
+            && run_in_wsl(
+                config,
+                &[
+                    "sh",
+                    "-c",
+                    &format!("chown -R \"$(stat -c %u:%g {home})\" {home}/.docker"),
+                ],
+                true
+            )?,
+        "failed to set up detach keys"
+    );
+    run_in_wsl(config, &["sh", "-c", "rm -f ~/.docker/config"], true)?;
+
+    Ok(())
+}
+
+/// Prints the dockerd log, which is `/var/log/docker.log` under the init scripts the distro
+/// uses by default and the journal once systemd manages the daemon.
+fn print_daemon_log(config: &Config, follow: bool, tail: usize) -> Result<bool> {
+    let (tail_follow, journal_follow) = if follow { ("-F", "-f") } else { ("", "") };
+    let script = format!(
+        "if {SYSTEMD_RUNNING} && systemctl is-enabled --quiet docker 2>/dev/null; then \
+             exec journalctl -u docker --no-pager {journal_follow} -n {tail}; \
+         elif [ -f /var/log/docker.log ]; then \
+             exec tail {tail_follow} -n {tail} /var/log/docker.log; \
+         elif command -v journalctl >/dev/null; then \
+             exec journalctl -u docker --no-pager {journal_follow} -n {tail}; \
+         else \
+             echo 'no dockerd log found' >&2; exit 1; \
+         fi",
+        SYSTEMD_RUNNING = flavor::SYSTEMD_RUNNING,
+    );
+    run_in_wsl(config, &["sh", "-c", &script], false)
+}
+
+/// Prints the VS Code settings for Dev Containers. The extension runs the wrapper as its
+/// docker, which rewrites the workspace mounts, while the Docker extension and anything else
+/// reading `docker.host` talk to the served pipe directly.
+fn print_devcontainers_settings(pipe: &str) -> Result<()> {
+    let docker_path = std::env::current_exe()?.display().to_string();
+    let settings = serde_json::json!({
+        "dev.containers.dockerPath": docker_path,
+        "docker.host": format!("npipe:////./pipe/{pipe}"),
+    });
+    println!("add these to the VS Code settings.json:");
+    println!("{}", serde_json::to_string_pretty(&settings)?);
+
+    Ok(())
+}
+
+/// Runs docker with `args` in the distro and returns its exit code, so that the wrapper can
+/// exit with exactly the same one.
+///
+/// With `dry_run`, the command is only printed along with the conversions made to `args`,
+/// and the distro is neither provisioned nor started.
+fn execute_wrapped(config: &Config, args: &mut Vec<String>, dry_run: bool) -> Result<ExitCode> {
+    if !dry_run {
+        state::mark_used(config);
+        ensure_docker(config)?;
+    }
+    let path_style = output_path_style(config, args);
+    let original_args = args.clone();
+    let _temp_files = modify_args(config, args)?;
+    if config.network.host_docker_internal {
+        network::add_host_docker_internal(config, args)?;
+    }
+
+    // Interactive sessions record their terminal so that console resizes can be applied to it.
+    let tty_file = requests_tty(args).then(|| format!("/tmp/docker-wrapper-tty-{}", process::id()));
+    let mut native_args = match &tty_file {
+        Some(tty_file) => vec!["sh", "-c", RECORD_TTY_SCRIPT, tty_file, "docker"],
+        None => vec!["docker"],
+    };
+    native_args.extend(args.iter().map(|arg| &**arg));
+
+    debug!("run: {:?}", native_args);
+    let mut cmd = command_in_wsl_at_cwd(config, &native_args)?;
+    // Builds go through the proxies of Windows, and docker sees the variables set for it there.
+    let mut env = network::Proxy::resolve(config).env();
+    env.extend(args::forwarded_env(config)?);
+    wsl::forward_env(&mut cmd, &env);
+    if dry_run {
+        print_dry_run(&cmd, &original_args, args);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if config.client.windows_credentials && credentials::needed(args) {
+        if let Err(err) = credentials::sync(config) {
+            warn!("{err:#}; the registry logins of Windows were not copied into the distro");
+        }
+    }
+    let _ssh_agent = ssh_agent::requested(args)
+        .then(|| match ssh_agent::Relay::start(config) {
+            Ok(relay) => {
+                wsl::forward_env(&mut cmd, &[("SSH_AUTH_SOCK".into(), relay.socket.clone())]);
+                Some(relay)
+            }
+            Err(err) => {
+                warn!("{err:#}; the build runs without the ssh agent of Windows");
+                None
             }
-            *arg = opts.join(",");
+        })
+        .flatten();
 
-            continue;
+    let sync_ports = config.network.lan_ports && portproxy::changes_ports(args);
+    if sync_ports {
+        // A container in the foreground only exits with the command, so its ports are
+        // forwarded ahead of it. Those picked by the daemon follow with the next sync.
+        let ports = portproxy::run_publish_ports(args);
+        if !ports.is_empty() {
+            sync_lan_ports(config, &ports);
         }
+    }
 
-        if arg.trim() == "--mount" {
-            is_mount_option = true;
+    let _ctrl_c_guard = CtrlCGuard::install();
+    let _raw_console = tty_file.as_ref().and_then(|_| RawConsole::enable());
+    let _resize_watcher = tty_file.and_then(|tty_file| {
+        let distro_name = config.distro_name.clone();
+        ResizeWatcher::spawn(move |rows, columns| {
+            resize_tty_in_wsl(&distro_name, &tty_file, rows, columns)
+        })
+    });
+    let run_docker = |cmd: &mut Command| -> Result<ExitStatus> {
+        let status = match path_style {
+            Some(style) => {
+                let output = cmd.stdout(Stdio::piped()).output()?;
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                print!("{}", translate_output(config, style, &stdout));
+                output.status
+            }
+            None => cmd.status()?,
+        };
+        debug!("docker exited with {status}");
+        Ok(status)
+    };
+    let mut status = run_docker(&mut cmd)?;
+    if daemon_died(config, status)? {
+        warn!("the docker daemon stopped; restarting it...");
+        state::forget_daemon_alive(config);
+        ensure_docker(config)?;
+        // Input piped into the command was consumed by the attempt that failed.
+        if io::stdin().is_terminal() {
+            info!("retrying the command...");
+            status = run_docker(&mut cmd)?;
+        } else {
+            warn!("the daemon is back; run the command again");
         }
     }
+    if sync_ports {
+        sync_lan_ports(config, &[]);
+    }
+    // Long builds run no container, so the idle time counts from when they end.
+    state::mark_used(config);
+    if status.success() {
+        // Whatever docker did, it talked to the daemon.
+        state::mark_daemon_alive(config);
+    }
 
-    Ok(())
+    Ok(exit_code(status))
 }
 
-fn fix_arg_containing_backslash(arg: &mut String) -> Result<()> {
-    if arg.contains('\\') {
-        if let Ok(path) = convert_path(arg) {
-            *arg = path;
-        }
+/// Forwards the published ports from the LAN around a docker command, which goes on even if
+/// the forwarding fails.
+fn sync_lan_ports(config: &Config, extra: &[u16]) {
+    if let Err(err) = portproxy::sync(config, extra) {
+        warn!("{err:#}; the ports of containers are only reachable on localhost");
     }
+}
 
-    Ok(())
+/// Whether a docker command failed because the daemon went away under it. The client exits
+/// with 1 when it cannot reach the daemon, which is then checked for directly.
+fn daemon_died(config: &Config, status: ExitStatus) -> Result<bool> {
+    if status.code() != Some(1) {
+        return Ok(false);
+    }
+
+    Ok(!run_in_wsl(config, &["docker", "version"], true)?)
 }
 
-fn execute_wrapped(args: &mut [String]) -> Result<()> {
-    ensure_docker()?;
-    modify_args(args)?;
-    let mut native_args = vec!["docker"];
-    native_args.extend(args.iter().map(|arg| &**arg));
-    ensure!(run_in_wsl(&native_args, false)?, "docker failed");
-    Ok(())
+fn print_dry_run(cmd: &Command, original_args: &[String], args: &[String]) {
+    let argv: Vec<_> = iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect();
+    println!("command: {argv:?}");
+
+    if original_args.len() == args.len() {
+        for (original, arg) in original_args.iter().zip(args).filter(|(a, b)| a != b) {
+            println!("converted: {original:?} -> {arg:?}");
+        }
+    } else {
+        println!("converted: {original_args:?} -> {args:?}");
+    }
+
+    for (name, value) in cmd.get_envs() {
+        let value = value.map_or_else(|| "(removed)".into(), |value| value.to_string_lossy());
+        println!("env: {}={value}", name.to_string_lossy());
+    }
 }
 
-fn handle_extra_subcommand(args: &mut [String]) -> Result<bool> {
-    if args.is_empty() {
-        return Ok(false);
+/// Converts the exit status of a child into the wrapper's own. A child killed without an exit
+/// code is reported as a plain failure.
+/// The exit code of `status` as far as it fits. Larger ones, such as the `0xC000013A` of a
+/// Ctrl+C on Windows, become 1 rather than whatever their low byte is, which may be 0.
+fn exit_code(status: ExitStatus) -> ExitCode {
+    match status.code().map(u8::try_from) {
+        Some(Ok(code)) => ExitCode::from(code),
+        _ => ExitCode::FAILURE,
     }
+}
 
-    match &*args[0] {
-        "stop-daemon" => {
+fn run_wrapper_command(config: &Config, cli: WrapperCli) -> Result<ExitCode> {
+    let config_path = cli.config.as_deref();
+    match cli.command {
+        WrapperCommand::StopDaemon => {
+            state::forget_daemon_alive(config);
             run(&["wsl", "--shutdown"], true)?;
-
-            Ok(true)
         }
-        "reset-registration" => {
+        WrapperCommand::ResetRegistration => {
+            let lock = state::lock_setup(config)?;
+            state::forget_daemon_alive(config);
             run(&["wsl", "--shutdown"], true)?;
-            run(&["wsl", "--unregister", DISTRO_NAME], true)?;
-            ensure_docker()?;
+            run(&["wsl", "--unregister", &config.distro_name], true)?;
+            state::forget_distro_dir(config);
+            drop(lock);
+            ensure_docker(config)?;
+        }
+        WrapperCommand::Setup {
+            flavor,
+            rootfs_file,
+            engine_debs,
+            rootless,
+            skip_disk_check,
+        } => {
+            let config = if rootless && !config.daemon.rootless {
+                ensure!(
+                    !docker_installed(config).unwrap_or(false),
+                    "'{}' is already set up with a root daemon; run `docker docker-wrapper reprovision` after setting `daemon.rootless` to switch",
+                    config.distro_name
+                );
+                let profile = config.profile.as_deref();
+                Config::set_in_file(config_path, profile, &["daemon", "rootless"], true)?;
+                Config::load(config_path, profile)?
+            } else {
+                config.clone()
+            };
+            let config = match flavor {
+                Some(flavor) if flavor != config.flavor => {
+                    ensure!(
+                        !docker_installed(&config).unwrap_or(false),
+                        "'{}' is already set up as {}; run `docker docker-wrapper reset-registration` to start over",
+                        config.distro_name,
+                        config.flavor
+                    );
+                    // Stored so that later commands keep managing the daemon the same way.
+                    let profile = config.profile.as_deref();
+                    Config::set_in_file(config_path, profile, &["flavor"], flavor.name())?;
+                    Config::load(config_path, profile)?
+                }
+                _ => config,
+            };
+            let config = Config {
+                rootfs_file: rootfs_file.or(config.rootfs_file),
+                engine_debs: engine_debs.or(config.engine_debs),
+                skip_disk_check: skip_disk_check || config.skip_disk_check,
+                ..config
+            };
+            ensure_docker(&config)?;
+        }
+        WrapperCommand::Reprovision { skip_disk_check } => reprovision(&Config {
+            skip_disk_check: skip_disk_check || config.skip_disk_check,
+            ..config.clone()
+        })?,
+        WrapperCommand::UpgradeEngine { check } => upgrade_engine(config, check)?,
+        WrapperCommand::Optimize {
+            sparse,
+            memory_reclaim,
+        } => {
+            let all = !sparse && memory_reclaim.is_none();
+            optimize(
+                config,
+                sparse || all,
+                memory_reclaim.or(all.then_some(vm::MemoryReclaim::Gradual)),
+            )?
+        }
+        WrapperCommand::Move {
+            dir,
+            skip_disk_check,
+        } => {
+            let dir = std::path::absolute(dir)?;
+            let config = Config {
+                skip_disk_check: skip_disk_check || config.skip_disk_check,
+                ..config.clone()
+            };
+            move_distro(&config, &dir)?;
+            Config::set_in_file(
+                config_path,
+                config.profile.as_deref(),
+                ConfigKey::StorageDir.path(),
+                dir.display().to_string(),
+            )?;
+        }
+        WrapperCommand::Backup { file } => backup(config, &file)?,
+        WrapperCommand::Restore {
+            file,
+            replace,
+            skip_disk_check,
+        } => {
+            let config = Config {
+                skip_disk_check: skip_disk_check || config.skip_disk_check,
+                ..config.clone()
+            };
+            restore(&config, config_path, &file, replace)?
+        }
+        WrapperCommand::Clone {
+            profile,
+            skip_disk_check,
+        } => {
+            let config = Config {
+                skip_disk_check: skip_disk_check || config.skip_disk_check,
+                ..config.clone()
+            };
+            clone_distro(&config, config_path, &profile)?
+        }
+        WrapperCommand::Rename {
+            new_name,
+            skip_disk_check,
+        } => {
+            let config = Config {
+                skip_disk_check: skip_disk_check || config.skip_disk_check,
+                ..config.clone()
+            };
+            rename_distro(&config, config_path, &new_name)?
+        }
+        WrapperCommand::Uninstall { yes } => uninstall(config, yes)?,
+        WrapperCommand::Migrate { adopt, ignore } => migrate(config, config_path, adopt, ignore)?,
+        WrapperCommand::Adopt { distro } => adopt(config, config_path, &distro)?,
+        WrapperCommand::Compact => compact(config)?,
+        WrapperCommand::DiskUsage => {
+            ensure_docker(config)?;
+            disk::print_usage(config)?
+        }
+        WrapperCommand::Doctor => {
+            if !doctor::report(&doctor::run_checks(config)) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        WrapperCommand::Status { json } => status::print(&status::query(config), json)?,
+        WrapperCommand::Logs { follow, tail } => {
+            if !print_daemon_log(config, follow, tail)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        WrapperCommand::Config(ConfigCommand::Set { key, value }) => {
+            let profile = config.profile.as_deref();
+            if let ConfigKey::StorageDir = key {
+                let storage_dir = value.clone().into();
+                Config {
+                    storage_dir,
+                    ..config.clone()
+                }
+                .validate_storage_dir()?;
+            }
+            let value: toml::Value = match key.is_list() {
+                true => value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>()
+                    .into(),
+                false => value.as_str().into(),
+            };
+            Config::set_in_file(config_path, profile, key.path(), value)?;
+            let config = Config::load(config_path, profile)?;
+            // An existing installation is updated right away; new ones get it during setup.
+            match key {
+                ConfigKey::DetachKeys if docker_installed(&config)? => {
+                    configure_detach_keys(&config)?
+                }
+                ConfigKey::RegistryMirror => {
+                    set_daemon_list(&config, "registry-mirrors", &config.daemon.registry_mirrors)?
+                }
+                ConfigKey::InsecureRegistry => set_daemon_list(
+                    &config,
+                    "insecure-registries",
+                    &config.daemon.insecure_registries,
+                )?,
+                ConfigKey::Dns if docker_installed(&config)? => {
+                    network::configure_dns(&config)?;
+                    set_daemon_list(&config, "dns", &network::daemon_dns(&config))?;
+                }
+                ConfigKey::StorageDir => {
+                    if let Some(dir) = state::recorded_distro_dir(&config) {
+                        info!(
+                            "'{}' stays in '{}'; the new location applies when it is imported again",
+                            config.distro_name,
+                            dir.display()
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        WrapperCommand::Config(ConfigCommand::Vm(VmCommand::Set { settings, apply })) => {
+            let settings = settings
+                .iter()
+                .map(|setting| vm::parse_setting(setting))
+                .collect::<Result<Vec<_>>>()?;
+            let mirrored = settings.contains(&("networkingMode".into(), "mirrored".into()));
+            if mirrored {
+                ensure!(
+                    wsl::wsl_version().unwrap_or_default().as_slice() >= vm::MIRRORED_VERSION,
+                    "mirrored networking needs WSL 2.0.0 or later on Windows 11; run `wsl --update`"
+                );
+            }
+            vm::set("wsl2", &settings)?;
+            if mirrored {
+                // Without it, Windows and containers cannot reach each other through the
+                // addresses of the machine, only through localhost.
+                vm::set(
+                    "experimental",
+                    &[("hostAddressLoopback".into(), "true".into())],
+                )?;
+            }
+            if apply {
+                state::forget_daemon_alive(config);
+                run(&["wsl", "--shutdown"], true)?;
+                info!("WSL was shut down; the settings apply from the next command");
+            } else {
+                info!(
+                    "updated '{}'; the settings apply after `wsl --shutdown`, which stops every \
+                     distro (pass --apply to do it now)",
+                    vm::wslconfig_path().display()
+                );
+            }
+        }
+        WrapperCommand::Config(ConfigCommand::WslConf(WslConfCommand::Set { settings, apply })) => {
+            let settings = settings
+                .iter()
+                .map(|setting| wsl::parse_wsl_conf_setting(setting))
+                .collect::<Result<Vec<_>>>()?;
+            for (section, key, value) in &settings {
+                wsl::set_wsl_conf(config, section, key, value)?;
+            }
+            if apply {
+                state::forget_daemon_alive(config);
+                run_in_wsl(config, &["sh", "-c", &daemon_stop_script(config)], true)?;
+                wsl::terminate(config)?;
+                info!(
+                    "'{}' was restarted; the settings apply from the next command",
+                    config.distro_name
+                );
+            } else {
+                info!(
+                    "updated /etc/wsl.conf; the settings apply once '{}' restarts (pass --apply \
+                     to restart it now)",
+                    config.distro_name
+                );
+            }
+        }
+        WrapperCommand::DaemonConfig(DaemonConfigCommand::Get { key }) => {
+            ensure_docker(config)?;
+            let daemon_json = daemon_config::read(config)?;
+            let value = match &key {
+                Some(key) => daemon_config::get(&daemon_json, key)
+                    .with_context(|| format!("'{key}' is not set in {}", daemon_config::PATH))?
+                    .clone(),
+                None => daemon_json.into(),
+            };
+            match value {
+                serde_json::Value::String(value) => println!("{value}"),
+                value => println!("{}", serde_json::to_string_pretty(&value)?),
+            }
+        }
+        WrapperCommand::DaemonConfig(DaemonConfigCommand::Set { key, value }) => {
+            ensure_docker(config)?;
+            let value = daemon_config::parse_value(&value);
+            match daemon_config::update(config, |daemon_json| {
+                daemon_config::set(daemon_json, &key, value)
+            })? {
+                Some(before) => restart_daemon_with(config, before)?,
+                None => info!("'{key}' already has that value"),
+            }
+        }
+        WrapperCommand::DaemonConfig(DaemonConfigCommand::Unset { key }) => {
+            ensure_docker(config)?;
+            match daemon_config::update(config, |daemon_json| {
+                daemon_config::unset(daemon_json, &key);
+            })? {
+                Some(before) => restart_daemon_with(config, before)?,
+                None => info!("'{key}' is not set"),
+            }
+        }
+        WrapperCommand::Network(NetworkCommand::FixMtu { mtu }) => {
+            let mtu = match mtu {
+                Some(mtu) => mtu,
+                None => network::windows_mtu()
+                    .context("could not detect the MTU of the Windows adapters; pass --mtu")?,
+            };
+            ensure!(
+                mtu >= config::MIN_MTU,
+                "MTU {mtu} is too small (at least {} is needed)",
+                config::MIN_MTU
+            );
+            Config::set_in_file(
+                config_path,
+                config.profile.as_deref(),
+                &["network", "mtu"],
+                i64::from(mtu),
+            )?;
+            ensure_docker(config)?;
+            ensure!(
+                run_in_wsl(config, &["sh", "-c", &network::mtu_script(mtu)], true)?,
+                "failed to set the MTU of the distro"
+            );
+            let changed = daemon_config::update(config, |daemon_json| {
+                network::set_daemon_mtu(daemon_json, mtu)
+            })?;
+            if let Some(before) = changed {
+                restart_daemon_with(config, before)?;
+            }
+            info!(
+                "the distro and new docker networks use MTU {mtu}; recreate existing networks \
+                 such as those of compose projects to apply it to them"
+            );
+        }
+        WrapperCommand::Ports(PortsCommand::List { json }) => {
+            ensure_docker(config)?;
+            ports::print(config, &ports::query(config)?, json)?;
+        }
+        WrapperCommand::Ports(PortsCommand::Sync) => {
+            ensure_docker(config)?;
+            portproxy::sync(config, &[])?;
+        }
+        WrapperCommand::Volume(VolumeCommand::Backup { volume, file }) => {
+            ensure_docker(config)?;
+            volume::backup(config, &volume, &file)?;
+        }
+        WrapperCommand::Volume(VolumeCommand::Restore {
+            file,
+            volume,
+            replace,
+        }) => {
+            ensure_docker(config)?;
+            volume::restore(config, &file, &volume, replace)?;
+        }
+        WrapperCommand::Expose {
+            pipe,
+            tcp,
+            tls,
+            no_tls,
+        } => {
+            ensure_docker(config)?;
+            match tcp {
+                Some(port) if tls => {
+                    let (cert_dir, before) = expose::enable_tls_listener(config, port)?;
+                    if let Some(before) = before {
+                        restart_daemon_with(config, before)?;
+                    }
+                    println!("DOCKER_HOST=tcp://localhost:{port}");
+                    println!("DOCKER_TLS_VERIFY=1");
+                    println!("DOCKER_CERT_PATH={}", cert_dir.display());
+                }
+                _ if no_tls => {
+                    if let Some(before) = expose::disable_tls_listener(config)? {
+                        restart_daemon_with(config, before)?;
+                    }
+                }
+                Some(port) => expose::serve_tcp(config, port)?,
+                None => expose::serve_pipe(config, &pipe)?,
+            }
+        }
+        WrapperCommand::Devcontainers { pipe } => {
+            ensure_docker(config)?;
+            print_devcontainers_settings(&pipe)?;
+            expose::serve_pipe(config, &pipe)?;
+        }
+        WrapperCommand::Enable {
+            preset: Preset::Testcontainers,
+            port,
+            persist,
+        } => {
+            ensure_docker(config)?;
+            let (cert_dir, before) = expose::enable_tls_listener(config, port)?;
+            if let Some(before) = before {
+                restart_daemon_with(config, before)?;
+            }
+            preset::check_ryuk(config)?;
+            preset::write_testcontainers_properties(port, &cert_dir)?;
 
-            Ok(true)
+            let env = preset::testcontainers_env(port, &cert_dir);
+            if persist {
+                preset::persist_env(&env)?;
+            }
+            for (name, value) in &env {
+                println!("{name}={value}");
+            }
         }
-        _ => Ok(false),
+        WrapperCommand::Enable {
+            preset: Preset::Multiarch,
+            ..
+        } => {
+            ensure_docker(config)?;
+            preset::enable_multiarch(config)?;
+            info!(
+                "multi-platform builds use builder '{}', e.g. `docker buildx build --platform linux/amd64,linux/arm64 .`",
+                preset::MULTIARCH_BUILDER
+            );
+        }
+        WrapperCommand::Enable {
+            preset: Preset::Gpu,
+            ..
+        } => {
+            ensure_docker(config)?;
+            let before = preset::enable_gpu(config)?;
+            restart_daemon_with(config, before)?;
+            preset::check_gpu(config)?;
+            info!("GPUs are available with `docker run --gpus all ...`");
+        }
+        WrapperCommand::Context(ContextCommand::Create {
+            name,
+            pipe,
+            tcp,
+            tls,
+        }) => {
+            let endpoint = match tcp {
+                Some(port) => context::Endpoint::Tcp { port, tls },
+                None => context::Endpoint::Pipe(pipe),
+            };
+            context::create(config, &name, &endpoint)?;
+            info!("created context '{name}'; select it with `docker context use {name}`");
+        }
+        WrapperCommand::Agent(AgentCommand::Start) => agent::start(config)?,
+        WrapperCommand::Agent(AgentCommand::Stop) => {
+            if !agent::stop(config) {
+                info!("the agent is not running");
+            }
+        }
+        WrapperCommand::Agent(AgentCommand::Serve { distro }) => agent::serve(config, &distro)?,
+        WrapperCommand::Maintenance(MaintenanceCommand::Install) => maintenance::install(config)?,
+        WrapperCommand::Maintenance(MaintenanceCommand::Uninstall) => {
+            maintenance::uninstall(config)?
+        }
+        WrapperCommand::Maintenance(MaintenanceCommand::Run) => run_maintenance(config)?,
+        WrapperCommand::Idle(IdleCommand::Install) => idle::install(config)?,
+        WrapperCommand::Idle(IdleCommand::Uninstall) => idle::uninstall(config)?,
+        WrapperCommand::Idle(IdleCommand::Check) => shut_down_if_idle(config)?,
+        WrapperCommand::Autostart(AutostartCommand::Enable) => {
+            let task = task::name(config, "autostart");
+            // Delayed a little so as not to compete with everything else starting at login.
+            task::create(
+                config,
+                &task,
+                &["/SC", "ONLOGON", "/DELAY", "0000:30"],
+                "autostart run",
+            )?;
+            info!("'{}' starts when you log in to Windows", config.distro_name);
+        }
+        WrapperCommand::Autostart(AutostartCommand::Disable) => {
+            task::delete(&task::name(config, "autostart"))?
+        }
+        WrapperCommand::Autostart(AutostartCommand::Run) => ensure_docker(config)?,
+        WrapperCommand::SshAgentRelay => ssh_agent::relay()?,
+        WrapperCommand::Docker(mut args) => return execute_wrapped(config, &mut args, cli.dry_run),
     }
+
+    Ok(ExitCode::SUCCESS)
 }
 
-fn main() -> Result<()> {
-    let mut args: Vec<_> = std::env::args().skip(1).collect();
-    if handle_extra_subcommand(&mut args)? {
-        return Ok(());
+fn main() -> Result<ExitCode> {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    match Invocation::parse(args) {
+        Invocation::Docker(mut args) => {
+            let config = Config::load(None, None)?;
+            log::init(config.output.verbosity);
+            execute_wrapped(&config, &mut args, false)
+        }
+        Invocation::Wrapper(cli) => {
+            let mut config = Config::load(cli.config.as_deref(), cli.profile.as_deref())?;
+            config.daemon.auto_repair |= cli.auto_repair;
+            log::init(cli.verbosity().unwrap_or(config.output.verbosity));
+            run_wrapper_command(&config, cli)
+        }
     }
-
-    execute_wrapped(&mut args)
 }
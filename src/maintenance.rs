@@ -0,0 +1,87 @@
+//! Opt-in maintenance: package and engine updates applied inside the distro by a daily task
+//! of the Windows Task Scheduler, with what changed appended to a log.
+
+use crate::{
+    config::{data_dir, Config},
+    flavor::Flavor,
+    task,
+};
+use anyhow::{ensure, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+use tracing::info;
+
+pub fn log_path() -> PathBuf {
+    data_dir().join("maintenance.log")
+}
+
+/// Registers the daily task running `docker-wrapper maintenance run` at `maintenance.time`,
+/// replacing one registered earlier.
+pub fn install(config: &Config) -> Result<()> {
+    ensure!(
+        config.maintenance.enabled,
+        "maintenance is off; set `maintenance.enabled = true` in the config first"
+    );
+
+    let task = task::name(config, "maintenance");
+    task::create(
+        config,
+        &task,
+        &["/SC", "DAILY", "/ST", &config.maintenance.time],
+        "maintenance run",
+    )?;
+    info!(
+        "'{task}' runs daily at {}; changes are logged to '{}'",
+        config.maintenance.time,
+        log_path().display()
+    );
+
+    Ok(())
+}
+
+pub fn uninstall(config: &Config) -> Result<()> {
+    task::delete(&task::name(config, "maintenance"))
+}
+
+/// Shell script applying the updates `maintenance` asks for and printing what it changed.
+/// Distro updates leave a pinned `docker_version` alone.
+pub fn update_script(config: &Config) -> String {
+    let pinned = config.docker_version.is_some();
+    let mut steps = vec![];
+    if config.maintenance.distro_updates {
+        steps.push(match (config.flavor, pinned) {
+            (Flavor::Ubuntu | Flavor::Debian, false) => {
+                "apt-get update -qq && DEBIAN_FRONTEND=noninteractive apt-get upgrade -y"
+            }
+            (Flavor::Ubuntu | Flavor::Debian, true) => {
+                "apt-mark hold 'docker-*' containerd.io >/dev/null && apt-get update -qq \
+                 && DEBIAN_FRONTEND=noninteractive apt-get upgrade -y"
+            }
+            // The `=~` constraint of a pinned version is kept in the world file.
+            (Flavor::Alpine, _) => "apk upgrade --no-cache",
+            (Flavor::Fedora, false) => "dnf upgrade -y",
+            (Flavor::Fedora, true) => "dnf upgrade -y --exclude='docker-*' --exclude=containerd.io",
+        });
+    }
+    if config.maintenance.engine_updates && !pinned {
+        steps.push(config.flavor.upgrade_script());
+    }
+
+    steps.join(" && ")
+}
+
+/// Appends the outcome of a maintenance run to the log.
+pub fn append_log(header: &str, output: &str) -> Result<()> {
+    fs::create_dir_all(data_dir())?;
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+    writeln!(log, "== {header}")?;
+    writeln!(log, "{}", output.trim_end())?;
+
+    Ok(())
+}
@@ -0,0 +1,510 @@
+//! Proxy and CA settings for machines behind corporate proxies, applied both to the wrapper's
+//! own downloads and to the distro while it is set up.
+
+use crate::{
+    config::Config,
+    daemon_config,
+    flags::{first_positional, resolve_command, scan, Token, GLOBAL_FLAGS},
+    flavor::{Flavor, SYSTEMD_RUNNING},
+    state, vm,
+    wsl::{output, output_in_wsl, run_in_wsl, set_wsl_conf, shell_quote, write_file_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use serde_json::{json, Map, Value};
+use std::{env, fs, net::Ipv4Addr, sync::OnceLock};
+use tracing::{debug, info};
+
+/// Proxies in effect, from the config, else the usual environment variables, else the proxy
+/// configured in the Windows internet settings.
+#[derive(Debug, Clone, Default)]
+pub struct Proxy {
+    pub http: Option<String>,
+    pub https: Option<String>,
+    /// Comma-separated hosts reached directly, in the `NO_PROXY` format.
+    pub no_proxy: Option<String>,
+}
+
+impl Proxy {
+    pub fn resolve(config: &Config) -> Self {
+        let network = &config.network;
+        let from_config = Self {
+            http: network.http_proxy.clone(),
+            https: network.https_proxy.clone(),
+            no_proxy: network.no_proxy.clone(),
+        };
+        if !from_config.is_empty() {
+            return from_config;
+        }
+
+        let from_env = Self {
+            http: env_var(&["http_proxy", "HTTP_PROXY"]),
+            https: env_var(&["https_proxy", "HTTPS_PROXY"]),
+            no_proxy: env_var(&["no_proxy", "NO_PROXY"]),
+        };
+        if !from_env.is_empty() || !cfg!(windows) {
+            return from_env;
+        }
+
+        // `reg query` is spawned at most once per run, since every wrapped command asks.
+        static WINDOWS_PROXY: OnceLock<Proxy> = OnceLock::new();
+        WINDOWS_PROXY
+            .get_or_init(|| windows_proxy().unwrap_or_default())
+            .clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none()
+    }
+
+    /// Proxy to use for `url`, if any.
+    pub fn for_url(&self, url: &str) -> Option<&str> {
+        let (scheme, rest) = url.split_once("://")?;
+        let host = rest.split(['/', ':']).next()?;
+        if self.bypasses(host) {
+            return None;
+        }
+
+        match scheme {
+            "https" => self.https.as_deref().or(self.http.as_deref()),
+            _ => self.http.as_deref(),
+        }
+    }
+
+    fn bypasses(&self, host: &str) -> bool {
+        let Some(no_proxy) = &self.no_proxy else {
+            return false;
+        };
+
+        no_proxy.split(',').map(str::trim).any(|entry| {
+            let suffix = entry.trim_start_matches('*').trim_start_matches('.');
+            entry == "*"
+                || host == suffix
+                || (!suffix.is_empty() && host.ends_with(&format!(".{suffix}")))
+        })
+    }
+
+    /// The proxies as environment variables, in both the lower and upper case spellings
+    /// that different tools read.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut vars = vec![];
+        for (name, value) in [
+            ("http_proxy", &self.http),
+            ("https_proxy", &self.https),
+            ("no_proxy", &self.no_proxy),
+        ] {
+            if let Some(value) = value {
+                vars.push((name.to_string(), value.clone()));
+                vars.push((name.to_ascii_uppercase(), value.clone()));
+            }
+        }
+
+        vars
+    }
+
+    /// The `proxies` section of `daemon.json`, which the daemon uses when pulling images.
+    pub fn daemon_json(&self) -> Option<Value> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut proxies = Map::new();
+        for (key, value) in [
+            ("http-proxy", &self.http),
+            ("https-proxy", &self.https),
+            ("no-proxy", &self.no_proxy),
+        ] {
+            if let Some(value) = value {
+                proxies.insert(key.into(), json!(value));
+            }
+        }
+
+        Some(Value::Object(proxies))
+    }
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+/// Reads the manual proxy of the Windows internet settings. `ProxyServer` is either one
+/// `host:port` for every scheme or a list such as `http=host:port;https=host:port`, and
+/// `ProxyOverride` lists the bypassed hosts separated by semicolons.
+fn windows_proxy() -> Option<Proxy> {
+    let settings = output(&[
+        "reg",
+        "query",
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings",
+    ])
+    .ok()?;
+    let value = |name: &str| {
+        settings.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next() == Some(name)).then(|| fields.nth(1).map(str::to_string))?
+        })
+    };
+
+    if value("ProxyEnable").as_deref() != Some("0x1") {
+        return None;
+    }
+    let server = value("ProxyServer")?;
+    let url = |address: &str| {
+        if address.contains("://") {
+            address.to_string()
+        } else {
+            format!("http://{address}")
+        }
+    };
+    let mut proxy = Proxy::default();
+    if server.contains('=') {
+        for entry in server.split(';') {
+            match entry.split_once('=') {
+                Some(("http", address)) => proxy.http = Some(url(address)),
+                Some(("https", address)) => proxy.https = Some(url(address)),
+                _ => {}
+            }
+        }
+    } else {
+        proxy.http = Some(url(&server));
+        proxy.https = Some(url(&server));
+    }
+    proxy.no_proxy = value("ProxyOverride").map(|hosts| {
+        hosts
+            .split(';')
+            .map(|host| if host == "<local>" { "localhost" } else { host })
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    debug!("using the Windows proxy settings: {proxy:?}");
+
+    Some(proxy)
+}
+
+/// Environment of dockerd holding the proxies, read by the systemd unit through a drop-in
+/// and by the init script of the flavors that start dockerd directly.
+pub const PROXY_ENV_FILE: &str = "/etc/docker/docker-wrapper-proxy.env";
+const PROXY_DROP_IN: &str = "/etc/systemd/system/docker.service.d/docker-wrapper-proxy.conf";
+
+/// Hands the proxies in effect now to dockerd, both as its environment and as `proxies` of
+/// `daemon.json`, which engines before 23.0 ignore. The proxies last handed over are
+/// remembered, so that the distro is only touched when they change. Returns whether they
+/// did, in which case the daemon has to be restarted to use them.
+pub fn sync_daemon_proxy(config: &Config) -> Result<bool> {
+    let proxy = Proxy::resolve(config);
+    let env: String = proxy
+        .env()
+        .iter()
+        .map(|(name, value)| format!("{name}={}\n", shell_quote(value)))
+        .collect();
+    let marker = state::state_dir().join(format!("{}.proxy", config.distro_name));
+    if fs::read_to_string(&marker).unwrap_or_default() == env {
+        return Ok(false);
+    }
+
+    debug!("handing the proxies to dockerd: {proxy:?}");
+    let drop_in = format!("[Service]\nEnvironmentFile=-{PROXY_ENV_FILE}");
+    ensure!(
+        write_file_in_wsl(config, PROXY_ENV_FILE, env.trim_end())?
+            && write_file_in_wsl(config, PROXY_DROP_IN, &drop_in)?,
+        "failed to write the proxy environment of dockerd"
+    );
+    let reload = format!("! {SYSTEMD_RUNNING} || systemctl daemon-reload");
+    run_in_wsl(config, &["sh", "-c", &reload], true)?;
+    daemon_config::update(config, |daemon_json| match proxy.daemon_json() {
+        Some(proxies) => {
+            daemon_json.insert("proxies".into(), proxies);
+        }
+        None => {
+            daemon_json.remove("proxies");
+        }
+    })?;
+
+    fs::create_dir_all(state::state_dir())?;
+    fs::write(marker, env)?;
+    Ok(true)
+}
+
+/// Makes the distro trust `network.ca_file` and go through the proxy, before anything is
+/// installed into it.
+pub fn configure_distro(config: &Config, proxy: &Proxy) -> Result<()> {
+    configure_dns(config)?;
+    if let Some(ca_file) = &config.network.ca_file {
+        let ca = fs::read_to_string(ca_file)
+            .with_context(|| format!("failed to read CA file '{}'", ca_file.display()))?;
+        let (dir, update) = match config.flavor {
+            Flavor::Fedora => ("/etc/pki/ca-trust/source/anchors", "update-ca-trust"),
+            _ => ("/usr/local/share/ca-certificates", "update-ca-certificates"),
+        };
+        ensure!(
+            write_file_in_wsl(config, &format!("{dir}/docker-wrapper-ca.crt"), &ca)?,
+            "failed to install the CA certificate"
+        );
+        // The trust store of rootfs without ca-certificates is built once it is installed.
+        let script = format!("! command -v {update} >/dev/null || {update}");
+        run_in_wsl(config, &["sh", "-c", &script], true)?;
+    }
+
+    if matches!(config.flavor, Flavor::Ubuntu | Flavor::Debian) && !proxy.is_empty() {
+        let mut apt_conf = String::new();
+        for (scheme, value) in [("http", &proxy.http), ("https", &proxy.https)] {
+            if let Some(value) = value {
+                apt_conf.push_str(&format!("Acquire::{scheme}::Proxy \"{value}\";\n"));
+            }
+        }
+        ensure!(
+            write_file_in_wsl(
+                config,
+                "/etc/apt/apt.conf.d/95docker-wrapper-proxy",
+                &apt_conf
+            )?,
+            "failed to configure the proxy for apt"
+        );
+    }
+
+    Ok(())
+}
+
+/// First line of the `/etc/resolv.conf` the wrapper writes, telling it apart from one WSL
+/// generated or one written by hand.
+const RESOLV_CONF_HEADER: &str = "# Written by docker-wrapper from network.dns";
+
+/// Pins the nameservers of the distro to `network.dns` when it applies to the distro, or
+/// hands `/etc/resolv.conf` back to WSL if the wrapper pinned it before. WSL reads the
+/// `generateResolvConf` setting at boot, while the file takes effect right away.
+pub fn configure_dns(config: &Config) -> Result<()> {
+    let network = &config.network;
+    if network.dns.is_empty() || !network.dns_target.distro() {
+        let script =
+            format!("head -n 1 /etc/resolv.conf 2>/dev/null | grep -qx '{RESOLV_CONF_HEADER}'");
+        if run_in_wsl(config, &["sh", "-c", &script], true)? {
+            set_wsl_conf(config, "network", "generateResolvConf", "true")?;
+            run_in_wsl(config, &["rm", "-f", "/etc/resolv.conf"], true)?;
+            info!(
+                "WSL generates /etc/resolv.conf again once '{}' restarts",
+                config.distro_name
+            );
+        }
+        return Ok(());
+    }
+
+    set_wsl_conf(config, "network", "generateResolvConf", "false")?;
+    let mut resolv_conf = format!("{RESOLV_CONF_HEADER}\n");
+    for server in &network.dns {
+        resolv_conf.push_str(&format!("nameserver {server}\n"));
+    }
+    // WSL links the file to one of its own, which would be written through otherwise.
+    let script = format!(
+        "rm -f /etc/resolv.conf && printf '%s' {} >/etc/resolv.conf",
+        shell_quote(&resolv_conf)
+    );
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", &script], true)?,
+        "failed to write /etc/resolv.conf"
+    );
+
+    Ok(())
+}
+
+/// The nameservers `daemon.json` gets from `network.dns`, empty when they do not apply to it.
+pub fn daemon_dns(config: &Config) -> Vec<String> {
+    match config.network.dns_target.daemon() {
+        true => config.network.dns.clone(),
+        false => vec![],
+    }
+}
+
+/// Smallest MTU of the connected network adapters of Windows, leaving out the virtual ones
+/// of WSL and Hyper-V. A VPN adapter has the smallest one, as it wraps packets in its own.
+pub fn windows_mtu() -> Option<u16> {
+    let script = "Get-NetIPInterface -AddressFamily IPv4 -ConnectionState Connected \
+                  | Where-Object { $_.InterfaceAlias -notlike 'vEthernet*' \
+                      -and $_.InterfaceAlias -notlike 'Loopback*' } \
+                  | ForEach-Object { $_.NlMtu }";
+    let mtus = output(&["powershell", "-NoProfile", "-Command", script]).ok()?;
+
+    mtus.lines().filter_map(|mtu| mtu.trim().parse().ok()).min()
+}
+
+/// Shell script setting the MTU of the distro's interface, which WSL resets on every boot.
+pub fn mtu_script(mtu: u16) -> String {
+    format!("ip link set dev eth0 mtu {mtu}")
+}
+
+/// Sets `mtu` in `daemon.json` both for the default bridge and, through the default options
+/// of the bridge driver, for the networks created later.
+pub fn set_daemon_mtu(daemon_json: &mut Map<String, Value>, mtu: u16) {
+    daemon_json.insert("mtu".into(), json!(mtu));
+    // The option has dots in its name, so it cannot be set through a dotted key.
+    daemon_config::object_mut(daemon_json, "default-network-opts.bridge").insert(
+        "com.docker.network.driver.mtu".into(),
+        json!(mtu.to_string()),
+    );
+}
+
+/// Host name Docker Desktop gives containers for reaching Windows.
+const HOST_DOCKER_INTERNAL: &str = "host.docker.internal";
+
+/// Adds `--add-host host.docker.internal:<address>` to a `docker run` or `docker create`
+/// that does not map the name itself, `<address>` being where the Windows host is reached
+/// from the distro. It is looked up every time, since WSL assigns it anew on every boot.
+pub fn add_host_docker_internal(config: &Config, args: &mut Vec<String>) -> Result<()> {
+    let Some(position) = host_mapping_position(args) else {
+        return Ok(());
+    };
+    let Some(address) = windows_host_address(config) else {
+        debug!("the address of the Windows host is unknown");
+        return Ok(());
+    };
+    args.insert(
+        position,
+        format!("--add-host={HOST_DOCKER_INTERNAL}:{address}"),
+    );
+
+    Ok(())
+}
+
+/// Where `--add-host` goes into `args`, right after the `run` or `create`, or `None` when the
+/// command is something else or maps the name itself.
+fn host_mapping_position(args: &[String]) -> Option<usize> {
+    let command_index = first_positional(&scan(args, GLOBAL_FLAGS, false))?;
+    let (spec, start) = resolve_command(&args[command_index..])?;
+    // Services may run on other nodes of the swarm than the distro.
+    if !matches!(spec.name, "run" | "create") || spec.parents.contains(&"service") {
+        return None;
+    }
+
+    let start = command_index + start;
+    let command_args = &args[start..];
+    let mapped = scan(command_args, spec.flags, spec.interspersed)
+        .into_iter()
+        .any(|token| match token {
+            Token::Flag {
+                spec: Some(flag),
+                value: Some(value),
+                ..
+            } if flag.names.contains(&"--add-host") => command_args[value.index][value.offset..]
+                .strip_prefix(HOST_DOCKER_INTERNAL)
+                .is_some_and(|rest| rest.starts_with([':', '='])),
+            _ => false,
+        });
+
+    (!mapped).then_some(start)
+}
+
+/// Address of the Windows host as seen from the distro: its gateway in NAT networking, and in
+/// mirrored networking the address the distro shares with Windows.
+fn windows_host_address(config: &Config) -> Option<Ipv4Addr> {
+    let route = output_in_wsl(config, &["ip", "-4", "route", "get", "1.1.1.1"]).ok()?;
+    let field = match vm::networking_mode(config).as_str() {
+        "mirrored" => "src",
+        _ => "via",
+    };
+
+    parse_route_field(&route, field)
+}
+
+/// Reads a field of `ip route get`, such as `via` of `1.1.1.1 via 172.20.0.1 dev eth0 src
+/// 172.20.5.3 uid 0`.
+fn parse_route_field(route: &str, field: &str) -> Option<Ipv4Addr> {
+    let mut fields = route.split_whitespace();
+    fields.find(|name| *name == field)?;
+
+    fields.next()?.parse().ok()
+}
+
+/// Parses an IPv4 range such as `172.17.0.0/16` into its address and prefix length.
+pub fn parse_cidr(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+    let (address, prefix) = cidr.trim().split_once('/')?;
+    let prefix = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+
+    Some((address.parse().ok()?, prefix))
+}
+
+/// Whether two IPv4 ranges share any address.
+pub fn cidrs_overlap((a, a_prefix): (Ipv4Addr, u8), (b, b_prefix): (Ipv4Addr, u8)) -> bool {
+    let prefix = a_prefix.min(b_prefix);
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(command: &str) -> Option<usize> {
+        let args: Vec<String> = command.split(' ').map(str::to_string).collect();
+        host_mapping_position(&args)
+    }
+
+    #[test]
+    fn host_mapping_goes_after_the_command() {
+        assert_eq!(position("run --rm alpine"), Some(1));
+        assert_eq!(position("--context x container create alpine"), Some(4));
+        assert_eq!(
+            position("run alpine --add-host host.docker.internal:1.2.3.4"),
+            Some(1)
+        );
+        assert_eq!(position("ps -a"), None);
+        assert_eq!(position("service create alpine"), None);
+    }
+
+    #[test]
+    fn own_host_mappings_are_kept() {
+        assert_eq!(
+            position("run --add-host host.docker.internal:10.0.0.1 alpine"),
+            None
+        );
+        assert_eq!(
+            position("run --add-host=host.docker.internal=10.0.0.1 alpine"),
+            None
+        );
+        assert_eq!(
+            position("run --add-host host.docker.internal.example:10.0.0.1 alpine"),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn no_proxy_matches_hosts_and_subdomains() {
+        let proxy = Proxy {
+            http: Some("http://proxy:8080".into()),
+            https: None,
+            no_proxy: Some("localhost, .corp.example".into()),
+        };
+        assert_eq!(proxy.for_url("http://localhost:2375/"), None);
+        assert_eq!(proxy.for_url("https://git.corp.example/x"), None);
+        assert_eq!(
+            proxy.for_url("https://example.com/rootfs.tar.gz"),
+            Some("http://proxy:8080")
+        );
+    }
+
+    #[test]
+    fn cidrs_overlap_when_one_contains_the_other() {
+        let cidr = |s| parse_cidr(s).unwrap();
+        assert!(cidrs_overlap(cidr("172.16.0.0/12"), cidr("172.17.0.0/16")));
+        assert!(cidrs_overlap(cidr("172.17.5.0/24"), cidr("172.17.0.0/16")));
+        assert!(!cidrs_overlap(cidr("10.0.0.0/8"), cidr("172.17.0.0/16")));
+        assert!(cidrs_overlap(cidr("0.0.0.0/0"), cidr("192.168.1.0/24")));
+        assert_eq!(parse_cidr("10.0.0.0/33"), None);
+    }
+
+    #[test]
+    fn windows_host_is_read_from_the_route() {
+        let route = "1.1.1.1 via 172.20.0.1 dev eth0 src 172.20.5.3 uid 0\n    cache\n";
+        assert_eq!(
+            parse_route_field(route, "via"),
+            Some(Ipv4Addr::new(172, 20, 0, 1))
+        );
+        assert_eq!(
+            parse_route_field(route, "src"),
+            Some(Ipv4Addr::new(172, 20, 5, 3))
+        );
+        assert_eq!(
+            parse_route_field("1.1.1.1 dev eth0 src 10.0.0.2", "via"),
+            None
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    config::{Config, PathStyle},
+    flags::{first_positional, resolve_command, scan, GLOBAL_FLAGS},
+    wsl::automount_root,
+};
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Serializer, Value};
+
+/// Keys of inspect output whose values are paths on the docker host rather than in a
+/// container. `LowerDir` holds a colon-separated list of them.
+const HOST_PATH_KEYS: &[&str] = &[
+    "HostnamePath",
+    "HostsPath",
+    "LogPath",
+    "LowerDir",
+    "MergedDir",
+    "Mountpoint",
+    "ResolvConfPath",
+    "Source",
+    "UpperDir",
+    "WorkDir",
+];
+
+/// Characters that end a path when looking for paths in free-form output.
+const PATH_DELIMITERS: &[char] = &[
+    ' ', '\t', '\r', '\n', '"', '\'', ',', ';', ':', '(', ')', '[', ']', '{', '}',
+];
+
+/// Returns how to translate the output of the docker invocation `args`, or `None` when its
+/// output is passed through untouched. Only inspect commands are translated.
+pub fn output_path_style(config: &Config, args: &[String]) -> Option<PathStyle> {
+    let style = config.output.translate_paths;
+    (style != PathStyle::Off && is_inspect_command(args)).then_some(style)
+}
+
+fn is_inspect_command(args: &[String]) -> bool {
+    let Some(index) = first_positional(&scan(args, GLOBAL_FLAGS, false)) else {
+        return false;
+    };
+
+    // Either `docker inspect` or `docker <object> inspect`.
+    resolve_command(&args[index..]).is_some_and(|(spec, _)| spec.name == "inspect")
+}
+
+/// Translates distro paths in inspect output back to paths Windows tools can open. JSON output
+/// is rewritten structurally, only touching host path fields; anything else, such as
+/// `--format` output, only has paths under the automount root such as `/mnt/<drive>/...`
+/// replaced.
+pub fn translate_output(config: &Config, style: PathStyle, output: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(output) else {
+        return translate_text(automount_root(config), output);
+    };
+
+    translate_json(config, style, &mut value, None);
+
+    // Match the four-space indentation of docker's own output.
+    let mut buf = vec![];
+    let mut serializer =
+        Serializer::with_formatter(&mut buf, PrettyFormatter::with_indent(b"    "));
+    if value.serialize(&mut serializer).is_err() {
+        return output.to_string();
+    }
+
+    String::from_utf8_lossy(&buf).into_owned() + "\n"
+}
+
+fn translate_json(config: &Config, style: PathStyle, value: &mut Value, key: Option<&str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                translate_json(config, style, value, Some(key));
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    // `HostConfig.Binds` entries are `host:container[:mode]`.
+                    Value::String(bind) if key == Some("Binds") => {
+                        if let Some((host, rest)) = bind.split_once(':') {
+                            *bind = format!("{}:{rest}", translate_host_path(config, style, host));
+                        }
+                    }
+                    item => translate_json(config, style, item, None),
+                }
+            }
+        }
+        Value::String(path) if key.is_some_and(|key| HOST_PATH_KEYS.contains(&key)) => {
+            *path = path
+                .split(':')
+                .map(|path| translate_host_path(config, style, path))
+                .collect::<Vec<_>>()
+                .join(":");
+        }
+        _ => {}
+    }
+}
+
+fn translate_host_path(config: &Config, style: PathStyle, path: &str) -> String {
+    if let Some(path) = mount_to_windows_path(automount_root(config), path) {
+        return path;
+    }
+
+    if style == PathStyle::Unc && path.starts_with('/') {
+        return format!(r"\\wsl$\{}{}", config.distro_name, path.replace('/', r"\"));
+    }
+
+    path.to_string()
+}
+
+/// Maps `<root>c/x/y`, such as `/mnt/c/x/y`, to `C:\x\y`.
+fn mount_to_windows_path(root: &str, path: &str) -> Option<String> {
+    let rest = path.strip_prefix(root)?;
+    let mut chars = rest.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+    let rest = chars.as_str();
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+
+    let rest = rest.trim_start_matches('/').replace('/', r"\");
+    Some(format!(r"{}:\{rest}", drive.to_ascii_uppercase()))
+}
+
+fn translate_text(root: &str, text: &str) -> String {
+    let mut translated = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(root) {
+        let at_boundary = rest[..pos]
+            .chars()
+            .next_back()
+            .is_none_or(|c| PATH_DELIMITERS.contains(&c) || c == '=');
+        let end = rest[pos..]
+            .find(PATH_DELIMITERS)
+            .map_or(rest.len(), |len| pos + len);
+        let candidate = &rest[pos..end];
+
+        translated.push_str(&rest[..pos]);
+        match mount_to_windows_path(root, candidate).filter(|_| at_boundary) {
+            Some(path) => translated.push_str(&path),
+            None => translated.push_str(candidate),
+        }
+        rest = &rest[end..];
+    }
+    translated.push_str(rest);
+
+    translated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn translated(style: PathStyle, mut value: Value) -> Value {
+        translate_json(&Config::default(), style, &mut value, None);
+        value
+    }
+
+    #[test]
+    fn host_path_keys_are_translated() {
+        let inspect = json!([{
+            "LogPath": "/var/lib/docker/containers/abc/abc-json.log",
+            "Mounts": [{ "Source": "/mnt/c/work/app", "Destination": "/mnt/c/work/app" }],
+            "GraphDriver": { "Data": { "LowerDir": "/mnt/d/a:/mnt/d/b" } },
+            "Config": { "WorkingDir": "/mnt/c/work", "Env": ["DIR=/mnt/c/work"] },
+        }]);
+        assert_eq!(
+            translated(PathStyle::Windows, inspect),
+            json!([{
+                "LogPath": "/var/lib/docker/containers/abc/abc-json.log",
+                "Mounts": [{ "Source": r"C:\work\app", "Destination": "/mnt/c/work/app" }],
+                "GraphDriver": { "Data": { "LowerDir": r"D:\a:D:\b" } },
+                "Config": { "WorkingDir": "/mnt/c/work", "Env": ["DIR=/mnt/c/work"] },
+            }])
+        );
+    }
+
+    #[test]
+    fn distro_paths_become_unc_paths() {
+        let distro = Config::default().distro_name;
+        assert_eq!(
+            translated(PathStyle::Unc, json!({ "LogPath": "/var/log/x.log" })),
+            json!({ "LogPath": format!(r"\\wsl$\{distro}\var\log\x.log") })
+        );
+    }
+
+    #[test]
+    fn binds_translate_their_host_side() {
+        let host_config = json!({ "Binds": ["/mnt/c/work:/app:ro", "data:/data"] });
+        assert_eq!(
+            translated(PathStyle::Windows, host_config),
+            json!({ "Binds": [r"C:\work:/app:ro", "data:/data"] })
+        );
+    }
+
+    #[test]
+    fn free_text_is_translated_at_word_boundaries() {
+        assert_eq!(
+            translate_text(
+                "/mnt/",
+                "source=/mnt/c/work \"/mnt/d\" /data/mnt/c/x /mnt/cd"
+            ),
+            r#"source=C:\work "D:\" /data/mnt/c/x /mnt/cd"#
+        );
+    }
+
+    #[test]
+    fn a_custom_automount_root_is_followed() {
+        assert_eq!(
+            mount_to_windows_path("/win/", "/win/c/work").as_deref(),
+            Some(r"C:\work")
+        );
+        assert_eq!(mount_to_windows_path("/win/", "/mnt/c/work"), None);
+        assert_eq!(
+            translate_text("/win/", "/win/c/x /mnt/c/x"),
+            r"C:\x /mnt/c/x"
+        );
+    }
+}
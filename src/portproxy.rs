@@ -0,0 +1,438 @@
+//! Forwarding of the ports containers publish from the LAN addresses of Windows, through
+//! `netsh interface portproxy`. In NAT networking WSL only forwards them to `localhost`, so
+//! other machines cannot reach them otherwise; in mirrored networking they need nothing.
+//!
+//! The rules the wrapper adds are recorded, so that it only ever removes its own.
+
+use crate::{
+    config::Config,
+    flags::{first_positional, resolve_command, scan, Token, GLOBAL_FLAGS},
+    state, vm,
+    wsl::{output, output_in_wsl, run},
+};
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs, path::PathBuf};
+use tracing::{debug, info};
+
+/// A rule forwarding `port` on every address of Windows to the same port of the distro.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub port: u16,
+    /// Address of the distro the rule connects to, which WSL assigns anew on every boot.
+    pub address: String,
+    /// Whether a Windows Firewall rule lets the port in along with the forwarding.
+    #[serde(default)]
+    pub firewall: bool,
+}
+
+/// A port published by a running container, as `docker ps` shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Published {
+    /// Address of the distro the port is bound to, `0.0.0.0` or `::` for all of them.
+    pub address: String,
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: String,
+}
+
+impl Published {
+    /// Whether the port can be forwarded to: TCP, and bound to every address rather than
+    /// only to the loopback of the distro.
+    pub fn forwardable(&self) -> bool {
+        self.protocol == "tcp" && matches!(&*self.address, "0.0.0.0" | "::")
+    }
+}
+
+fn record_path(config: &Config) -> PathBuf {
+    state::state_dir().join(format!("{}.portproxy.json", config.distro_name))
+}
+
+/// The rules the wrapper added for the distro and did not remove yet.
+pub fn recorded(config: &Config) -> Vec<Rule> {
+    fs::read_to_string(record_path(config))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn record(config: &Config, rules: &[Rule]) -> Result<()> {
+    if rules.is_empty() {
+        let _ = fs::remove_file(record_path(config));
+        return Ok(());
+    }
+
+    fs::create_dir_all(state::state_dir())?;
+    fs::write(record_path(config), serde_json::to_string_pretty(rules)?)?;
+    Ok(())
+}
+
+/// Ports the running containers publish, parsed from `docker ps`.
+pub fn published(config: &Config) -> Result<Vec<(String, Published)>> {
+    let ps = output_in_wsl(
+        config,
+        &["docker", "ps", "--format", "{{.Names}}\t{{.Ports}}"],
+    )?;
+
+    Ok(ps
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .flat_map(|(name, ports)| {
+            parse_ps_ports(ports)
+                .into_iter()
+                .map(|port| (name.to_string(), port))
+        })
+        .collect())
+}
+
+/// Parses the `Ports` column of `docker ps`, such as `0.0.0.0:8080->80/tcp, [::]:8080->80/tcp`.
+/// Ports that are only exposed, as in `80/tcp`, are left out.
+pub fn parse_ps_ports(ports: &str) -> Vec<Published> {
+    let mut published = vec![];
+    for entry in ports.split(',').map(str::trim) {
+        let Some((host, container)) = entry.split_once("->") else {
+            continue;
+        };
+        let (container, protocol) = container.split_once('/').unwrap_or((container, "tcp"));
+        let Some((address, host_ports)) = host.rsplit_once(':') else {
+            continue;
+        };
+        let address = address.trim_start_matches('[').trim_end_matches(']');
+        // The old notation of every IPv6 address is `:::8080`, which leaves `::` here.
+        let address = if address.is_empty() { "::" } else { address };
+        let (Some(host_ports), Some(container_ports)) =
+            (parse_port_range(host_ports), parse_port_range(container))
+        else {
+            continue;
+        };
+        for (host_port, container_port) in host_ports.zip(container_ports) {
+            published.push(Published {
+                address: address.to_string(),
+                host_port,
+                container_port,
+                protocol: protocol.to_string(),
+            });
+        }
+    }
+
+    published
+}
+
+/// Host ports a `docker run` publishes on every address with `-p`, for forwarding them before
+/// the container starts. Ports picked by the daemon are only known once it runs.
+pub fn run_publish_ports(args: &[String]) -> Vec<u16> {
+    let Some(command_index) = first_positional(&scan(args, GLOBAL_FLAGS, false)) else {
+        return vec![];
+    };
+    let args = &args[command_index..];
+    let Some((spec, start)) = resolve_command(args) else {
+        return vec![];
+    };
+    if spec.name != "run" {
+        return vec![];
+    }
+
+    let args = &args[start..];
+    let mut ports = vec![];
+    for token in scan(args, spec.flags, spec.interspersed) {
+        let Token::Flag {
+            spec: Some(flag),
+            value: Some(value),
+            ..
+        } = token
+        else {
+            continue;
+        };
+        if flag.names.contains(&"--publish") {
+            ports.extend(publish_spec_ports(&args[value.index][value.offset..]));
+        }
+    }
+
+    ports
+}
+
+/// Host ports of a `-p` value such as `8080:80`, `0.0.0.0:8080-8081:80-81/tcp` or
+/// `[::]:8080:80`, when published on every address over TCP.
+fn publish_spec_ports(spec: &str) -> Vec<u16> {
+    let (spec, protocol) = spec.rsplit_once('/').unwrap_or((spec, "tcp"));
+    let Some((rest, _container)) = spec.rsplit_once(':') else {
+        return vec![];
+    };
+    let (address, host) = match rest.rsplit_once(':') {
+        Some((address, host)) => (address.trim_matches(['[', ']']), host),
+        None => ("", rest),
+    };
+    if protocol != "tcp" || !matches!(address, "" | "0.0.0.0" | "::") {
+        return vec![];
+    }
+
+    parse_port_range(host).map_or(vec![], Iterator::collect)
+}
+
+fn parse_port_range(range: &str) -> Option<std::ops::RangeInclusive<u16>> {
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+
+    (first <= last).then_some(first..=last)
+}
+
+/// Whether the docker command in `args` may start or stop containers publishing ports.
+pub fn changes_ports(args: &[String]) -> bool {
+    let Some(command_index) = first_positional(&scan(args, GLOBAL_FLAGS, false)) else {
+        return false;
+    };
+    let mut name = &*args[command_index];
+    if name == "container" {
+        name = args.get(command_index + 1).map_or("", |arg| &**arg);
+    }
+
+    matches!(
+        name,
+        "run" | "start" | "restart" | "stop" | "kill" | "rm" | "compose"
+    )
+}
+
+/// Address of the distro on the virtual network of WSL, as opposed to those of the bridges
+/// of docker.
+fn distro_address(config: &Config) -> Result<String> {
+    let addresses = output_in_wsl(config, &["ip", "-4", "-o", "addr", "show", "dev", "eth0"])?;
+    // Lines such as `2: eth0    inet 172.20.1.2/20 brd ... scope global eth0`.
+    addresses
+        .split_whitespace()
+        .skip_while(|field| *field != "inet")
+        .nth(1)
+        .and_then(|cidr| cidr.split('/').next())
+        .map(str::to_string)
+        .context("the distro has no network address")
+}
+
+/// Makes the recorded rules forward exactly the ports the running containers publish on
+/// every address, plus `extra`: missing rules are added, rules for ports no longer published
+/// removed, and rules to an address the distro no longer has pointed at the current one.
+pub fn sync(config: &Config, extra: &[u16]) -> Result<()> {
+    if vm::networking_mode(config) == "mirrored" {
+        debug!("mirrored networking reaches published ports directly");
+        return remove_all(config);
+    }
+
+    let address = distro_address(config)?;
+    let wanted: BTreeSet<u16> = published(config)?
+        .into_iter()
+        .filter(|(_, port)| port.forwardable())
+        .map(|(_, port)| port.host_port)
+        .chain(extra.iter().copied())
+        .collect();
+
+    let mut rules = recorded(config);
+    let result = reconcile(config, &mut rules, &wanted, &address);
+    record(config, &rules)?;
+
+    result
+}
+
+/// Removes every rule the wrapper added for the distro.
+pub fn remove_all(config: &Config) -> Result<()> {
+    let mut rules = recorded(config);
+    let result = reconcile(config, &mut rules, &BTreeSet::new(), "");
+    record(config, &rules)?;
+
+    result
+}
+
+/// Brings `rules` in line with `wanted` one rule at a time, so that `rules` still tells which
+/// rules exist when netsh fails halfway.
+fn reconcile(
+    config: &Config,
+    rules: &mut Vec<Rule>,
+    wanted: &BTreeSet<u16>,
+    address: &str,
+) -> Result<()> {
+    let firewall = config.network.lan_firewall;
+    while let Some(i) = rules.iter().position(|rule| {
+        !wanted.contains(&rule.port) || rule.address != address || rule.firewall != firewall
+    }) {
+        let rule = &rules[i];
+        if rule.firewall {
+            delete_firewall_rule(config, rule.port)?;
+        }
+        delete_rule(rule.port)?;
+        info!("stopped forwarding port {} from the LAN", rule.port);
+        rules.remove(i);
+    }
+    for &port in wanted {
+        if rules.iter().any(|rule| rule.port == port) {
+            continue;
+        }
+        add_rule(port, address)?;
+        // Recorded without the firewall rule until it exists, so that a failure leaves a
+        // mismatch the next sync starts over from.
+        rules.push(Rule {
+            port,
+            address: address.to_string(),
+            firewall: false,
+        });
+        if firewall {
+            add_firewall_rule(config, port)?;
+            rules.last_mut().unwrap().firewall = true;
+        }
+        info!(
+            "forwarding port {port} from the LAN to '{}'",
+            config.distro_name
+        );
+    }
+
+    Ok(())
+}
+
+fn add_rule(port: u16, address: &str) -> Result<()> {
+    let port = port.to_string();
+    ensure!(
+        run(
+            &[
+                "netsh",
+                "interface",
+                "portproxy",
+                "add",
+                "v4tov4",
+                &format!("listenport={port}"),
+                "listenaddress=0.0.0.0",
+                &format!("connectport={port}"),
+                &format!("connectaddress={address}"),
+            ],
+            true
+        )?,
+        "failed to forward port {port} with netsh; it takes an elevated terminal"
+    );
+
+    Ok(())
+}
+
+/// Deletes the rule for `port`, which is fine to be gone already.
+fn delete_rule(port: u16) -> Result<()> {
+    ensure!(
+        run(
+            &[
+                "netsh",
+                "interface",
+                "portproxy",
+                "delete",
+                "v4tov4",
+                &format!("listenport={port}"),
+                "listenaddress=0.0.0.0",
+            ],
+            true
+        )? || !rule_exists(port),
+        "failed to remove the forwarding of port {port} with netsh; it takes an elevated terminal"
+    );
+
+    Ok(())
+}
+
+/// Name of the firewall rule letting `port` in, which tells the rules the wrapper made apart
+/// in `wf.msc` and `netsh advfirewall firewall show rule name=all`.
+pub fn firewall_rule_name(config: &Config, port: u16) -> String {
+    format!("docker-wrapper {} tcp/{port}", config.distro_name)
+}
+
+fn add_firewall_rule(config: &Config, port: u16) -> Result<()> {
+    let name = firewall_rule_name(config, port);
+    ensure!(
+        run(
+            &[
+                "netsh",
+                "advfirewall",
+                "firewall",
+                "add",
+                "rule",
+                &format!("name={name}"),
+                "dir=in",
+                "action=allow",
+                "protocol=TCP",
+                &format!("localport={port}"),
+                &format!("remoteip={}", config.network.lan_firewall_scope),
+                "description=Forwarded to a container by docker-wrapper; removed with the forwarding",
+            ],
+            true
+        )?,
+        "failed to add firewall rule '{name}' with netsh; it takes an elevated terminal"
+    );
+
+    Ok(())
+}
+
+/// Deletes the firewall rule for `port`, which is fine to be gone already.
+fn delete_firewall_rule(config: &Config, port: u16) -> Result<()> {
+    let name = format!("name={}", firewall_rule_name(config, port));
+    let deleted = run(
+        &["netsh", "advfirewall", "firewall", "delete", "rule", &name],
+        true,
+    )?;
+    ensure!(
+        deleted || !firewall_rule_exists(config, port),
+        "failed to delete firewall rule '{}' with netsh; it takes an elevated terminal",
+        firewall_rule_name(config, port)
+    );
+
+    Ok(())
+}
+
+pub fn firewall_rule_exists(config: &Config, port: u16) -> bool {
+    let name = format!("name={}", firewall_rule_name(config, port));
+    run(
+        &["netsh", "advfirewall", "firewall", "show", "rule", &name],
+        true,
+    )
+    .unwrap_or(false)
+}
+
+fn rule_exists(port: u16) -> bool {
+    let Ok(rules) = output(&["netsh", "interface", "portproxy", "show", "v4tov4"]) else {
+        return false;
+    };
+    let port = port.to_string();
+
+    rules.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        fields.next() == Some("0.0.0.0") && fields.next() == Some(&port)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn published_ports_are_parsed_from_docker_ps() {
+        let ports = parse_ps_ports("0.0.0.0:8080->80/tcp, :::8080->80/tcp, 127.0.0.1:5432->5432/tcp, 9000/tcp, 0.0.0.0:7000-7001->7000-7001/udp");
+        let forwardable: Vec<u16> = ports
+            .iter()
+            .filter(|port| port.forwardable())
+            .map(|port| port.host_port)
+            .collect();
+        assert_eq!(ports.len(), 5);
+        assert_eq!(ports[1].address, "::");
+        assert_eq!(forwardable, [8080, 8080]);
+    }
+
+    #[test]
+    fn run_publish_ports_skip_loopback_udp_and_random_ports() {
+        let args: Vec<String> = [
+            "run",
+            "-d",
+            "-p",
+            "8080:80",
+            "--publish=127.0.0.1:5432:5432",
+            "-p",
+            "53:53/udp",
+            "-p",
+            "9000",
+            "-p[::]:7000-7001:7000-7001",
+            "nginx",
+            "-p",
+            "1:1",
+        ]
+        .map(String::from)
+        .into();
+        assert_eq!(run_publish_ports(&args), [8080, 7000, 7001]);
+    }
+}
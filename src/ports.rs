@@ -0,0 +1,109 @@
+//! `docker docker-wrapper ports list`: the ports running containers publish and how each is
+//! reached from Windows, for when a container cannot be.
+
+use crate::{config::Config, portproxy, vm};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Mapping {
+    pub container: String,
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: String,
+    /// Whether the port is bound to every address of the distro rather than its loopback.
+    pub all_addresses: bool,
+    /// Whether something accepts connections on the port of Windows' `localhost`. Only
+    /// checked for TCP.
+    pub localhost: bool,
+    /// Whether the wrapper forwards the port from the LAN with a portproxy rule.
+    pub portproxy: bool,
+    /// Whether the wrapper's firewall rule for the port exists.
+    pub firewall: bool,
+    /// Address to use from Windows, if the port is reachable there.
+    pub url: Option<String>,
+}
+
+pub fn query(config: &Config) -> Result<Vec<Mapping>> {
+    let rules = portproxy::recorded(config);
+    let mut mappings: Vec<Mapping> = vec![];
+    for (container, port) in portproxy::published(config)? {
+        // The IPv4 and the IPv6 binding of a port show up separately.
+        if let Some(mapping) = mappings.iter_mut().find(|mapping| {
+            mapping.container == container
+                && mapping.host_port == port.host_port
+                && mapping.protocol == port.protocol
+        }) {
+            mapping.all_addresses |= port.forwardable();
+            continue;
+        }
+
+        let rule = rules.iter().find(|rule| rule.port == port.host_port);
+        let localhost = port.protocol == "tcp" && accepts_connections(port.host_port);
+        mappings.push(Mapping {
+            host_port: port.host_port,
+            container_port: port.container_port,
+            all_addresses: port.forwardable(),
+            localhost,
+            portproxy: rule.is_some(),
+            firewall: rule.is_some_and(|rule| rule.firewall)
+                && portproxy::firewall_rule_exists(config, port.host_port),
+            url: localhost.then(|| format!("http://localhost:{}", port.host_port)),
+            protocol: port.protocol,
+            container,
+        });
+    }
+
+    Ok(mappings)
+}
+
+fn accepts_connections(port: u16) -> bool {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    TcpStream::connect_timeout(&address, Duration::from_millis(500)).is_ok()
+}
+
+pub fn print(config: &Config, mappings: &[Mapping], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(mappings)?);
+        return Ok(());
+    }
+    if mappings.is_empty() {
+        println!("no running container publishes a port");
+        return Ok(());
+    }
+
+    let mirrored = vm::networking_mode(config) == "mirrored";
+    println!(
+        "{:<24} {:<18} {:<10} {:<24} URL",
+        "CONTAINER", "PORT", "LOCALHOST", "LAN"
+    );
+    for mapping in mappings {
+        let port = format!(
+            "{}->{}/{}",
+            mapping.host_port, mapping.container_port, mapping.protocol
+        );
+        let localhost = match (mapping.protocol.as_str(), mapping.localhost) {
+            ("tcp", true) => "yes",
+            ("tcp", false) => "no",
+            _ => "unchecked",
+        };
+        let lan = match (mapping.all_addresses, mirrored, mapping.portproxy) {
+            (false, _, _) => "no (bound to loopback)",
+            (true, true, _) => "yes (mirrored)",
+            (true, false, true) if mapping.firewall => "forwarded, firewall open",
+            (true, false, true) => "forwarded",
+            (true, false, false) => "no (see `ports sync`)",
+        };
+        println!(
+            "{:<24} {port:<18} {localhost:<10} {lan:<24} {}",
+            mapping.container,
+            mapping.url.as_deref().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,309 @@
+//! `docker docker-wrapper enable <preset>`: sets the daemon up for a particular use, such as
+//! tools that talk to the Docker API themselves or builds for other architectures.
+
+use crate::{
+    config::{home_dir, Config},
+    daemon_config,
+    flavor::Arch,
+    wsl::{output_in_wsl, run, run_in_wsl, write_file_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    time::Duration,
+};
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Preset {
+    /// Testcontainers for Java, .NET, Go and the like.
+    Testcontainers,
+    /// Building and running images of other architectures through QEMU.
+    Multiarch,
+    /// `docker run --gpus` with the NVIDIA GPUs that WSL shares with the distro.
+    Gpu,
+}
+
+/// Directory of scripts run in the distro each time the wrapper starts the daemon, for
+/// state that does not survive WSL shutting down.
+pub const START_HOOKS_DIR: &str = "/etc/docker-wrapper/start.d";
+
+/// Port the daemon serves TLS on for presets, the conventional one for docker over TLS.
+pub const PRESET_TLS_PORT: u16 = 2376;
+
+/// Image of the ryuk reaper that Testcontainers starts next to every test session.
+const RYUK_IMAGE: &str = "testcontainers/ryuk:0.11.0";
+
+/// Environment Testcontainers needs to reach the daemon served with TLS on `port` of
+/// localhost. Mapped ports are reachable on localhost too, thanks to WSL's forwarding, and
+/// ryuk gets the socket inside the distro mounted rather than the TCP endpoint.
+pub fn testcontainers_env(port: u16, cert_dir: &Path) -> Vec<(&'static str, String)> {
+    vec![
+        ("DOCKER_HOST", format!("tcp://localhost:{port}")),
+        ("DOCKER_TLS_VERIFY", "1".to_string()),
+        ("DOCKER_CERT_PATH", cert_dir.display().to_string()),
+        ("TESTCONTAINERS_HOST_OVERRIDE", "localhost".to_string()),
+        (
+            "TESTCONTAINERS_DOCKER_SOCKET_OVERRIDE",
+            "/var/run/docker.sock".to_string(),
+        ),
+    ]
+}
+
+/// Writes the endpoint into `~/.testcontainers.properties`, which Testcontainers for Java
+/// reads when the environment is not set, keeping the other lines of the file.
+pub fn write_testcontainers_properties(port: u16, cert_dir: &Path) -> Result<()> {
+    let path = home_dir().join(".testcontainers.properties");
+    let properties = [
+        ("docker.host", format!("tcp://localhost:{port}")),
+        ("docker.tls.verify", "1".to_string()),
+        (
+            "docker.cert.path",
+            cert_dir.display().to_string().replace('\\', "/"),
+        ),
+    ];
+
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    let mut lines: Vec<String> = existing
+        .lines()
+        .filter(|line| {
+            let key = line.split('=').next().unwrap_or_default().trim();
+            properties.iter().all(|(name, _)| *name != key)
+        })
+        .map(|line| line.to_string())
+        .collect();
+    lines.extend(
+        properties
+            .iter()
+            .map(|(name, value)| format!("{name}={value}")),
+    );
+
+    fs::write(&path, lines.join("\n") + "\n")
+        .with_context(|| format!("failed to write '{}'", path.display()))
+}
+
+/// Starts ryuk the way Testcontainers does and checks that its published port answers on
+/// localhost, which covers both the socket mount and host port mapping.
+pub fn check_ryuk(config: &Config) -> Result<()> {
+    info!("checking that ryuk can start...");
+    let id = output_in_wsl(
+        config,
+        &[
+            "docker",
+            "run",
+            "-d",
+            "--rm",
+            "-P",
+            "-v",
+            "/var/run/docker.sock:/var/run/docker.sock",
+            "-e",
+            "RYUK_CONNECTION_TIMEOUT=10s",
+            RYUK_IMAGE,
+        ],
+    )
+    .with_context(|| format!("failed to start ryuk from '{RYUK_IMAGE}'"))?
+    .trim()
+    .to_string();
+
+    let result = (|| {
+        let port = output_in_wsl(config, &["docker", "port", &id, "8080/tcp"])?;
+        let port = port
+            .lines()
+            .next()
+            .and_then(|addr| addr.rsplit(':').next())
+            .context("ryuk has no published port")?
+            .trim()
+            .to_string();
+        debug!("ryuk is published on port {port}");
+
+        let addr = ("localhost", port.parse::<u16>()?)
+            .to_socket_addrs()?
+            .next()
+            .context("failed to resolve localhost")?;
+        TcpStream::connect_timeout(&addr, Duration::from_secs(5)).with_context(|| {
+            format!("ryuk started but localhost:{port} is unreachable; check localhost forwarding of WSL")
+        })?;
+
+        Ok(())
+    })();
+    let _ = output_in_wsl(config, &["docker", "rm", "-f", &id]);
+
+    result
+}
+
+/// Image registering the QEMU emulators of every architecture with binfmt_misc.
+const BINFMT_IMAGE: &str = "tonistiigi/binfmt";
+
+/// Name of the buildx builder created for multi-platform builds.
+pub const MULTIARCH_BUILDER: &str = "multiarch";
+
+/// Registers QEMU for foreign binaries, now and from a start hook since the registration
+/// lives in the kernel of the WSL VM, then creates a multi-platform buildx builder and runs
+/// an image of the other architecture to check it.
+pub fn enable_multiarch(config: &Config) -> Result<()> {
+    info!("registering QEMU emulators...");
+    // The daemon may still be starting when the hook runs, so it waits in the background.
+    let hook = format!(
+        "#!/bin/sh\n\
+         ls /proc/sys/fs/binfmt_misc/qemu-* >/dev/null 2>&1 && exit 0\n\
+         (until docker info >/dev/null 2>&1; do sleep 1; done; \
+         docker run --privileged --rm {BINFMT_IMAGE} --install all) >/dev/null 2>&1 &\n"
+    );
+    let hook_path = format!("{START_HOOKS_DIR}/binfmt");
+    ensure!(
+        write_file_in_wsl(config, &hook_path, &hook)?
+            && run_in_wsl(config, &["chmod", "+x", &hook_path], true)?,
+        "failed to install the binfmt start hook"
+    );
+    ensure!(
+        run_in_wsl(
+            config,
+            &[
+                "docker",
+                "run",
+                "--privileged",
+                "--rm",
+                BINFMT_IMAGE,
+                "--install",
+                "all"
+            ],
+            false
+        )?,
+        "failed to register the QEMU emulators"
+    );
+
+    info!("creating buildx builder '{MULTIARCH_BUILDER}'...");
+    let builder_exists = run_in_wsl(
+        config,
+        &["docker", "buildx", "inspect", MULTIARCH_BUILDER],
+        true,
+    )?;
+    if !builder_exists {
+        ensure!(
+            run_in_wsl(
+                config,
+                &[
+                    "docker",
+                    "buildx",
+                    "create",
+                    "--name",
+                    MULTIARCH_BUILDER,
+                    "--driver",
+                    "docker-container",
+                    "--platform",
+                    "linux/amd64,linux/arm64",
+                    "--bootstrap",
+                    "--use",
+                ],
+                false
+            )?,
+            "failed to create buildx builder '{MULTIARCH_BUILDER}'"
+        );
+    }
+
+    let foreign = match Arch::host() {
+        Arch::Amd64 => "linux/arm64",
+        Arch::Arm64 => "linux/amd64",
+    };
+    info!("running hello-world for {foreign}...");
+    ensure!(
+        run_in_wsl(
+            config,
+            &[
+                "docker",
+                "run",
+                "--rm",
+                "--platform",
+                foreign,
+                "hello-world"
+            ],
+            true
+        )?,
+        "hello-world for {foreign} does not run; check that the WSL kernel supports binfmt_misc"
+    );
+
+    Ok(())
+}
+
+/// Image the GPU smoke test runs `nvidia-smi` in, which the toolkit mounts from the driver.
+const GPU_TEST_IMAGE: &str = "ubuntu";
+
+/// Installs the NVIDIA container toolkit and registers its runtime in `daemon.json`, once WSL
+/// is seen to share a GPU with the distro. Returns `daemon.json` from before, and the daemon has
+/// to be restarted afterwards.
+pub fn enable_gpu(config: &Config) -> Result<Map<String, Value>> {
+    // WSL shares the GPU through /dev/dxg, and the Windows driver provides libcuda.
+    let paravirtualized = run_in_wsl(
+        config,
+        &[
+            "sh",
+            "-c",
+            "[ -e /dev/dxg ] && ls /usr/lib/wsl/lib/libcuda.so* >/dev/null 2>&1",
+        ],
+        true,
+    )?;
+    ensure!(
+        paravirtualized,
+        "WSL shares no NVIDIA GPU with '{}'; install a current NVIDIA driver on Windows and run `wsl --update`",
+        config.distro_name
+    );
+
+    let script = config.flavor.nvidia_toolkit_script().with_context(|| {
+        format!(
+            "the NVIDIA container toolkit does not support {}",
+            config.flavor
+        )
+    })?;
+    let before = daemon_config::read(config)?;
+    info!("installing the NVIDIA container toolkit...");
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", script], false)?,
+        "failed to install the NVIDIA container toolkit"
+    );
+    ensure!(
+        run_in_wsl(
+            config,
+            &["nvidia-ctk", "runtime", "configure", "--runtime=docker"],
+            true
+        )?,
+        "failed to register the NVIDIA runtime in daemon.json"
+    );
+
+    Ok(before)
+}
+
+/// Runs `nvidia-smi` in a container with every GPU, as a tool using `--gpus` would.
+pub fn check_gpu(config: &Config) -> Result<()> {
+    info!("running nvidia-smi in a container...");
+    ensure!(
+        run_in_wsl(
+            config,
+            &[
+                "docker",
+                "run",
+                "--rm",
+                "--gpus",
+                "all",
+                GPU_TEST_IMAGE,
+                "nvidia-smi"
+            ],
+            false
+        )?,
+        "containers cannot use the GPU; see `docker docker-wrapper logs`"
+    );
+
+    Ok(())
+}
+
+/// Stores `env` in the user environment of Windows with `setx`, for programs started later.
+pub fn persist_env(env: &[(&str, String)]) -> Result<()> {
+    for (name, value) in env {
+        ensure!(run(&["setx", name, value], true)?, "failed to set {name}");
+    }
+
+    Ok(())
+}
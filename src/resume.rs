@@ -0,0 +1,48 @@
+//! Recovery after Windows sleeps. The clock of the distro stops along with the VM and falls
+//! behind, which breaks TLS and apt until it is set again, and a daemon caught mid-request
+//! by the suspend sometimes stops answering.
+
+use crate::{
+    config::Config,
+    wsl::{output_in_wsl, run_in_wsl},
+};
+use anyhow::{ensure, Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Difference from the Windows clock, in seconds, past which the distro is taken to have slept.
+const MAX_SKEW: i64 = 10;
+
+/// Sets the clock of the distro from the one of Windows when it drifted, returning whether it
+/// had. The time of Windows is used rather than `hwclock -s`, as the emulated RTC is not
+/// always there nor right after a resume.
+pub fn resync_clock(config: &Config) -> Result<bool> {
+    let distro_now = output_in_wsl(config, &["date", "+%s"])?;
+    let distro_now: i64 = distro_now
+        .trim()
+        .parse()
+        .with_context(|| format!("unexpected output of date: {distro_now:?}"))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let skew = now - distro_now;
+    debug!(skew, "compared the clock of the distro with Windows");
+    if skew.abs() < MAX_SKEW {
+        return Ok(false);
+    }
+
+    info!(
+        "the clock of '{}' is off by {skew}s, likely after Windows slept; setting it",
+        config.distro_name
+    );
+    ensure!(
+        run_in_wsl(config, &["date", "-u", "-s", &format!("@{now}")], true)?,
+        "failed to set the clock of '{}'",
+        config.distro_name
+    );
+
+    Ok(true)
+}
+
+/// Whether the daemon answers within a few seconds, rather than hanging.
+pub fn daemon_responds(config: &Config) -> Result<bool> {
+    run_in_wsl(config, &["timeout", "10", "docker", "version"], true)
+}
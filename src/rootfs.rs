@@ -0,0 +1,261 @@
+//! Getting the rootfs tarball of the docker host and importing it into WSL.
+
+use crate::{
+    config::Config,
+    download, state,
+    status::format_size,
+    wsl::{output, run},
+};
+use anyhow::{bail, ensure, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Where the rootfs downloaded from `rootfs_url` is kept, named like the file it came from
+/// so that a different release is not mistaken for the one already downloaded.
+pub fn download_path(config: &Config, rootfs_url: &str) -> PathBuf {
+    let name = rootfs_url
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or("rootfs.tar.gz");
+    config.distro_dir().join(name)
+}
+
+/// Downloads the rootfs, or what is missing of it, returning where it was saved. The tarball
+/// is fetched into a `.part` file and only takes its real name once its SHA-256 matches, so
+/// that a file under that name is always complete; a mismatch deletes it.
+pub fn download(config: &Config, rootfs_url: &str) -> Result<PathBuf> {
+    config.validate_storage_dir()?;
+    let download_path = download_path(config, rootfs_url);
+    if download_path.exists() {
+        info!("using '{}' downloaded earlier", download_path.display());
+        return Ok(download_path);
+    }
+
+    fs::create_dir_all(config.distro_dir())?;
+    let expected = match &config.rootfs_sha256 {
+        Some(hash) => Some(hash.to_ascii_lowercase()),
+        None => published_sha256(config, rootfs_url),
+    };
+
+    let part_path = part_path(&download_path);
+    info!("downloading '{}'...", download_path.display());
+    download::fetch_to_file(config, rootfs_url, &part_path)?;
+
+    match expected {
+        Some(expected) => verify(&part_path, &expected)?,
+        None => warn!("no checksum published for '{rootfs_url}'; importing it unverified"),
+    }
+    fs::rename(&part_path, &download_path)?;
+
+    Ok(download_path)
+}
+
+/// Where the download to `path` is kept until it is complete and verified.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Looks the SHA-256 of the tarball up where distros publish it next to their images:
+/// a `SHA256SUMS` listing the directory, or a `<file>.sha256` of its own.
+fn published_sha256(config: &Config, rootfs_url: &str) -> Option<String> {
+    let (dir, name) = rootfs_url.rsplit_once('/')?;
+    [format!("{dir}/SHA256SUMS"), format!("{rootfs_url}.sha256")]
+        .iter()
+        .filter_map(|url| download::fetch_text(config, url).ok())
+        .find_map(|sums| find_sha256(&sums, name))
+}
+
+/// Finds the hash of `name` in the output of `sha256sum`, whose lines are a hash followed by
+/// the file name, starred in binary mode. A file holding nothing but a hash matches as well.
+fn find_sha256(sums: &str, name: &str) -> Option<String> {
+    let is_hash = |hash: &str| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+    sums.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let hash = fields.next().filter(|hash| is_hash(hash))?;
+        match fields.next() {
+            Some(file) if file.trim_start_matches('*') != name => None,
+            _ => Some(hash.to_ascii_lowercase()),
+        }
+    })
+}
+
+/// Fails early when the drive of `storage_dir` lacks room for the distro, rather than let
+/// `wsl --import` fail halfway or fill the drive. The rootfs at `rootfs_url` is counted
+/// unless it is downloaded already, and so are `extra` bytes the caller needs on top.
+pub fn check_free_space(config: &Config, rootfs_url: Option<&str>, extra: u64) -> Result<()> {
+    if config.skip_disk_check {
+        return Ok(());
+    }
+    config.validate_storage_dir()?;
+
+    let mut needed = config.flavor.installed_size() + extra;
+    if let Some(url) = rootfs_url {
+        if !download_path(config, url).exists() {
+            needed += config.flavor.download_size();
+        }
+    }
+    let free = match free_space(&config.storage_dir) {
+        Ok(free) => free,
+        Err(err) => {
+            warn!("could not check the free disk space: {err:#}");
+            return Ok(());
+        }
+    };
+    ensure!(
+        free >= needed,
+        "not enough disk space for '{}' in '{}': {} free, about {} needed; \
+         free some up, set another storage_dir or pass --skip-disk-check",
+        config.distro_name,
+        config.storage_dir.display(),
+        format_size(free),
+        format_size(needed)
+    );
+
+    Ok(())
+}
+
+/// Bytes available to the user on the drive of `dir`.
+#[cfg(windows)]
+fn free_space(dir: &Path) -> Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain([0]).collect();
+    let mut available = 0;
+    // SAFETY: `wide` is NUL-terminated and the out pointer is valid for the call; the
+    // totals that are not wanted may be null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    ensure!(ok != 0, "{}", std::io::Error::last_os_error());
+
+    Ok(available)
+}
+
+#[cfg(not(windows))]
+fn free_space(dir: &Path) -> Result<u64> {
+    let df = output(&["df", "-Pk", &dir.display().to_string()])?;
+    let kb: u64 = df
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .context("unexpected output of df")?
+        .parse()?;
+
+    Ok(kb * 1024)
+}
+
+/// Checks the SHA-256 of `tarball`, deleting it on a mismatch so that the next attempt
+/// downloads it again rather than importing a truncated file.
+pub fn verify(tarball: &Path, expected: &str) -> Result<()> {
+    info!("verifying '{}'...", tarball.display());
+    let mut hasher = Sha256::new();
+    let mut file = File::open(tarball)?;
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let actual: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    if actual != expected {
+        let _ = fs::remove_file(tarball);
+        bail!(
+            "checksum mismatch for '{}' (expected {expected}, got {actual}); deleted it",
+            tarball.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a tarball supplied by the user is a rootfs before WSL is handed it: a
+/// readable archive with an `os-release` in the usual places. The `tar` of Windows reads
+/// every compression WSL imports.
+pub fn validate(tarball: &Path) -> Result<()> {
+    ensure!(
+        tarball.is_file(),
+        "rootfs file '{}' does not exist",
+        tarball.display()
+    );
+
+    let listing = output(&["tar", "-tf", &tarball.display().to_string()])
+        .with_context(|| format!("'{}' is not a readable tar archive", tarball.display()))?;
+    let has_os_release = listing.lines().any(|entry| {
+        let entry = entry.trim_start_matches("./");
+        entry == "etc/os-release" || entry == "usr/lib/os-release"
+    });
+    ensure!(
+        has_os_release,
+        "'{}' does not look like a rootfs: it has no etc/os-release",
+        tarball.display()
+    );
+
+    Ok(())
+}
+
+/// Imports `tarball` as the distro, with its disk image in the distro directory.
+pub fn import(config: &Config, tarball: &Path) -> Result<()> {
+    config.validate_storage_dir()?;
+    let path = config.distro_dir();
+    let distro_root_path = path.join("root");
+    fs::create_dir_all(&distro_root_path)?;
+
+    info!("importing distro '{}'...", config.distro_name);
+    ensure!(
+        run(
+            &[
+                "wsl",
+                "--import",
+                &config.distro_name,
+                &distro_root_path.display().to_string(),
+                &tarball.display().to_string()
+            ],
+            false,
+        )?,
+        "failed to import distro"
+    );
+    state::record_distro_dir(config, &path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn hash_is_found_by_file_name() {
+        let sums = format!("{} *other.tar.gz\n{HASH} *rootfs.tar.gz\n", "0".repeat(64));
+        assert_eq!(find_sha256(&sums, "rootfs.tar.gz").as_deref(), Some(HASH));
+        assert_eq!(find_sha256(&sums, "missing.tar.gz"), None);
+    }
+
+    #[test]
+    fn bare_hash_matches_any_name() {
+        let sums = format!("{}\n", HASH.to_uppercase());
+        assert_eq!(find_sha256(&sums, "rootfs.tar.xz").as_deref(), Some(HASH));
+    }
+}
@@ -0,0 +1,83 @@
+//! Rootless mode: dockerd runs as an unprivileged user of the distro, under rootlesskit.
+//! `/var/run/docker.sock` is linked to the socket of that daemon and its `daemon.json` to
+//! `/etc/docker/daemon.json`, so that the wrapper, mounted sockets and the settings it writes
+//! reach the daemon as they would in the usual mode.
+
+use crate::{config::Config, flavor::Flavor, wsl::run_in_wsl};
+use anyhow::{ensure, Result};
+
+/// User of the distro the rootless daemon runs as.
+pub const USER: &str = "rootless";
+
+/// Shell script installing what rootless dockerd needs: the setuid helpers mapping the
+/// subordinate ids, user-mode networking and the rootless scripts of docker.
+fn packages_script(flavor: Flavor) -> &'static str {
+    match flavor {
+        Flavor::Ubuntu | Flavor::Debian => {
+            "apt-get install -y uidmap slirp4netns fuse-overlayfs docker-ce-rootless-extras"
+        }
+        Flavor::Alpine => {
+            "apk add --no-cache shadow shadow-subids slirp4netns fuse-overlayfs docker-rootless-extras"
+        }
+        Flavor::Fedora => {
+            "dnf install -y shadow-utils slirp4netns fuse-overlayfs docker-ce-rootless-extras"
+        }
+    }
+}
+
+/// Provisions the rootless daemon: the packages, the user with its subordinate ids, and the
+/// link to the shared `daemon.json`. The root daemon the engine packages may have started is
+/// stopped, since it would take the socket.
+pub fn provision(config: &Config) -> Result<()> {
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", packages_script(config.flavor)], false)?,
+        "failed to install the packages of rootless docker"
+    );
+
+    let script = format!(
+        "{stop}; \
+         id -u {USER} >/dev/null 2>&1 || useradd -m -s /bin/sh {USER} || exit 1; \
+         for ids in /etc/subuid /etc/subgid; do \
+             grep -q '^{USER}:' \"$ids\" 2>/dev/null || echo '{USER}:231072:65536' >>\"$ids\"; \
+         done; \
+         mkdir -p /etc/docker /home/{USER}/.config/docker \
+         && touch /etc/docker/daemon.json && chmod 644 /etc/docker/daemon.json \
+         && ln -sf /etc/docker/daemon.json /home/{USER}/.config/docker/daemon.json \
+         && chown -R {USER}: /home/{USER}/.config",
+        stop = config.flavor.stop_script(),
+    );
+    ensure!(
+        run_in_wsl(config, &["sh", "-c", &script], true)?,
+        "failed to set up user '{USER}' for rootless docker"
+    );
+
+    Ok(())
+}
+
+/// Shell script starting the rootless daemon, doing nothing if it already runs. `/run` is
+/// emptied when WSL shuts down, so the runtime directory and the socket link are made anew.
+pub fn start_script() -> String {
+    format!(
+        "uid=$(id -u {USER}) && run=/run/user/$uid \
+         && mkdir -p \"$run\" && chown {USER}: \"$run\" && chmod 700 \"$run\" \
+         && touch /var/log/docker.log && chown {USER} /var/log/docker.log \
+         && ln -sf \"$run/docker.sock\" /var/run/docker.sock \
+         && {{ pgrep -u \"$uid\" -x dockerd >/dev/null \
+               || su {USER} -s /bin/sh -c \"cd && XDG_RUNTIME_DIR=$run \
+                  PATH=/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin \
+                  nohup dockerd-rootless.sh >>/var/log/docker.log 2>&1 </dev/null &\"; }}"
+    )
+}
+
+/// Shell script stopping the rootless daemon and waiting for it to exit.
+pub fn stop_script() -> String {
+    format!(
+        "uid=$(id -u {USER}) || exit 0; \
+         pkill -u \"$uid\" -x rootlesskit; pkill -u \"$uid\" -x dockerd; \
+         while pgrep -u \"$uid\" -x dockerd >/dev/null; do sleep 0.1; done"
+    )
+}
+
+pub fn restart_script() -> String {
+    format!("{}; {}", stop_script(), start_script())
+}
@@ -0,0 +1,58 @@
+//! Snapshots of the docker host: the distro exported by `wsl --export`, with a small JSON file
+//! next to it recording what it was, so that it is restored the way it ran.
+
+use crate::{config::Config, flavor::Flavor};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Version of the wrapper that took the snapshot.
+    pub wrapper_version: String,
+    pub distro_name: String,
+    pub flavor: Flavor,
+    pub rootless: bool,
+    /// Version of docker in the distro, if it could be queried.
+    pub docker_version: Option<String>,
+}
+
+impl Metadata {
+    pub fn new(config: &Config, docker_version: Option<String>) -> Self {
+        Self {
+            wrapper_version: env!("CARGO_PKG_VERSION").to_string(),
+            distro_name: config.distro_name.clone(),
+            flavor: config.flavor,
+            rootless: config.daemon.rootless,
+            docker_version,
+        }
+    }
+}
+
+/// `<snapshot>.json`, next to the snapshot.
+pub fn metadata_path(snapshot: &Path) -> PathBuf {
+    let mut name = snapshot.file_name().unwrap_or_default().to_os_string();
+    name.push(".json");
+    snapshot.with_file_name(name)
+}
+
+pub fn write_metadata(snapshot: &Path, metadata: &Metadata) -> Result<()> {
+    let path = metadata_path(snapshot);
+    fs::write(&path, serde_json::to_string_pretty(metadata)?)
+        .with_context(|| format!("failed to write '{}'", path.display()))
+}
+
+/// The metadata of `snapshot`, `None` for a plain `wsl --export` taken without the wrapper.
+pub fn read_metadata(snapshot: &Path) -> Result<Option<Metadata>> {
+    let path = metadata_path(snapshot);
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content).with_context(|| {
+            format!("'{}' is not snapshot metadata", path.display())
+        })?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read '{}'", path.display())),
+    }
+}
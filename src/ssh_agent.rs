@@ -0,0 +1,130 @@
+//! Forwarding of the Windows OpenSSH agent for `docker build --ssh`. For the duration of the
+//! command, `socat` in the distro serves a socket given to docker as `SSH_AUTH_SOCK`, and
+//! relays each connection to the wrapper run through interop, which carries the requests over
+//! to the agent's named pipe.
+
+use crate::{
+    config::Config,
+    wsl::{convert_path, run_in_wsl, shell_quote},
+};
+use anyhow::{ensure, Context, Result};
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    process::{self, Child, Command, Stdio},
+};
+use tracing::debug;
+
+const PIPE: &str = r"\\.\pipe\openssh-ssh-agent";
+
+/// Serves the socket `$0` with the command `$1`, printing `ready` once it listens, until the
+/// input ends. socat splits the command of EXEC on spaces, so it goes into a script.
+const SERVE_SCRIPT: &str =
+    "umask 077; rm -f \"$0\"; printf '#!/bin/sh\\n%s\\n' \"$1\" > \"$0.sh\"; \
+     chmod +x \"$0.sh\"; socat UNIX-LISTEN:\"$0\",fork EXEC:\"$0.sh\" & pid=$!; \
+     while [ ! -S \"$0\" ] && kill -0 $pid 2>/dev/null; do sleep 0.05; done; \
+     [ -S \"$0\" ] && echo ready; cat >/dev/null; kill $pid; rm -f \"$0\" \"$0.sh\"";
+
+/// Whether `args` hand an ssh agent to a build, as opposed to keys named after the id
+/// (`--ssh default=~/.ssh/id_ed25519`).
+pub fn requested(args: &[String]) -> bool {
+    args.iter().enumerate().any(|(index, arg)| {
+        let value = match arg.strip_prefix("--ssh") {
+            Some("") => args.get(index + 1).map_or("", |value| &**value),
+            Some(rest) => match rest.strip_prefix('=') {
+                Some(value) => value,
+                None => return false,
+            },
+            None => return false,
+        };
+        !value.contains('=')
+    })
+}
+
+/// Socket in the distro relaying to the Windows agent, removed when dropped.
+pub struct Relay {
+    pub socket: String,
+    child: Child,
+}
+
+impl Relay {
+    pub fn start(config: &Config) -> Result<Self> {
+        let install = format!(
+            "command -v socat >/dev/null || {{ {}; }} >/dev/null",
+            config.flavor.socat_script()
+        );
+        ensure!(
+            run_in_wsl(config, &["sh", "-c", &install], true)?,
+            "failed to install socat in the distro"
+        );
+
+        let exe = convert_path(config, &env::current_exe()?.display().to_string())?;
+        let socket = format!("/tmp/docker-wrapper-ssh-{}.sock", process::id());
+        let relay = format!("exec {} docker-wrapper ssh-agent-relay", shell_quote(&exe));
+        let mut child = Command::new("wsl")
+            .args(["-d", &config.distro_name, "-e", "sh", "-c", SERVE_SCRIPT])
+            .args([&socket, &relay])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut line = String::new();
+        if let Some(stdout) = child.stdout.take() {
+            BufReader::new(stdout).read_line(&mut line)?;
+        }
+        let relay = Self { socket, child };
+        ensure!(
+            line.trim() == "ready",
+            "failed to serve the ssh agent socket"
+        );
+        debug!("relaying the ssh agent on {}", relay.socket);
+
+        Ok(relay)
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        drop(self.child.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// Carries the messages of an agent connection between stdio and the named pipe of the
+/// Windows agent. The protocol is request and response, each prefixed by its length, so one
+/// message is relayed at a time.
+pub fn relay() -> Result<()> {
+    let mut pipe = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(PIPE)
+        .with_context(|| format!("failed to connect to the ssh agent at {PIPE}"))?;
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    loop {
+        let Some(request) = read_message(&mut stdin)? else {
+            return Ok(());
+        };
+        pipe.write_all(&request)?;
+        pipe.flush()?;
+        let response = read_message(&mut pipe)?.context("the ssh agent closed the connection")?;
+        stdout.write_all(&response)?;
+        stdout.flush()?;
+    }
+}
+
+/// Reads a message with its length prefix, or `None` at the end of the input.
+fn read_message(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    let mut message = length.to_vec();
+    message.resize(4 + u32::from_be_bytes(length) as usize, 0);
+    reader.read_exact(&mut message[4..])?;
+    Ok(Some(message))
+}
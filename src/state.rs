@@ -0,0 +1,200 @@
+//! State the wrapper remembers between invocations, kept under its data directory.
+
+use crate::config::{data_dir, Config};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, TryLockError},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::info;
+
+pub fn state_dir() -> PathBuf {
+    data_dir().join("state")
+}
+
+/// File whose modification time is when the daemon was last seen accepting connections.
+fn alive_marker(config: &Config) -> PathBuf {
+    state_dir().join(format!("{}.alive", config.distro_name))
+}
+
+/// Whether the daemon was confirmed alive within `daemon.alive_cache` seconds, in which
+/// case checking and starting it again can be skipped.
+pub fn daemon_recently_alive(config: &Config) -> bool {
+    let ttl = Duration::from_secs(config.daemon.alive_cache);
+    fs::metadata(alive_marker(config))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed < ttl)
+}
+
+/// Records that the daemon is alive right now. Failing to do so only costs a check later.
+pub fn mark_daemon_alive(config: &Config) {
+    let _ = fs::create_dir_all(state_dir());
+    let _ = fs::write(alive_marker(config), "");
+}
+
+/// Forgets the daemon was alive, for when the wrapper stops it itself.
+pub fn forget_daemon_alive(config: &Config) {
+    let _ = fs::remove_file(alive_marker(config));
+}
+
+/// File whose modification time is when a docker command last went through the wrapper.
+fn used_marker(config: &Config) -> PathBuf {
+    state_dir().join(format!("{}.used", config.distro_name))
+}
+
+pub fn mark_used(config: &Config) {
+    let _ = fs::create_dir_all(state_dir());
+    let _ = fs::write(used_marker(config), "");
+}
+
+/// Time since a docker command last went through the wrapper, `None` if none ever did.
+pub fn unused_for(config: &Config) -> Option<Duration> {
+    fs::metadata(used_marker(config))
+        .and_then(|metadata| metadata.modified())
+        .ok()?
+        .elapsed()
+        .ok()
+}
+
+/// Deletes everything remembered about the distro, for when it is uninstalled. The lock file
+/// is left, as the caller may hold it.
+pub fn forget_distro(config: &Config) {
+    forget_daemon_alive(config);
+    forget_distro_dir(config);
+    let _ = fs::remove_file(used_marker(config));
+}
+
+/// Version of the layout recorded for installations, raised whenever the naming or storage of
+/// distros changes so that installations of an older layout can be told apart and migrated.
+/// Layout 0 is the plain `<distro>.location` file of the first versions.
+pub const LAYOUT: u32 = 1;
+
+/// What the wrapper knows about a distro it imported.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Installation {
+    pub layout: u32,
+    pub distro_name: String,
+    /// Profile of the config the distro was imported for, `None` for the top-level settings.
+    pub profile: Option<String>,
+    /// Directory the distro was imported into, which stays where it is when `storage_dir`
+    /// changes later.
+    pub dir: PathBuf,
+    pub wrapper_version: String,
+    /// Whether the distro was set up outside the wrapper and adopted, in which case `dir` is
+    /// where WSL keeps it and the wrapper never deletes it.
+    #[serde(default)]
+    pub adopted: bool,
+}
+
+fn installation_record(distro_name: &str) -> PathBuf {
+    state_dir().join(format!("{distro_name}.install.json"))
+}
+
+fn legacy_location_record(distro_name: &str) -> PathBuf {
+    state_dir().join(format!("{distro_name}.location"))
+}
+
+/// The record of the distro named `distro_name`, reading the location file of layout 0 as
+/// an installation of the top-level settings.
+pub fn installation(distro_name: &str) -> Option<Installation> {
+    if let Ok(content) = fs::read_to_string(installation_record(distro_name)) {
+        return serde_json::from_str(&content).ok();
+    }
+
+    let dir = fs::read_to_string(legacy_location_record(distro_name)).ok()?;
+    Some(Installation {
+        layout: 0,
+        distro_name: distro_name.to_string(),
+        profile: None,
+        dir: PathBuf::from(dir.trim()),
+        wrapper_version: String::new(),
+        adopted: false,
+    })
+}
+
+/// Every recorded installation, of any layout.
+pub fn installations() -> Vec<Installation> {
+    let Ok(entries) = fs::read_dir(state_dir()) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|name| {
+            let distro = name
+                .strip_suffix(".install.json")
+                .or(name.strip_suffix(".location"))?;
+            Some(distro.to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+
+    names.iter().filter_map(|name| installation(name)).collect()
+}
+
+/// Directory the distro was imported into. `None` for distros imported before locations were
+/// recorded.
+pub fn recorded_distro_dir(config: &Config) -> Option<PathBuf> {
+    installation(&config.distro_name).map(|installation| installation.dir)
+}
+
+/// Records the distro of `config` as imported into `dir`, in the current layout.
+pub fn record_distro_dir(config: &Config, dir: &Path) -> Result<()> {
+    record_installation(config, dir, false)
+}
+
+/// Records the distro of `config`, kept by WSL in `dir`, as adopted.
+pub fn record_adopted(config: &Config, dir: &Path) -> Result<()> {
+    record_installation(config, dir, true)
+}
+
+fn record_installation(config: &Config, dir: &Path, adopted: bool) -> Result<()> {
+    fs::create_dir_all(state_dir())?;
+    let installation = Installation {
+        layout: LAYOUT,
+        distro_name: config.distro_name.clone(),
+        profile: config.profile.clone(),
+        dir: dir.to_path_buf(),
+        wrapper_version: env!("CARGO_PKG_VERSION").to_string(),
+        adopted,
+    };
+    fs::write(
+        installation_record(&config.distro_name),
+        serde_json::to_string_pretty(&installation)?,
+    )?;
+    let _ = fs::remove_file(legacy_location_record(&config.distro_name));
+
+    Ok(())
+}
+
+pub fn forget_distro_dir(config: &Config) {
+    let _ = fs::remove_file(installation_record(&config.distro_name));
+    let _ = fs::remove_file(legacy_location_record(&config.distro_name));
+}
+
+/// Takes the lock held while the distro is checked and provisioned, so that commands started
+/// together on a fresh machine wait for one of them to import it rather than all importing
+/// it at once. The lock is released when the file is dropped, or when the process dies.
+pub fn lock_setup(config: &Config) -> Result<File> {
+    fs::create_dir_all(state_dir())?;
+    let path = state_dir().join(format!("{}.lock", config.distro_name));
+    let file = File::create(&path)
+        .with_context(|| format!("failed to open lock file '{}'", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            info!(
+                "waiting for another command to finish setting up '{}'...",
+                config.distro_name
+            );
+            file.lock()?;
+        }
+        Err(TryLockError::Error(err)) => return Err(err.into()),
+    }
+
+    Ok(file)
+}
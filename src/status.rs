@@ -0,0 +1,149 @@
+//! `docker docker-wrapper status`: what the distro and the docker daemon are up to.
+
+use crate::{
+    config::Config,
+    flavor::SYSTEMD_RUNNING,
+    state, vm,
+    wsl::{distro_info, output_in_wsl},
+};
+use anyhow::Result;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub distro: String,
+    pub registered: bool,
+    pub running: bool,
+    /// WSL version the distro runs on.
+    pub wsl_version: Option<String>,
+    /// Directory the distro was imported into, if the wrapper recorded it.
+    pub location: Option<String>,
+    /// Networking mode of the WSL VM, `nat` or `mirrored`, while the distro runs.
+    pub networking: Option<String>,
+    /// Only queried while the distro runs, so that asking for the status never boots it.
+    pub daemon: Option<DaemonStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DaemonStatus {
+    pub running: bool,
+    pub version: Option<String>,
+    pub data_root: Option<String>,
+    /// Space taken by the data root, in bytes.
+    pub data_root_usage: Option<u64>,
+    /// Seconds since dockerd started.
+    pub uptime: Option<u64>,
+    /// Whether systemd manages dockerd rather than the init scripts of the distro.
+    pub systemd: bool,
+}
+
+pub fn query(config: &Config) -> Status {
+    let info = distro_info(&config.distro_name);
+    let running = info.as_ref().is_some_and(|info| info.is_running());
+
+    Status {
+        distro: config.distro_name.clone(),
+        registered: info.is_some(),
+        running,
+        wsl_version: info.map(|info| info.version),
+        location: state::recorded_distro_dir(config).map(|dir| dir.display().to_string()),
+        networking: running.then(|| vm::networking_mode(config)),
+        daemon: running.then(|| query_daemon(config)),
+    }
+}
+
+fn query_daemon(config: &Config) -> DaemonStatus {
+    let query = |args: &[&str]| {
+        output_in_wsl(config, args)
+            .ok()
+            .map(|output| output.trim().to_string())
+            .filter(|output| !output.is_empty())
+    };
+
+    let version = query(&["docker", "version", "--format", "{{.Server.Version}}"]);
+    let data_root = query(&["docker", "info", "--format", "{{.DockerRootDir}}"]);
+    let data_root_usage = data_root.as_ref().and_then(|root| {
+        let du = query(&["du", "-skx", root])?;
+        let kb: u64 = du.split_whitespace().next()?.parse().ok()?;
+        Some(kb * 1024)
+    });
+    let uptime = query(&["ps", "-o", "etimes=", "-C", "dockerd"])
+        .and_then(|etimes| etimes.lines().next()?.trim().parse().ok());
+    let systemd =
+        format!("{SYSTEMD_RUNNING} && systemctl is-enabled --quiet docker 2>/dev/null && echo yes");
+    let systemd = query(&["sh", "-c", &systemd]).is_some();
+
+    DaemonStatus {
+        running: version.is_some(),
+        version,
+        data_root,
+        data_root_usage,
+        uptime,
+        systemd,
+    }
+}
+
+pub fn print(status: &Status, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(status)?);
+        return Ok(());
+    }
+
+    let state = match (status.registered, status.running) {
+        (false, _) => "not registered",
+        (true, false) => "stopped",
+        (true, true) => "running",
+    };
+    println!("distro: {} ({state})", status.distro);
+    if let Some(version) = &status.wsl_version {
+        println!("wsl version: {version}");
+    }
+    if let Some(location) = &status.location {
+        println!("location: {location}");
+    }
+    if let Some(networking) = &status.networking {
+        println!("networking: {networking}");
+    }
+
+    let Some(daemon) = &status.daemon else {
+        return Ok(());
+    };
+    match &daemon.version {
+        Some(version) if daemon.systemd => {
+            println!("dockerd: running under systemd, engine {version}")
+        }
+        Some(version) => println!("dockerd: running, engine {version}"),
+        None => println!("dockerd: not running"),
+    }
+    if let Some(uptime) = daemon.uptime {
+        println!("uptime: {}", format_duration(uptime));
+    }
+    if let Some(root) = &daemon.data_root {
+        match daemon.data_root_usage {
+            Some(usage) => println!("data root: {root} ({})", format_size(usage)),
+            None => println!("data root: {root}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
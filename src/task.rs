@@ -0,0 +1,53 @@
+//! Tasks of the Windows Task Scheduler running wrapper commands in the background, one per
+//! distro so that profiles are kept apart.
+
+use crate::{config::Config, wsl::run};
+use anyhow::{ensure, Result};
+use std::env;
+
+pub fn name(config: &Config, purpose: &str) -> String {
+    format!("docker-wrapper {purpose} ({})", config.distro_name)
+}
+
+/// Registers the task `name` running `docker-wrapper <subcommand>` with the config file and
+/// profile in use, on the `schtasks` schedule given, such as `["/SC", "ONLOGON"]`. A task
+/// registered earlier under the name is replaced.
+pub fn create(config: &Config, name: &str, schedule: &[&str], subcommand: &str) -> Result<()> {
+    // A headless console keeps a window from flashing up each time the task runs.
+    let mut command = format!(
+        "conhost.exe --headless \"{}\" docker-wrapper",
+        env::current_exe()?.display()
+    );
+    // `/TR` has no way of escaping a double quote, which Windows paths cannot hold anyway.
+    if let Some(path) = &config.path {
+        let path = std::path::absolute(path)?;
+        command.push_str(&format!(" --config \"{}\"", path.display()));
+    }
+    if let Some(profile) = &config.profile {
+        ensure!(
+            !profile.contains('"'),
+            "the profile name '{profile}' cannot be passed to a scheduled task"
+        );
+        command.push_str(&format!(" --profile \"{profile}\""));
+    }
+    command.push_str(&format!(" {subcommand}"));
+
+    let mut args = vec!["schtasks", "/Create", "/F"];
+    args.extend(schedule);
+    args.extend(["/TN", name, "/TR", &command]);
+    ensure!(
+        run(&args, true)?,
+        "failed to register the scheduled task '{name}'"
+    );
+
+    Ok(())
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    ensure!(
+        run(&["schtasks", "/Delete", "/F", "/TN", name], true)?,
+        "failed to remove the scheduled task '{name}'"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Settings of the WSL 2 VM in `%USERPROFILE%\.wslconfig`, such as how much memory and how
+//! many processors it gets. They are shared by every WSL 2 distro and apply once WSL has been
+//! shut down.
+
+use crate::{
+    config::{home_dir, Config},
+    wsl::{ini_value, output_in_wsl, set_ini_value},
+};
+use anyhow::{bail, ensure, Context, Result};
+use clap::ValueEnum;
+use std::{fs, path::PathBuf};
+
+/// First WSL version with `wsl --manage --set-sparse`.
+pub const SPARSE_VERSION: &[u32] = &[2, 0, 0];
+/// First WSL version with `networkingMode=mirrored`.
+pub const MIRRORED_VERSION: &[u32] = &[2, 0, 0];
+/// First WSL version with `autoMemoryReclaim`.
+pub const MEMORY_RECLAIM_VERSION: &[u32] = &[1, 3, 10];
+
+/// Modes of the experimental `autoMemoryReclaim` of WSL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MemoryReclaim {
+    /// Releases cached memory slowly while the VM is idle.
+    Gradual,
+    /// Drops the whole page cache as soon as the VM is idle.
+    Dropcache,
+    Disabled,
+}
+
+impl MemoryReclaim {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gradual => "gradual",
+            Self::Dropcache => "dropcache",
+            Self::Disabled => "disabled",
+        }
+    }
+}
+
+pub fn wslconfig_path() -> PathBuf {
+    home_dir().join(".wslconfig")
+}
+
+/// Networking mode of the VM: `nat`, where Windows reaches the distro through forwarded
+/// localhost ports, or `mirrored`, where the distro shares the network interfaces of Windows.
+/// Asked from the running VM when WSL can tell, since `.wslconfig` only applies after a
+/// shutdown; read from `.wslconfig` otherwise.
+pub fn networking_mode(config: &Config) -> String {
+    if let Ok(mode) = output_in_wsl(config, &["wslinfo", "--networking-mode"]) {
+        if !mode.trim().is_empty() {
+            return mode.trim().to_string();
+        }
+    }
+
+    fs::read_to_string(wslconfig_path())
+        .ok()
+        .and_then(|content| ini_value(&content, "wsl2", "networkingMode"))
+        .map_or("nat".to_string(), |mode| mode.to_ascii_lowercase())
+}
+
+/// Parses a `key=value` setting of the `[wsl2]` section, checking the values of the well-known
+/// resource limits. Other keys are passed through for WSL to judge.
+pub fn parse_setting(setting: &str) -> Result<(String, String)> {
+    let Some((key, value)) = setting.split_once('=') else {
+        bail!("expected KEY=VALUE, got '{setting}'");
+    };
+    let (key, value) = match (key.trim(), value.trim()) {
+        // Spelled as it is shown in the settings app.
+        ("networking", value) => ("networkingMode", value),
+        setting => setting,
+    };
+    ensure!(
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric()),
+        "invalid .wslconfig key '{key}'"
+    );
+    match key {
+        "memory" | "swap" => ensure!(
+            is_size(value),
+            "invalid size '{value}' for {key} (expected a size such as 8GB or 512MB)"
+        ),
+        "processors" => ensure!(
+            value.parse::<u32>().is_ok_and(|n| n > 0),
+            "invalid processor count '{value}'"
+        ),
+        "networkingMode" => ensure!(
+            ["nat", "mirrored", "virtioproxy", "none"].contains(&value),
+            "invalid networking mode '{value}' (expected nat or mirrored)"
+        ),
+        _ => ensure!(!value.is_empty(), "missing value for {key}"),
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn is_size(value: &str) -> bool {
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = &value[digits.len()..];
+    !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && ["", "B", "KB", "MB", "GB", "TB"].contains(&unit.to_ascii_uppercase().as_str())
+}
+
+/// Writes `settings` into `[section]` of `.wslconfig`, keeping everything else.
+pub fn set(section: &str, settings: &[(String, String)]) -> Result<()> {
+    let path = wslconfig_path();
+    let mut content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read '{}'", path.display()))
+        }
+    };
+    for (key, value) in settings {
+        content = set_ini_value(&content, section, key, value);
+    }
+    fs::write(&path, content).with_context(|| format!("failed to write '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_limits_are_checked() {
+        assert_eq!(
+            parse_setting("memory=8GB").unwrap(),
+            ("memory".into(), "8GB".into())
+        );
+        assert!(parse_setting("swap=0").is_ok());
+        assert!(parse_setting("memory=lots").is_err());
+        assert!(parse_setting("processors=0").is_err());
+        assert!(parse_setting("processors").is_err());
+        assert_eq!(
+            parse_setting("networking=mirrored").unwrap(),
+            ("networkingMode".into(), "mirrored".into())
+        );
+        assert!(parse_setting("networkingMode=bridged").is_err());
+    }
+}
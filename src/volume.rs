@@ -0,0 +1,101 @@
+//! `docker docker-wrapper volume backup|restore`: named volumes, which live in the virtual
+//! disk of the distro, saved to and loaded from gzipped tarballs on the Windows side. The
+//! contents are streamed through a throwaway container, so that the tarball never has to be
+//! reachable from the distro.
+
+use crate::{
+    config::Config,
+    wsl::{command_in_wsl_at_cwd, run_in_wsl},
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fs::{self, File},
+    path::Path,
+    process::Stdio,
+};
+use tracing::info;
+
+/// Image of the container streaming the volume, for its `tar`.
+const HELPER_IMAGE: &str = "busybox";
+
+fn exists(config: &Config, volume: &str) -> Result<bool> {
+    run_in_wsl(config, &["docker", "volume", "inspect", volume], true)
+}
+
+pub fn backup(config: &Config, volume: &str, file: &Path) -> Result<()> {
+    ensure!(exists(config, volume)?, "there is no volume '{volume}'");
+
+    let tarball =
+        File::create(file).with_context(|| format!("failed to create '{}'", file.display()))?;
+    let mount = format!("{volume}:/volume:ro");
+    let status = command_in_wsl_at_cwd(
+        config,
+        &[
+            "docker",
+            "run",
+            "--rm",
+            "-v",
+            &mount,
+            HELPER_IMAGE,
+            "tar",
+            "czf",
+            "-",
+            "-C",
+            "/volume",
+            ".",
+        ],
+    )?
+    .stdin(Stdio::null())
+    .stdout(tarball)
+    .status()?;
+    if !status.success() {
+        let _ = fs::remove_file(file);
+        bail!("failed to back up the volume '{volume}'");
+    }
+    info!("backed the volume '{volume}' up to '{}'", file.display());
+
+    Ok(())
+}
+
+/// Loads a tarball saved by `backup` into `volume`, creating it if needed. The contents of a
+/// volume that exists already are only replaced with `replace`.
+pub fn restore(config: &Config, file: &Path, volume: &str, replace: bool) -> Result<()> {
+    let tarball =
+        File::open(file).with_context(|| format!("failed to open '{}'", file.display()))?;
+    let existed = exists(config, volume)?;
+    ensure!(
+        !existed || replace,
+        "the volume '{volume}' exists already; pass --replace to replace its contents"
+    );
+
+    let mount = format!("{volume}:/volume");
+    let status = command_in_wsl_at_cwd(
+        config,
+        &[
+            "docker",
+            "run",
+            "--rm",
+            "-i",
+            "-v",
+            &mount,
+            HELPER_IMAGE,
+            "sh",
+            "-c",
+            "find /volume -mindepth 1 -delete && tar xzf - -C /volume",
+        ],
+    )?
+    .stdin(tarball)
+    .status()?;
+    ensure!(
+        status.success(),
+        "failed to restore the volume '{volume}' from '{}'",
+        file.display()
+    );
+    info!(
+        "{} the volume '{volume}' from '{}'",
+        if existed { "replaced" } else { "created" },
+        file.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,657 @@
+use crate::{agent, config::Config, rootless};
+use anyhow::{bail, ensure, Result};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Mutex, OnceLock},
+};
+use tracing::{debug, trace};
+
+pub fn output(args: &[&str]) -> Result<String> {
+    debug!("output: {:?}", args);
+    let mut cmd = Command::new(args[0]);
+    // Makes `wsl.exe` print its own messages in UTF-8 rather than UTF-16.
+    cmd.args(&args[1..]).env("WSL_UTF8", "1");
+    let output = cmd.output()?;
+    trace!(stdout = %String::from_utf8_lossy(&output.stdout), "{}", output.status);
+    if !output.status.success() {
+        debug!(
+            stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+            "{:?} failed with {}", args[0], output.status
+        );
+    }
+    ensure!(output.status.success(), "command failed");
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn output_in_wsl(config: &Config, args_in_wsl: &[&str]) -> Result<String> {
+    if let Some((success, stdout)) = agent::run(config, args_in_wsl) {
+        debug!("output through the agent: {:?}", args_in_wsl);
+        ensure!(success, "command failed");
+        return Ok(stdout);
+    }
+
+    let mut args = vec!["wsl", "-d", &config.distro_name, "-u", "root", "-e"];
+    args.extend(args_in_wsl);
+    output(&args)
+}
+
+/// A registered distro as listed by `wsl --list --verbose`.
+pub struct DistroInfo {
+    /// `Running`, `Stopped`, `Installing`...
+    pub state: String,
+    /// WSL version of the distro, `1` or `2`.
+    pub version: String,
+}
+
+impl DistroInfo {
+    pub fn is_running(&self) -> bool {
+        self.state == "Running"
+    }
+}
+
+/// Version of WSL from the first line of `wsl --version`, such as `WSL version: 2.0.9.0`.
+/// `None` for the inbox WSL, which predates the option.
+pub fn wsl_version() -> Option<Vec<u32>> {
+    parse_wsl_version(&output(&["wsl", "--version"]).ok()?)
+}
+
+fn parse_wsl_version(output: &str) -> Option<Vec<u32>> {
+    // The label is localized, the number is not.
+    let number = output.lines().next()?.split_whitespace().last()?;
+    number.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Directory WSL keeps the distro `name` in, the `BasePath` of its registration.
+pub fn distro_base_path(name: &str) -> Option<PathBuf> {
+    let script = format!(
+        "(Get-ChildItem HKCU:\\Software\\Microsoft\\Windows\\CurrentVersion\\Lxss | \
+         Where-Object {{ $_.GetValue('DistributionName') -eq '{}' }}).GetValue('BasePath')",
+        name.replace('\'', "''")
+    );
+    let path = output(&["powershell", "-NoProfile", "-Command", &script]).ok()?;
+    let path = path.trim().trim_start_matches(r"\\?\");
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Looks `name` up among the registered distros, without starting anything.
+pub fn distro_info(name: &str) -> Option<DistroInfo> {
+    let list = output(&["wsl", "--list", "--verbose"]).ok()?;
+    // Lines look like `* custom-docker-host    Running    2`, the star marking the default.
+    list.lines().find_map(|line| {
+        let fields: Vec<_> = line
+            .trim_start_matches(['*', ' '])
+            .split_whitespace()
+            .collect();
+        match fields[..] {
+            [distro, state, version] if distro == name => Some(DistroInfo {
+                state: state.to_string(),
+                version: version.to_string(),
+            }),
+            _ => None,
+        }
+    })
+}
+
+/// Runs a helper command. Its stdin is closed: `wsl.exe` relays whatever it can read from
+/// its stdin into the distro, which would swallow input piped into the wrapper for docker.
+pub fn run(args: &[&str], silent: bool) -> Result<bool> {
+    debug!("run: {:?}", args);
+    let (stdout, stderr) = if silent {
+        (Stdio::null(), Stdio::null())
+    } else {
+        (Stdio::inherit(), Stdio::inherit())
+    };
+
+    let mut cmd = Command::new(args[0]);
+    cmd.args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr);
+    let status = cmd.spawn()?.wait()?;
+    debug!("{:?} exited with {status}", args[0]);
+
+    Ok(status.success())
+}
+
+/// Runs a command in the distro. `-e` makes WSL execute the command directly rather than
+/// through a shell, so each argument arrives exactly as given, spaces and quotes included.
+pub fn run_in_wsl(config: &Config, args_in_wsl: &[&str], silent: bool) -> Result<bool> {
+    if silent {
+        if let Some((success, _)) = agent::run(config, args_in_wsl) {
+            debug!("run through the agent: {:?}", args_in_wsl);
+            return Ok(success);
+        }
+    }
+
+    let mut args = vec!["wsl", "-d", &config.distro_name, "-u", "root", "-e"];
+    args.extend(args_in_wsl);
+    run(&args, silent)
+}
+
+/// Builds the command running `args_in_wsl` in the distro's view of the current Windows
+/// directory, so that relative paths resolve the same way as on the Windows side.
+///
+/// The standard handles are handed over as-is, whether they are the console or pipes and
+/// files redirected into or out of the wrapper (`docker load < image.tar`, `docker save img >
+/// image.tar`): the child uses the very same handles, so data is streamed byte for byte
+/// without the wrapper buffering or translating anything.
+pub fn command_in_wsl_at_cwd(config: &Config, args_in_wsl: &[&str]) -> Result<Command> {
+    let mut cmd = Command::new("wsl");
+    cmd.args(["-d", &config.distro_name, "--cd"])
+        .arg(env::current_dir()?)
+        .arg("-e")
+        .args(args_in_wsl)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    Ok(cmd)
+}
+
+/// Hands `vars` to the command in the distro. WSL only carries over the variables of Windows
+/// listed in `WSLENV`, to which their names are added. Entries the user made for them are
+/// replaced, since a flag such as `/p` would translate values that are distro paths already.
+pub fn forward_env(cmd: &mut Command, vars: &[(String, String)]) {
+    if vars.is_empty() {
+        return;
+    }
+
+    for (name, value) in vars {
+        cmd.env(name, value);
+    }
+    let names: Vec<_> = vars.iter().map(|(name, _)| name.as_str()).collect();
+    cmd.env(
+        "WSLENV",
+        merge_wslenv(&env::var("WSLENV").unwrap_or_default(), &names),
+    );
+}
+
+/// `wslenv` with unflagged entries for `names` in place of any it has for them.
+fn merge_wslenv(wslenv: &str, names: &[&str]) -> String {
+    let mut entries: Vec<&str> = wslenv
+        .split(':')
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| !names.contains(&entry.split('/').next().unwrap_or_default()))
+        .collect();
+    entries.extend(names);
+
+    entries.join(":")
+}
+
+/// Prefix of the distro command line recording which terminal the session got into
+/// `$0`, for [`resize_tty_in_wsl`] to find it. The remaining arguments are run as the command.
+pub const RECORD_TTY_SCRIPT: &str = r#"tty > "$0"; "$@"; status=$?; rm -f "$0"; exit $status"#;
+
+/// Sets the size of the terminal recorded in `tty_file` by [`RECORD_TTY_SCRIPT`]. The kernel
+/// then signals the change to the session in it, so docker resizes the container's TTY.
+///
+/// Runs in the middle of an interactive session, so nothing is printed, and failures (e.g.
+/// the session ending meanwhile) are ignored.
+pub fn resize_tty_in_wsl(distro_name: &str, tty_file: &str, rows: u16, columns: u16) {
+    let script = format!(
+        "stty -F \"$(cat {})\" rows {rows} cols {columns}",
+        shell_quote(tty_file)
+    );
+    let _ = Command::new("wsl")
+        .args(["-d", distro_name, "-u", "root", "-e", "sh", "-c", &script])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Paths converted so far in this process, answering repeated conversions without a WSL
+/// round trip.
+static CONVERTED_PATHS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+pub fn convert_path(config: &Config, from: &str) -> Result<String> {
+    if let Some(path) = convert_path_natively(config, from) {
+        return Ok(path);
+    }
+    if let Some(path) = CONVERTED_PATHS.lock().unwrap().get(from) {
+        return Ok(path.clone());
+    }
+
+    let path = output_in_wsl(config, &["wslpath", "-u", from])?
+        .trim()
+        .to_string();
+    debug!("converted path {from:?} to {path:?}");
+    CONVERTED_PATHS
+        .lock()
+        .unwrap()
+        .insert(from.to_string(), path.clone());
+
+    Ok(path)
+}
+
+/// Converts all of `paths` in a single WSL round trip, so that [`convert_path`] answers them
+/// from memory afterwards. Paths `wslpath` rejects are left for [`convert_path`] to report.
+pub fn prefetch_paths(config: &Config, paths: &[&str]) -> Result<()> {
+    let mut converted = CONVERTED_PATHS.lock().unwrap();
+    let mut paths: Vec<_> = paths
+        .iter()
+        .filter(|path| !converted.contains_key(**path))
+        .filter(|path| convert_path_natively(config, path).is_none())
+        .copied()
+        .collect();
+    paths.sort_unstable();
+    paths.dedup();
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    // One line per path, empty for those that fail. Windows paths cannot contain newlines.
+    let script = r#"for path; do wslpath -u "$path" 2>/dev/null || echo; done"#;
+    let mut args = vec!["sh", "-c", script, "sh"];
+    args.extend(&paths);
+    let output = output_in_wsl(config, &args)?;
+    for (from, path) in paths.iter().zip(output.lines()) {
+        if !path.is_empty() {
+            debug!("converted path {from:?} to {path:?}");
+            converted.insert(from.to_string(), path.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts the common cases without asking `wslpath`: drive paths, which live under the
+/// automount root, and UNC paths into the distro itself. Returns `None` for anything else,
+/// such as network shares and drive-relative paths (`C:foo`).
+fn convert_path_natively(config: &Config, path: &str) -> Option<String> {
+    if let Some(path) = distro_unc_to_wsl_path(&config.distro_name, path) {
+        return Some(path);
+    }
+
+    drive_to_wsl_path(path, automount_root(config))
+}
+
+/// Where the distro mounts the Windows drives, with a trailing slash. It is read once per
+/// run.
+pub fn automount_root(config: &Config) -> &'static str {
+    static AUTOMOUNT_ROOT: OnceLock<String> = OnceLock::new();
+
+    AUTOMOUNT_ROOT.get_or_init(|| read_automount_root(config))
+}
+
+/// `[automount] root` in the `/etc/wsl.conf` of the distro. The file is read through the
+/// `\\wsl.localhost` share rather than by spawning `wsl.exe`.
+fn read_automount_root(config: &Config) -> String {
+    let conf = [r"\\wsl.localhost", r"\\wsl$"].iter().find_map(|share| {
+        let path = format!(r"{share}\{}\etc\wsl.conf", config.distro_name);
+        fs::read_to_string(path).ok()
+    });
+
+    let root = conf
+        .as_deref()
+        .and_then(parse_automount_root)
+        .unwrap_or_else(|| DEFAULT_AUTOMOUNT_ROOT.to_string());
+    debug!("automount root is {root:?}");
+
+    root
+}
+
+const DEFAULT_AUTOMOUNT_ROOT: &str = "/mnt/";
+
+/// Reads `root` from the `[automount]` section of a `wsl.conf`, with a trailing slash.
+fn parse_automount_root(conf: &str) -> Option<String> {
+    let value = ini_value(conf, "automount", "root")?;
+    let value = value.trim_matches('"');
+    if !value.starts_with('/') {
+        return None;
+    }
+
+    Some(format!("{}/", value.trim_end_matches('/')))
+}
+
+/// Value of `key` in the `[section]` of an INI file such as `wsl.conf`, without a trailing
+/// comment.
+pub fn ini_value(conf: &str, section: &str, key: &str) -> Option<String> {
+    let header = format!("[{section}]");
+    let mut in_section = false;
+    for line in conf.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_section = line.eq_ignore_ascii_case(&header);
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        if name.trim() == key {
+            return Some(
+                value
+                    .split('#')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            );
+        }
+    }
+
+    None
+}
+
+/// Stops the distro, which WSL boots again for the next command, rereading `wsl.conf`.
+pub fn terminate(config: &Config) -> Result<()> {
+    ensure!(
+        run(&["wsl", "--terminate", &config.distro_name], true)?,
+        "failed to terminate '{}'",
+        config.distro_name
+    );
+
+    Ok(())
+}
+
+/// Sets `key` in the `[section]` of an INI file such as `wsl.conf`, replacing the value it has
+/// and adding the section when it is missing. Everything else is kept as it is.
+pub fn set_ini_value(conf: &str, section: &str, key: &str, value: &str) -> String {
+    let header = format!("[{section}]");
+    let entry = format!("{key} = {value}");
+    let mut lines: Vec<String> = conf.lines().map(str::to_string).collect();
+
+    let Some(start) = lines
+        .iter()
+        .position(|line| line.trim().eq_ignore_ascii_case(&header))
+    else {
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.extend([header, entry]);
+        return lines.join("\n") + "\n";
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim().starts_with('['))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+
+    let existing = (start + 1..end).find(|&i| {
+        lines[i]
+            .split_once('=')
+            .is_some_and(|(name, _)| name.trim() == key)
+    });
+    match existing {
+        Some(i) => lines[i] = entry,
+        None => {
+            // After the last setting of the section, ahead of any blank lines closing it.
+            let at = (start + 1..end)
+                .rev()
+                .find(|&i| !lines[i].trim().is_empty())
+                .map_or(start + 1, |i| i + 1);
+            lines.insert(at, entry);
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Sets `key` in the `[section]` of the distro's `/etc/wsl.conf`. WSL reads the file when
+/// the distro starts, so the change applies after it is terminated.
+pub fn set_wsl_conf(config: &Config, section: &str, key: &str, value: &str) -> Result<()> {
+    let conf = output_in_wsl(
+        config,
+        &["sh", "-c", "cat /etc/wsl.conf 2>/dev/null || true"],
+    )?;
+    let conf = set_ini_value(&conf, section, key, value);
+    ensure!(
+        write_file_in_wsl(config, "/etc/wsl.conf", conf.trim_end())?,
+        "failed to write /etc/wsl.conf"
+    );
+
+    Ok(())
+}
+
+/// Parses a `section.key=value` setting of `wsl.conf`.
+pub fn parse_wsl_conf_setting(setting: &str) -> Result<(String, String, String)> {
+    let parsed = setting.split_once('=').and_then(|(name, value)| {
+        let (section, key) = name.trim().split_once('.')?;
+        Some((section.trim(), key.trim(), value.trim()))
+    });
+    let Some((section, key, value)) = parsed else {
+        bail!("expected SECTION.KEY=VALUE, got '{setting}'");
+    };
+    let is_name = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric());
+    ensure!(
+        is_name(section) && is_name(key) && !value.contains('\n'),
+        "invalid wsl.conf setting '{setting}'"
+    );
+
+    Ok((section.to_string(), key.to_string(), value.to_string()))
+}
+
+/// User that docker commands run as in the distro: the one the rootless daemon belongs to in
+/// that mode, and otherwise `distro_user`, which reaches the daemon through the docker group.
+pub fn docker_user(config: &Config) -> &str {
+    match config.daemon.rootless {
+        true => rootless::USER,
+        false => &config.distro_user,
+    }
+}
+
+/// Home directory of the user docker runs as, or root's in distros set up before there was
+/// such a user.
+pub fn docker_home(config: &Config) -> Result<String> {
+    let script = format!(
+        "getent passwd {} | cut -d: -f6 || true",
+        docker_user(config)
+    );
+    let home = output_in_wsl(config, &["sh", "-c", &script])?;
+    match home.trim() {
+        "" => Ok("/root".to_string()),
+        home => Ok(home.to_string()),
+    }
+}
+
+/// `C:\work\app` or `C:/work/app` under `root`, with the drive letter lowercased as WSL does.
+fn drive_to_wsl_path(path: &str, root: &str) -> Option<String> {
+    let mut chars = path.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+    let rest = chars.as_str().strip_prefix(':')?;
+    if !rest.is_empty() && !rest.starts_with(['\\', '/']) {
+        return None;
+    }
+
+    let rest = rest.replace('\\', "/");
+    Some(format!("{root}{}{rest}", drive.to_ascii_lowercase()))
+}
+
+/// `\\wsl.localhost\<distro>\x` (or `\\wsl$\...`) naming a path inside the distro itself.
+fn distro_unc_to_wsl_path(distro_name: &str, path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let rest = ["//wsl.localhost/", "//wsl$/"].iter().find_map(|share| {
+        path.get(share.len()..)
+            .filter(|_| path[..share.len()].eq_ignore_ascii_case(share))
+    })?;
+    let (distro, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if !distro.eq_ignore_ascii_case(distro_name) {
+        return None;
+    }
+
+    Some(format!("/{rest}"))
+}
+
+/// Reads the JSON object in `path` inside the distro, such as `/etc/docker/daemon.json`. A
+/// missing or malformed file reads as an empty object. A leading `~/` is the home directory.
+pub fn read_json_object_in_wsl(config: &Config, path: &str) -> Result<Map<String, Value>> {
+    let script = format!("cat {} 2>/dev/null || true", quote_distro_path(path));
+    let content = output_in_wsl(config, &["sh", "-c", &script])?;
+    match serde_json::from_str(&content) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Ok(Map::new()),
+    }
+}
+
+/// Writes `content` to `path` inside the distro, creating the parent directory. A leading `~/`
+/// in `path` refers to the home directory of the distro user.
+pub fn write_file_in_wsl(config: &Config, path: &str, content: &str) -> Result<bool> {
+    let path = quote_distro_path(path);
+    let script = format!(
+        "mkdir -p \"$(dirname {path})\" && printf '%s\\n' {} > {path}",
+        shell_quote(content)
+    );
+    run_in_wsl(config, &["sh", "-c", &script], true)
+}
+
+/// Quotes a distro path for a shell script, leaving a leading `~/` for the shell to expand.
+fn quote_distro_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", shell_quote(rest)),
+        None => shell_quote(path),
+    }
+}
+
+/// Quotes `arg` for a POSIX shell so that it is passed on as a single word, unexpanded.
+pub fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_words_are_left_alone() {
+        assert_eq!(shell_quote("docker"), "docker");
+        assert_eq!(shell_quote("/mnt/c/work"), "/mnt/c/work");
+        assert_eq!(shell_quote("--label=a"), "--label=a");
+    }
+
+    #[test]
+    fn special_characters_are_single_quoted() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote(r"C:\My Projects"), r"'C:\My Projects'");
+        assert_eq!(shell_quote(r#"say "hi""#), r#"'say "hi"'"#);
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn drive_paths_are_converted_under_the_root() {
+        assert_eq!(
+            drive_to_wsl_path(r"C:\work\app", "/mnt/").as_deref(),
+            Some("/mnt/c/work/app")
+        );
+        assert_eq!(
+            drive_to_wsl_path("D:/data", "/").as_deref(),
+            Some("/d/data")
+        );
+        assert_eq!(
+            drive_to_wsl_path(r"C:\", "/mnt/").as_deref(),
+            Some("/mnt/c/")
+        );
+        assert_eq!(drive_to_wsl_path("C:", "/mnt/").as_deref(), Some("/mnt/c"));
+        assert_eq!(drive_to_wsl_path("C:work", "/mnt/"), None);
+        assert_eq!(drive_to_wsl_path(r"\\server\share", "/mnt/"), None);
+        assert_eq!(drive_to_wsl_path("relative", "/mnt/"), None);
+    }
+
+    #[test]
+    fn distro_unc_paths_become_distro_paths() {
+        assert_eq!(
+            distro_unc_to_wsl_path("dh", r"\\wsl.localhost\dh\home\me").as_deref(),
+            Some("/home/me")
+        );
+        assert_eq!(
+            distro_unc_to_wsl_path("dh", r"\\wsl$\DH\etc").as_deref(),
+            Some("/etc")
+        );
+        assert_eq!(distro_unc_to_wsl_path("dh", r"\\wsl$\other\etc"), None);
+        assert_eq!(distro_unc_to_wsl_path("dh", r"\\server\share"), None);
+    }
+
+    #[test]
+    fn wsl_version_is_read_from_the_first_line() {
+        let output = "WSL-Version: 2.0.9.0\nKernelversion: 5.15.133.1-1\n";
+        assert_eq!(parse_wsl_version(output), Some(vec![2, 0, 9, 0]));
+        assert_eq!(parse_wsl_version("usage: wsl.exe [Argument]"), None);
+    }
+
+    #[test]
+    fn ini_values_are_set_in_their_section() {
+        let conf = "[automount]\nroot = /win/\n\n[user]\ndefault = root\n";
+        assert_eq!(
+            set_ini_value(conf, "user", "default", "me"),
+            "[automount]\nroot = /win/\n\n[user]\ndefault = me\n"
+        );
+        assert_eq!(
+            set_ini_value(conf, "automount", "options", "metadata"),
+            "[automount]\nroot = /win/\noptions = metadata\n\n[user]\ndefault = root\n"
+        );
+        assert_eq!(
+            set_ini_value("[user]\ndefault = me", "boot", "systemd", "true"),
+            "[user]\ndefault = me\n\n[boot]\nsystemd = true\n"
+        );
+        assert_eq!(
+            set_ini_value("", "boot", "systemd", "true"),
+            "[boot]\nsystemd = true\n"
+        );
+    }
+
+    #[test]
+    fn forwarded_names_replace_their_wslenv_entries() {
+        assert_eq!(
+            merge_wslenv(
+                "WT_SESSION:DOCKER_CONFIG/p:USERPROFILE/pu:",
+                &["DOCKER_CONFIG", "http_proxy"]
+            ),
+            "WT_SESSION:USERPROFILE/pu:DOCKER_CONFIG:http_proxy"
+        );
+        assert_eq!(merge_wslenv("", &["DOCKER_CONFIG"]), "DOCKER_CONFIG");
+    }
+
+    #[test]
+    fn automount_root_is_read_from_wsl_conf() {
+        let conf = "[boot]\nroot = /ignored\n\n[automount]\nenabled = true\nroot = /win # drives\n";
+        assert_eq!(parse_automount_root(conf).as_deref(), Some("/win/"));
+        assert_eq!(
+            parse_automount_root("[automount]\nroot=\"/\"").as_deref(),
+            Some("/")
+        );
+        assert_eq!(parse_automount_root("[network]\nhostname = x"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quoted_arguments_survive_the_shell() {
+        let args = [
+            "a b",
+            "  leading and trailing  ",
+            "$HOME `id` $(id)",
+            r"C:\My Projects\app:/app",
+            r#"double " and single ' quotes"#,
+            "semi;colon && pipe | glob *",
+            "line\nbreak",
+            "",
+        ];
+        let script = format!(
+            "printf '%s\\0' {}",
+            args.iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let received: Vec<_> = stdout.split_terminator('\0').collect();
+
+        assert_eq!(received, args);
+    }
+}
@@ -0,0 +1,117 @@
+//! Runs the wrapper against fake `wsl` and `docker` executables to check that binary data
+//! passes through it untouched in both directions.
+#![cfg(unix)]
+
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Stands in for `wsl.exe`: drops the distro selection, follows `--cd` and executes whatever
+/// comes after `-e`.
+const FAKE_WSL: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -d) shift 2 ;;
+        --cd) cd "$2" && shift 2 ;;
+        -e) shift; exec "$@" ;;
+        *) shift ;;
+    esac
+done
+"#;
+
+/// Stands in for docker inside the distro: `save` writes the image archive to stdout and
+/// `load` stores whatever it reads from stdin.
+const FAKE_DOCKER: &str = r#"#!/bin/sh
+case "$1" in
+    save) cat "$FAKE_DOCKER_IMAGE" ;;
+    load) cat > "$FAKE_DOCKER_LOADED" ;;
+esac
+"#;
+
+struct Fixture {
+    dir: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("docker-wrapper-{name}-{}", std::process::id()));
+        let bin = dir.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        write_executable(&bin.join("wsl"), FAKE_WSL);
+        write_executable(&bin.join("docker"), FAKE_DOCKER);
+
+        Self { dir }
+    }
+
+    fn wrapper(&self, args: &[&str]) -> Command {
+        let path = format!(
+            "{}:{}",
+            self.dir.join("bin").display(),
+            env::var("PATH").unwrap_or_default()
+        );
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_docker"));
+        cmd.args(args)
+            .env("PATH", path)
+            .env("DOCKER_WRAPPER_CONFIG", self.dir.join("config.toml"))
+            .env("XDG_DATA_HOME", &self.dir)
+            .env("FAKE_DOCKER_IMAGE", self.dir.join("image.tar"))
+            .env("FAKE_DOCKER_LOADED", self.dir.join("loaded.tar"))
+            .stderr(Stdio::null());
+        // The fake distro is the host, whose dockerd must not be handed the proxies.
+        for proxy in ["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY"] {
+            cmd.env_remove(proxy).env_remove(proxy.to_lowercase());
+        }
+
+        cmd
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn write_executable(path: &Path, content: &str) {
+    fs::write(path, content).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A few megabytes covering every byte value, including line endings and NULs.
+fn image_archive() -> Vec<u8> {
+    (0..4 * 1024 * 1024).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[test]
+fn saved_image_is_streamed_byte_identically() {
+    let fixture = Fixture::new("save");
+    let image = image_archive();
+    fs::write(fixture.dir.join("image.tar"), &image).unwrap();
+
+    let output = fixture.wrapper(&["save", "myimage"]).output().unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout == image, "saved archive differs");
+}
+
+#[test]
+fn loaded_image_is_forwarded_byte_identically() {
+    let fixture = Fixture::new("load");
+    let image = image_archive();
+
+    let mut child = fixture
+        .wrapper(&["load"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&image).unwrap();
+    let status = child.wait().unwrap();
+
+    assert!(status.success());
+    let loaded = fs::read(fixture.dir.join("loaded.tar")).unwrap();
+    assert!(loaded == image, "loaded archive differs");
+}